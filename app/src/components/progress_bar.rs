@@ -15,6 +15,7 @@ pub struct ProgressBar {
     pb: indicatif::ProgressBar,
     current_progress: u64,
     skip: bool,
+    verified: Option<bool>,
     started_at: Option<Instant>,
     finished_elapsed: Option<Duration>,
 }
@@ -33,6 +34,7 @@ impl ProgressBar {
             pb,
             current_progress: 0,
             skip: false,
+            verified: None,
             started_at: None,
             finished_elapsed: None,
         }
@@ -55,6 +57,11 @@ impl ProgressBar {
         self.pb.finish_and_clear();
     }
 
+    /// Whether the file is being transferred, it may be cancelled meanwhile.
+    pub fn is_active(&self) -> bool {
+        !self.skip && self.started_at.is_some() && self.finished_elapsed.is_none()
+    }
+
     pub fn get_file_id(&self) -> u64 {
         self.file_id
     }
@@ -65,6 +72,15 @@ impl ProgressBar {
         self.finished_elapsed = Some(self.started_at.map(|s| s.elapsed()).unwrap_or(Duration::ZERO));
         self.pb.finish();
     }
+
+    /// Finish with the result of the content verification (receiver only).
+    pub fn finish_verified(
+        &mut self,
+        verified: bool,
+    ) {
+        self.verified = Some(verified);
+        self.finish();
+    }
 }
 
 impl RenderOnce for ProgressBar {
@@ -92,7 +108,21 @@ impl RenderOnce for ProgressBar {
                         Label::new(if self.current_progress >= self.file_size {
                             // Finished: show elapsed time (use recorded elapsed to avoid continued counting)
                             let elapsed = self.finished_elapsed.unwrap_or_else(|| self.started_at.map(|s| s.elapsed()).unwrap_or(Duration::ZERO));
-                            format!("{} • in {}", human_bytes(self.file_size), human_duration(elapsed))
+                            match self.verified {
+                                Some(true) => format!(
+                                    "{} • in {} • {}",
+                                    human_bytes(self.file_size),
+                                    human_duration(elapsed),
+                                    i18n_common(cx, "verified")
+                                ),
+                                Some(false) => format!(
+                                    "{} • in {} • {}",
+                                    human_bytes(self.file_size),
+                                    human_duration(elapsed),
+                                    i18n_common(cx, "verify_failed")
+                                ),
+                                None => format!("{} • in {}", human_bytes(self.file_size), human_duration(elapsed)),
+                            }
                         } else {
                             // In progress: show ETA
                             format!(
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use flash_cat_common::consts::PUBLIC_RELAY;
+use flash_cat_common::{consts::PUBLIC_RELAY, utils::rate_limit::parse_rate};
 use gpui::{App, AppContext, Bounds, Context, Entity, Global, Pixels};
 use gpui_component::ThemeMode;
 use locale_config::Locale;
@@ -25,6 +25,8 @@ pub struct FlashCatAppState {
     locale: Option<String>,
     relay_address: Option<String>,
     save_path: Option<String>,
+    /// Limit of the sending and receiving rate, e.g. `5MiB/s`.
+    limit_rate: Option<String>,
     bounds: Option<Bounds<Pixels>>,
     theme: Option<String>,
 }
@@ -72,6 +74,15 @@ impl FlashCatAppState {
         self.save_path.clone().unwrap_or_else(|| get_user_download_dir())
     }
 
+    pub fn limit_rate(&self) -> String {
+        self.limit_rate.clone().unwrap_or_default()
+    }
+
+    /// Limit of the sending and receiving rate in bytes per second, unlimited if not set.
+    pub fn limit_rate_bytes(&self) -> Option<u64> {
+        self.limit_rate.as_deref().and_then(|rate| parse_rate(rate).ok())
+    }
+
     pub fn set_theme(
         &mut self,
         theme: Option<ThemeMode>,
@@ -110,6 +121,13 @@ impl FlashCatAppState {
         self.save_path = Some(save_path);
     }
 
+    pub fn set_limit_rate(
+        &mut self,
+        limit_rate: Option<String>,
+    ) {
+        self.limit_rate = limit_rate;
+    }
+
     pub fn go_to(
         &mut self,
         route: Route,
@@ -1,12 +1,16 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
-use flash_cat_common::{consts::PUBLIC_RELAY, proto::ClientType};
-use flash_cat_core::{ReceiverConfirm, ReceiverInteractionMessage, receiver::FlashCatReceiver};
-use gpui::{AppContext, Context, Entity, InteractiveElement, IntoElement, ParentElement, Render, Styled, Window, div, prelude::FluentBuilder};
+use flash_cat_common::{consts::PUBLIC_RELAY, proto::ClientType, utils::human_bytes};
+use flash_cat_core::{
+    ManifestEntry, ReceiverConfirm, ReceiverInteractionMessage,
+    receiver::{FlashCatReceiver, ReceiveOptions},
+};
+use gpui::{AppContext, Context, Entity, InteractiveElement, IntoElement, ParentElement, Render, Styled, Window, div, prelude::FluentBuilder, px};
 use gpui_component::{
     ActiveTheme, Disableable, Sizable,
     button::{Button, ButtonVariants},
     checkbox::Checkbox,
+    clipboard::Clipboard,
     h_flex,
     input::{Input, InputState},
     label::Label,
@@ -28,6 +32,7 @@ enum ReceiveState {
     Idle,
     Connecting,
     AwaitingConfirm,
+    Selecting,
     Receiving,
     ReceiveDone,
 }
@@ -41,10 +46,19 @@ enum NotificationType {
         file_count: u64,
         folder_count: u64,
     },
+    ConfirmSelection,
     ConfirmFileDuplicate {
         file_id: u64,
         file_path: String,
     },
+    ConfirmBatchDuplicate {
+        batch_id: u64,
+        count: usize,
+    },
+    ConfirmExtract {
+        file_id: u64,
+        file_path: String,
+    },
     ConfirmOpenSavePath,
 }
 
@@ -55,8 +69,27 @@ pub struct ReceiveView {
     receive_but_hover: bool,
     flash_cat_receiver: Option<Arc<FlashCatReceiver>>,
     progress_bars: Vec<ProgressBar>,
+    /// Manifest entries and whether they are checked, while selecting the files to receive.
+    manifest: Vec<(ManifestEntry, bool)>,
     notification: NotificationType,
     num_files: u64,
+    paused: bool,
+    /// Text sent instead of files.
+    text: Option<String>,
+}
+
+/// A row of the manifest tree, a directory checks all entries below it.
+enum ManifestRow {
+    Dir {
+        name: String,
+        prefix: String,
+        depth: usize,
+    },
+    Entry {
+        index: usize,
+        name: String,
+        depth: usize,
+    },
 }
 
 impl ReceiveView {
@@ -73,9 +106,53 @@ impl ReceiveView {
             receive_but_hover: false,
             flash_cat_receiver: None,
             progress_bars: vec![],
+            manifest: vec![],
             notification: NotificationType::None,
             num_files: 0,
+            paused: false,
+            text: None,
+        }
+    }
+
+    /// Rows of the manifest tree, sorted by path with the directories inserted before their entries.
+    fn manifest_rows(&self) -> Vec<ManifestRow> {
+        let mut indices = (0..self.manifest.len()).collect::<Vec<_>>();
+        indices.sort_by_key(|&index| manifest_path(&self.manifest[index].0));
+
+        let mut rows = vec![];
+        let mut dirs = HashSet::new();
+        for index in indices {
+            let path = manifest_path(&self.manifest[index].0);
+            let mut parents = path.split('/').collect::<Vec<_>>();
+            let name = parents.pop().unwrap_or_default();
+            for (depth, dir) in parents.iter().enumerate() {
+                let prefix = parents[..=depth].join("/");
+                if dirs.insert(prefix.clone()) {
+                    rows.push(ManifestRow::Dir {
+                        name: dir.to_string(),
+                        prefix,
+                        depth,
+                    });
+                }
+            }
+            rows.push(ManifestRow::Entry {
+                index,
+                name: name.to_string(),
+                depth: parents.len(),
+            });
         }
+        rows
+    }
+
+    fn manifest_dir_checked(
+        &self,
+        prefix: &str,
+    ) -> bool {
+        self.manifest.iter().filter(|(entry, _)| is_below(entry, prefix)).all(|(_, checked)| *checked)
+    }
+
+    fn selected_file_ids(&self) -> Vec<u64> {
+        self.manifest.iter().filter(|(_, checked)| *checked).map(|(entry, _)| entry.file_id).collect()
     }
 
     fn send_confirm(
@@ -101,6 +178,17 @@ impl ReceiveView {
     }
 }
 
+fn manifest_path(entry: &ManifestEntry) -> String {
+    entry.relative_path.replace('\\', "/")
+}
+
+fn is_below(
+    entry: &ManifestEntry,
+    prefix: &str,
+) -> bool {
+    manifest_path(entry).strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/'))
+}
+
 impl Render for ReceiveView {
     fn render(
         &mut self,
@@ -135,11 +223,90 @@ impl Render for ReceiveView {
 
             let mut items = vec![];
             for progress_bar in &self.progress_bars {
-                items.push(div().p_2().mb_1().bg(cx.theme().list_hover).rounded_md().child(progress_bar.clone().into_element()));
+                let file_id = progress_bar.get_file_id();
+                let cancel_button = progress_bar.is_active().then(|| {
+                    Button::new(("cancel_file", file_id as usize))
+                        .cursor_pointer()
+                        .icon(CustomIconName::Remove)
+                        .small()
+                        .ghost()
+                        .tooltip(i18n_receive(cx, "cancel_file"))
+                        .on_click(cx.listener(move |view, _, _, _| {
+                            view.send_confirm(ReceiverConfirm::CancelFile(file_id));
+                        }))
+                });
+                items.push(
+                    div()
+                        .p_2()
+                        .mb_1()
+                        .bg(cx.theme().list_hover)
+                        .rounded_md()
+                        .child(h_flex().gap_2().child(div().flex_1().child(progress_bar.clone().into_element())).children(cancel_button)),
+                );
+            }
+
+            if let Some(text) = &self.text {
+                items.push(
+                    div().p_2().mb_1().bg(cx.theme().list_hover).rounded_md().child(
+                        h_flex()
+                            .gap_2()
+                            .items_start()
+                            .child(div().flex_1().child(Label::new(text.clone()).text_sm().text_color(cx.theme().primary)))
+                            .child(Clipboard::new("copy-text").value(text.clone())),
+                    ),
+                );
             }
 
-            Card::new("receive-view-card").overflow_y_scrollbar().h_72().when(self.progress_bars.is_empty(), |this| this.child(placeholder)).when(
-                !self.progress_bars.is_empty(),
+            if self.receive_state == ReceiveState::Selecting {
+                for row in self.manifest_rows() {
+                    let item = match row {
+                        ManifestRow::Dir {
+                            name,
+                            prefix,
+                            depth,
+                        } => {
+                            let checked = self.manifest_dir_checked(&prefix);
+                            let id = self.manifest.iter().position(|(entry, _)| is_below(entry, &prefix)).unwrap_or_default();
+                            h_flex().pl(px(depth as f32 * 16.)).child(
+                                Checkbox::new(("manifest_dir", id)).label(format!("{name}/")).checked(checked).on_click(cx.listener(
+                                    move |view, checked: &bool, _, _| {
+                                        for (entry, entry_checked) in view.manifest.iter_mut() {
+                                            if is_below(entry, &prefix) {
+                                                *entry_checked = *checked;
+                                            }
+                                        }
+                                    },
+                                )),
+                            )
+                        }
+                        ManifestRow::Entry {
+                            index,
+                            name,
+                            depth,
+                        } => {
+                            let (entry, checked) = &self.manifest[index];
+                            let label = if entry.is_empty_dir {
+                                format!("{name}/")
+                            } else {
+                                format!("{name} ({})", human_bytes(entry.size))
+                            };
+                            h_flex().pl(px(depth as f32 * 16.)).child(
+                                Checkbox::new(("manifest_entry", index)).label(label).checked(*checked).on_click(cx.listener(
+                                    move |view, checked: &bool, _, _| {
+                                        if let Some((_, entry_checked)) = view.manifest.get_mut(index) {
+                                            *entry_checked = *checked;
+                                        }
+                                    },
+                                )),
+                            )
+                        }
+                    };
+                    items.push(div().px_2().py_1().child(item));
+                }
+            }
+
+            Card::new("receive-view-card").overflow_y_scrollbar().h_72().when(items.is_empty(), |this| this.child(placeholder)).when(
+                !items.is_empty(),
                 |mut this| {
                     for file in items {
                         this = this.child(file);
@@ -149,10 +316,31 @@ impl Render for ReceiveView {
             )
         };
 
+        let pause_button = if self.receive_state == ReceiveState::Receiving {
+            let label = if self.paused {
+                i18n_receive(cx, "resume")
+            } else {
+                i18n_receive(cx, "pause")
+            };
+            h_flex().h_6().justify_end().child(
+                Button::new("pause_receive").info().label(label).small().cursor_pointer().on_click(cx.listener(|view, _, _, _| {
+                    if let Some(fcr) = &view.flash_cat_receiver {
+                        if fcr.is_paused() {
+                            fcr.resume();
+                        } else {
+                            fcr.pause();
+                        }
+                    }
+                })),
+            )
+        } else {
+            h_flex()
+        };
+
         let receive_button = {
             let label = match &self.receive_state {
                 ReceiveState::Idle => Some(i18n_receive(cx, "receive")),
-                ReceiveState::Connecting | ReceiveState::AwaitingConfirm => {
+                ReceiveState::Connecting | ReceiveState::AwaitingConfirm | ReceiveState::Selecting => {
                     if self.receive_but_hover {
                         Some(i18n_receive(cx, "cancel_receive"))
                     } else {
@@ -176,7 +364,10 @@ impl Render for ReceiveView {
 
             let mut button = Button::new("receive_button").size_full().h_10().info().disabled(disabled).when(!disabled, |this| this.cursor_pointer());
 
-            if self.receive_state == ReceiveState::Connecting || self.receive_state == ReceiveState::AwaitingConfirm {
+            if self.receive_state == ReceiveState::Connecting
+                || self.receive_state == ReceiveState::AwaitingConfirm
+                || self.receive_state == ReceiveState::Selecting
+            {
                 if !self.receive_but_hover {
                     button = button.child(div().flex().justify_center().child(spinner));
                 }
@@ -184,6 +375,7 @@ impl Render for ReceiveView {
 
             if self.receive_state == ReceiveState::Connecting
                 || self.receive_state == ReceiveState::AwaitingConfirm
+                || self.receive_state == ReceiveState::Selecting
                 || self.receive_state == ReceiveState::Receiving
             {
                 button = button.on_hover(cx.listener(|view, hover, _, _| {
@@ -204,6 +396,7 @@ impl Render for ReceiveView {
                     view.receive_state = ReceiveState::Connecting;
                     let relay_addr = cx.global::<FlashCatAppGlobalStore>().read(cx).relay_address();
                     let save_path = cx.global::<FlashCatAppGlobalStore>().read(cx).save_path();
+                    let limit_rate = cx.global::<FlashCatAppGlobalStore>().read(cx).limit_rate_bytes();
 
                     let relay = if relay_addr.contains(PUBLIC_RELAY) {
                         None
@@ -213,10 +406,22 @@ impl Render for ReceiveView {
 
                     let lan = view.lan;
 
-                    let fcr = FlashCatReceiver::new(share_code, relay, Some(save_path), ClientType::App, lan);
+                    let fcr = FlashCatReceiver::new(
+                        share_code,
+                        Some(save_path),
+                        ReceiveOptions {
+                            specify_relay: relay,
+                            client_type: ClientType::App,
+                            lan,
+                            limit_rate,
+                            ..Default::default()
+                        },
+                    );
                     match fcr {
                         Ok(fcr) => {
                             let fcr = Arc::new(fcr);
+                            view.paused = false;
+                            view.text = None;
                             view.flash_cat_receiver.replace(fcr.clone());
 
                             cx.spawn(async move |view, cx| {
@@ -272,12 +477,36 @@ impl Render for ReceiveView {
                                                         };
                                                         view.receive_state = ReceiveState::AwaitingConfirm;
                                                     }
+                                                    ReceiverInteractionMessage::Manifest(entries) => {
+                                                        if entries.len() > 1 {
+                                                            view.manifest = entries.into_iter().map(|entry| (entry, true)).collect();
+                                                            view.notification = NotificationType::ConfirmSelection;
+                                                            view.receive_state = ReceiveState::Selecting;
+                                                        } else {
+                                                            // nothing to choose from
+                                                            view.send_confirm(ReceiverConfirm::SelectFiles(
+                                                                entries.iter().map(|entry| entry.file_id).collect(),
+                                                            ));
+                                                        }
+                                                    }
                                                     ReceiverInteractionMessage::FileDuplication(dup) => {
                                                         view.notification = NotificationType::ConfirmFileDuplicate {
                                                             file_id: dup.file_id,
                                                             file_path: dup.path,
                                                         };
                                                     }
+                                                    ReceiverInteractionMessage::BatchDuplication(dup) => {
+                                                        view.notification = NotificationType::ConfirmBatchDuplicate {
+                                                            batch_id: dup.batch_id,
+                                                            count: dup.files.len(),
+                                                        };
+                                                    }
+                                                    ReceiverInteractionMessage::ExtractArchive(extract) => {
+                                                        view.notification = NotificationType::ConfirmExtract {
+                                                            file_id: extract.file_id,
+                                                            file_path: extract.path,
+                                                        };
+                                                    }
                                                     ReceiverInteractionMessage::RecvNewFile(new_file) => {
                                                         view.progress_bars.push(ProgressBar::new(new_file.file_id, new_file.filename, new_file.size));
                                                         if view.receive_state != ReceiveState::Receiving {
@@ -289,6 +518,8 @@ impl Render for ReceiveView {
                                                         if let Some(pb) = view.progress_bars.iter_mut().find(|pb| pb.get_file_id() == bp.file_id) {
                                                             pb.set_progress(bp.position);
                                                         }
+                                                        // resuming is safe, only chunks that differ are sent again and the file is verified
+                                                        view.send_confirm(ReceiverConfirm::BreakPointConfirm((true, bp.file_id)));
                                                     }
                                                     ReceiverInteractionMessage::FileProgress(progress) => {
                                                         if let Some(pb) = view.progress_bars.iter_mut().find(|pb| pb.get_file_id() == progress.file_id) {
@@ -297,9 +528,32 @@ impl Render for ReceiveView {
                                                     }
                                                     ReceiverInteractionMessage::FileProgressFinish(file_id) => {
                                                         if let Some(pb) = view.progress_bars.iter_mut().find(|pb| pb.get_file_id() == file_id) {
-                                                            pb.finish();
+                                                            pb.finish_verified(true);
+                                                        }
+                                                    }
+                                                    ReceiverInteractionMessage::FileVerifyFailed(file_id) => {
+                                                        if let Some(pb) = view.progress_bars.iter_mut().find(|pb| pb.get_file_id() == file_id) {
+                                                            pb.finish_verified(false);
                                                         }
                                                     }
+                                                    ReceiverInteractionMessage::FileCancelled(file_id) => {
+                                                        if let Some(pb) = view.progress_bars.iter_mut().find(|pb| pb.get_file_id() == file_id) {
+                                                            pb.skip();
+                                                        }
+                                                    }
+                                                    ReceiverInteractionMessage::Paused(paused) => {
+                                                        view.paused = paused;
+                                                    }
+                                                    ReceiverInteractionMessage::PausedByPeer(paused) => {
+                                                        view.notification = if paused {
+                                                            NotificationType::Message(i18n_receive(cx, "paused_by_peer").to_string())
+                                                        } else {
+                                                            NotificationType::None
+                                                        };
+                                                    }
+                                                    ReceiverInteractionMessage::Text(text) => {
+                                                        view.text = Some(text);
+                                                    }
                                                     ReceiverInteractionMessage::OtherClose => {
                                                         // let locale = cx.global::<FlashCatAppGlobalStore>().read(cx).locale();
                                                         // view.notification = NotificationType::Error(
@@ -309,7 +563,12 @@ impl Render for ReceiveView {
                                                     }
                                                     ReceiverInteractionMessage::ReceiveDone => {
                                                         view.receive_state = ReceiveState::ReceiveDone;
-                                                        view.notification = NotificationType::ConfirmOpenSavePath;
+                                                        // nothing was saved when a text was received
+                                                        view.notification = if view.text.is_some() {
+                                                            NotificationType::None
+                                                        } else {
+                                                            NotificationType::ConfirmOpenSavePath
+                                                        };
                                                     }
                                                 }
                                                 cx.notify();
@@ -333,7 +592,7 @@ impl Render for ReceiveView {
                         }
                     }
                 }
-                ReceiveState::Connecting | ReceiveState::AwaitingConfirm | ReceiveState::Receiving => {
+                ReceiveState::Connecting | ReceiveState::AwaitingConfirm | ReceiveState::Selecting | ReceiveState::Receiving => {
                     // Cancel receive
                     if let Some(fcr) = view.flash_cat_receiver.take() {
                         fcr.shutdown();
@@ -341,10 +600,13 @@ impl Render for ReceiveView {
                     view.receive_state = ReceiveState::Idle;
                     view.notification = NotificationType::None;
                     view.progress_bars.clear();
+                    view.manifest.clear();
+                    view.text = None;
                 }
                 ReceiveState::ReceiveDone => {
                     // Reset after completion
                     view.progress_bars.clear();
+                    view.text = None;
                     view.flash_cat_receiver = None;
                     view.share_code_state.update(cx, |state, cx| {
                         state.set_value("".to_string(), window, cx);
@@ -394,6 +656,19 @@ impl Render for ReceiveView {
                         })),
                     )
             }
+            NotificationType::ConfirmSelection => {
+                let locale = cx.global::<FlashCatAppGlobalStore>().read(cx).locale();
+                let file_count = self.manifest.iter().filter(|(entry, checked)| *checked && !entry.is_empty_dir).count();
+                let msg = t!("receive.confirm_selection", file_count = file_count, locale = locale);
+                h_flex().gap_2().child(Label::new(msg.to_string()).text_sm().text_color(cx.theme().primary)).child(
+                    Button::new("selection_yes").small().info().label("Yes").cursor_pointer().on_click(cx.listener(|view, _, _, _| {
+                        view.send_confirm(ReceiverConfirm::SelectFiles(view.selected_file_ids()));
+                        view.manifest.clear();
+                        view.notification = NotificationType::None;
+                        view.receive_state = ReceiveState::AwaitingConfirm;
+                    })),
+                )
+            }
             NotificationType::ConfirmFileDuplicate {
                 file_id,
                 file_path,
@@ -420,6 +695,52 @@ impl Render for ReceiveView {
                         })),
                     )
             }
+            NotificationType::ConfirmBatchDuplicate {
+                batch_id,
+                count,
+            } => {
+                let locale = cx.global::<FlashCatAppGlobalStore>().read(cx).locale();
+                let msg = t!("receive.batch_duplicate", count = count, locale = locale);
+                let batch_id = *batch_id;
+                h_flex()
+                    .gap_2()
+                    .child(Label::new(msg.to_string()).text_sm().text_color(cx.theme().primary))
+                    .child(
+                        Button::new("batch_dup_yes").small().info().label("Yes").cursor_pointer().on_click(cx.listener(move |view, _, _, _| {
+                            view.send_confirm(ReceiverConfirm::BatchConfirm((true, batch_id)));
+                            view.notification = NotificationType::None;
+                        })),
+                    )
+                    .child(
+                        Button::new("batch_dup_no").small().ghost().label("No").cursor_pointer().on_click(cx.listener(move |view, _, _, _| {
+                            view.send_confirm(ReceiverConfirm::BatchConfirm((false, batch_id)));
+                            view.notification = NotificationType::None;
+                        })),
+                    )
+            }
+            NotificationType::ConfirmExtract {
+                file_id,
+                file_path,
+            } => {
+                let locale = cx.global::<FlashCatAppGlobalStore>().read(cx).locale();
+                let msg = t!("receive.extract_archive", file_path = file_path, locale = locale);
+                let file_id = *file_id;
+                h_flex()
+                    .gap_2()
+                    .child(Label::new(msg.to_string()).text_sm().text_color(cx.theme().primary))
+                    .child(
+                        Button::new("extract_yes").small().info().label("Yes").cursor_pointer().on_click(cx.listener(move |view, _, _, _| {
+                            view.send_confirm(ReceiverConfirm::ExtractConfirm((true, file_id)));
+                            view.notification = NotificationType::None;
+                        })),
+                    )
+                    .child(
+                        Button::new("extract_no").small().ghost().label("No").cursor_pointer().on_click(cx.listener(move |view, _, _, _| {
+                            view.send_confirm(ReceiverConfirm::ExtractConfirm((false, file_id)));
+                            view.notification = NotificationType::None;
+                        })),
+                    )
+            }
             NotificationType::ConfirmOpenSavePath => {
                 let locale = cx.global::<FlashCatAppGlobalStore>().read(cx).locale();
                 let msg = t!("receive.open_save_path", locale = locale);
@@ -441,6 +762,15 @@ impl Render for ReceiveView {
             }
         };
 
-        v_flex().id("receive-view").m_2().gap_1().child(share_code_input).child(lan_checkbox).child(receive_card).child(receive_button).child(notification_view)
+        v_flex()
+            .id("receive-view")
+            .m_2()
+            .gap_1()
+            .child(share_code_input)
+            .child(lan_checkbox)
+            .child(receive_card)
+            .child(pause_button)
+            .child(receive_button)
+            .child(notification_view)
     }
 }
@@ -8,7 +8,10 @@ use flash_cat_common::{
         gen_share_code,
     },
 };
-use flash_cat_core::{SenderInteractionMessage, sender::FlashCatSender};
+use flash_cat_core::{
+    SenderInteractionMessage,
+    sender::{DEFAULT_CONCURRENT_FILES, FlashCatSender, SendOptions},
+};
 use gpui::{Context, InteractiveElement, IntoElement, ParentElement, Render, Styled, Window, div, prelude::FluentBuilder};
 use gpui_component::{
     ActiveTheme, Disableable, IconName, Sizable,
@@ -55,6 +58,7 @@ pub struct SendView {
     flash_cat_sender: Option<Arc<FlashCatSender>>,
     progress_bars: Vec<ProgressBar>,
     notification: NotificationType,
+    paused: bool,
 }
 
 impl SendView {
@@ -71,6 +75,7 @@ impl SendView {
             flash_cat_sender: None,
             progress_bars: vec![],
             notification: NotificationType::None,
+            paused: false,
         }
     }
 }
@@ -160,7 +165,28 @@ impl Render for SendView {
             let mut items = vec![];
             if self.send_state == SendState::AwaitingReceive || self.send_state == SendState::Sending || self.send_state == SendState::SendDone {
                 for progress_bar in &self.progress_bars {
-                    items.push(div().p_2().mb_1().bg(cx.theme().list_hover).rounded_md().child(progress_bar.clone().into_element()));
+                    let file_id = progress_bar.get_file_id();
+                    let cancel_button = progress_bar.is_active().then(|| {
+                        Button::new(("cancel_file", file_id as usize))
+                            .cursor_pointer()
+                            .icon(CustomIconName::Remove)
+                            .small()
+                            .ghost()
+                            .tooltip(i18n_send(cx, "cancel_file"))
+                            .on_click(cx.listener(move |view, _, _, _| {
+                                if let Some(fcs) = &view.flash_cat_sender {
+                                    fcs.cancel_file(file_id);
+                                }
+                            }))
+                    });
+                    items.push(
+                        div()
+                            .p_2()
+                            .mb_1()
+                            .bg(cx.theme().list_hover)
+                            .rounded_md()
+                            .child(h_flex().gap_2().child(div().flex_1().child(progress_bar.clone().into_element())).children(cancel_button)),
+                    );
                 }
             } else {
                 for (i, file) in self.selected_files.iter().enumerate() {
@@ -222,7 +248,24 @@ impl Render for SendView {
                         locale = locale
                     )))
                 }
-                SendState::Sending => h_flex,
+                SendState::Sending => {
+                    let label = if self.paused {
+                        i18n_send(cx, "resume")
+                    } else {
+                        i18n_send(cx, "pause")
+                    };
+                    h_flex.child(div().flex().gap_1().size_full().justify_end().child(
+                        Button::new("pause_send").info().label(label).small().cursor_pointer().on_click(cx.listener(|view, _, _, _| {
+                            if let Some(fcs) = &view.flash_cat_sender {
+                                if fcs.is_paused() {
+                                    fcs.resume();
+                                } else {
+                                    fcs.pause();
+                                }
+                            }
+                        })),
+                    ))
+                }
                 SendState::SendDone => h_flex,
             }
         };
@@ -269,9 +312,21 @@ impl Render for SendView {
                     view.send_state = SendState::Collecting;
                     let files = view.selected_files.clone();
                     let relay_addr = cx.global::<FlashCatAppGlobalStore>().read(cx).relay_address();
+                    let limit_rate = cx.global::<FlashCatAppGlobalStore>().read(cx).limit_rate_bytes();
 
                     cx.spawn(async move |view, cx| {
-                        let file_collector = cx.background_executor().spawn(async move { collect_files(&files) }).await;
+                        let file_collector = match cx.background_executor().spawn(async move { collect_files(&files) }).await {
+                            Ok(file_collector) => file_collector,
+                            Err(e) => {
+                                view.update(cx, |view, cx| {
+                                    view.notification = NotificationType::Error(e.to_string());
+                                    view.send_state = SendState::FileSelected;
+                                    cx.notify();
+                                })
+                                .ok();
+                                return;
+                            }
+                        };
                         view.update(cx, |view, _| {
                             view.file_collector = Some(file_collector.clone());
 
@@ -285,11 +340,24 @@ impl Render for SendView {
                             view.progress_bars.clear(); // in case of re-collecting files
                             file_collector.files.iter().for_each(|f| view.progress_bars.push(ProgressBar::new(f.file_id, f.name.clone(), f.size)));
 
-                            let fcs = FlashCatSender::new_with_file_collector(share_code.clone(), specify_relay, file_collector.clone(), ClientType::App, true);
+                            let fcs = FlashCatSender::new_with_file_collector(
+                                share_code.clone(),
+                                file_collector.clone(),
+                                SendOptions {
+                                    specify_relay,
+                                    client_type: ClientType::App,
+                                    lan_broadcast: true,
+                                    compress: true,
+                                    concurrency: DEFAULT_CONCURRENT_FILES,
+                                    limit_rate,
+                                    ..Default::default()
+                                },
+                            );
                             match fcs {
                                 Ok(fcs) => {
                                     let fcs = Arc::new(fcs);
                                     view.flash_cat_sender.replace(fcs.clone());
+                                    view.paused = false;
                                 }
                                 Err(_) => {
                                     // todo handle error
@@ -350,7 +418,7 @@ impl Render for SendView {
                                                 // Handle relay failure
                                                 view.notification = NotificationType::Error(error);
                                             }
-                                            SenderInteractionMessage::ContinueFile(file_id) => {
+                                            SenderInteractionMessage::ContinueFile(file_id) | SenderInteractionMessage::FileCancelled(file_id) => {
                                                 // Skip this file
                                                 if let Some(pb) = view.progress_bars.iter_mut().find(|pb| pb.get_file_id() == file_id) {
                                                     pb.skip();
@@ -369,6 +437,29 @@ impl Render for SendView {
                                                     pb.finish();
                                                 }
                                             }
+                                            SenderInteractionMessage::Stats(_) => {
+                                                // Chunk size tuning, not shown
+                                            }
+                                            SenderInteractionMessage::Paused(paused) => {
+                                                view.paused = paused;
+                                            }
+                                            SenderInteractionMessage::PausedByPeer(paused) => {
+                                                view.notification = if paused {
+                                                    NotificationType::Message(i18n_send(cx, "paused_by_peer").to_string())
+                                                } else {
+                                                    NotificationType::None
+                                                };
+                                            }
+                                            SenderInteractionMessage::ReceiverJoined(_)
+                                            | SenderInteractionMessage::ReceiverProgress(_)
+                                            | SenderInteractionMessage::ReceiverFileFinished(_)
+                                            | SenderInteractionMessage::ReceiverFileSkipped(_)
+                                            | SenderInteractionMessage::ReceiverLeft(_)
+                                            | SenderInteractionMessage::ReceiverCompleted(_)
+                                            | SenderInteractionMessage::Downloaded(_)
+                                            | SenderInteractionMessage::Expired => {
+                                                // Only sent to several receivers from the cli
+                                            }
                                             SenderInteractionMessage::OtherClose => {
                                                 // Handle other side close
                                                 view.notification = NotificationType::Message("Receiver disconnected".to_string());
@@ -425,6 +516,7 @@ impl Render for SendView {
                     view.flash_cat_sender = None;
                     view.share_code.clear();
                     view.notification = NotificationType::None;
+                    view.paused = false;
                     view.send_state = SendState::Idle;
                 }
             }))
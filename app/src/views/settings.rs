@@ -1,5 +1,7 @@
 use std::path::Path;
 
+use flash_cat_common::utils::rate_limit::parse_rate;
+
 use gpui::{AppContext, Context, Entity, InteractiveElement, IntoElement, ParentElement, Render, StatefulInteractiveElement, Styled, Window, div};
 use gpui_component::{
     ActiveTheme, IconName, Sizable,
@@ -23,6 +25,9 @@ pub struct SettingsView {
     relay_address: String,
     relay_address_state: Entity<InputState>,
     save_path: String,
+    edit_limit_rate: bool,
+    limit_rate: String,
+    limit_rate_state: Entity<InputState>,
 }
 
 impl SettingsView {
@@ -30,9 +35,9 @@ impl SettingsView {
         window: &mut Window,
         cx: &mut Context<Self>,
     ) -> Self {
-        let (relay_address, save_path) = {
+        let (relay_address, save_path, limit_rate) = {
             let store = cx.global::<FlashCatAppGlobalStore>().read(cx);
-            (store.relay_address(), store.save_path())
+            (store.relay_address(), store.save_path(), store.limit_rate())
         };
 
         let relay_address_state = cx.new(|cx| {
@@ -41,11 +46,20 @@ impl SettingsView {
             state
         });
 
+        let limit_rate_state = cx.new(|cx| {
+            let mut state = InputState::new(window, cx).placeholder("5MiB/s");
+            state.set_value(limit_rate.clone(), window, cx);
+            state
+        });
+
         Self {
             edit_relay_address: false,
             relay_address,
             relay_address_state,
             save_path,
+            edit_limit_rate: false,
+            limit_rate,
+            limit_rate_state,
         }
     }
 
@@ -171,7 +185,79 @@ impl SettingsView {
                     ),
             );
 
-        Card::new("general-settings-card").title(i18n_settings(cx, "general")).m_2().child(relay_setting).child(save_path_setting)
+        let limit_rate_setting = v_flex()
+            .mb_2()
+            .child(
+                Label::new(i18n_settings(cx, "limit_rate"))
+                    .secondary(i18n_settings(cx, "limit_rate_description"))
+                    .text_base()
+                    .whitespace_nowrap()
+                    .text_ellipsis(),
+            )
+            .child(if self.edit_limit_rate {
+                h_flex().items_center().child(h_flex().w_full().child(Input::new(&self.limit_rate_state).small())).child(
+                    h_flex()
+                        .gap_1()
+                        .w_full()
+                        .justify_end()
+                        .child(
+                            Button::new("edit-limit-rate-save")
+                                .icon(IconName::Check)
+                                .small()
+                                .ghost()
+                                .cursor_pointer()
+                                .tooltip(i18n_common(cx, "update_tooltip"))
+                                .on_click(cx.listener(|this, _, _, cx| {
+                                    let new_limit_rate = this.limit_rate_state.read(cx).value().trim().to_string();
+                                    // an invalid rate stays in the input to be corrected
+                                    if !new_limit_rate.is_empty() && parse_rate(&new_limit_rate).is_err() {
+                                        return;
+                                    }
+                                    this.limit_rate = new_limit_rate.clone();
+                                    this.edit_limit_rate = false;
+                                    update_app_state_and_save(cx, "update limit rate", move |state, _| {
+                                        state.set_limit_rate(Some(new_limit_rate).filter(|rate| !rate.is_empty()));
+                                    });
+                                })),
+                        )
+                        .child(
+                            Button::new("edit-limit-rate-cancel")
+                                .icon(CustomIconName::Remove)
+                                .small()
+                                .ghost()
+                                .cursor_pointer()
+                                .tooltip(i18n_common(cx, "cancel_tooltip"))
+                                .on_click(cx.listener(|this, _, window, cx| {
+                                    this.edit_limit_rate = false;
+                                    let old_limit_rate = this.limit_rate.clone();
+                                    this.limit_rate_state.update(cx, |state, cx| {
+                                        state.set_value(old_limit_rate, window, cx);
+                                    });
+                                })),
+                        ),
+                )
+            } else {
+                let limit_rate = if self.limit_rate.is_empty() {
+                    i18n_settings(cx, "unlimited")
+                } else {
+                    self.limit_rate.clone().into()
+                };
+                h_flex().items_center().child(h_flex().w_full().child(Label::new(limit_rate).text_sm().ml_2())).child(
+                    h_flex().w_full().justify_end().child(
+                        Button::new("edit-limit-rate")
+                            .icon(CustomIconName::Edit)
+                            .small()
+                            .ghost()
+                            .cursor_pointer()
+                            .tooltip(i18n_common(cx, "edit_tooltip"))
+                            .on_click(cx.listener(|this, _, _, _| {
+                                this.edit_limit_rate = true;
+                            })),
+                    ),
+                )
+            });
+
+        Card::new("general-settings-card").title(i18n_settings(cx, "general")).m_2().child(relay_setting).child(save_path_setting).child(limit_rate_setting)
     }
 
     fn about_card(
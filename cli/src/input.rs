@@ -0,0 +1,79 @@
+use std::io::stdin;
+
+use tokio::sync::mpsc;
+
+/// Command typed to pause the transfer.
+pub const PAUSE_COMMAND: &str = "p";
+
+/// Command typed to resume the transfer.
+pub const RESUME_COMMAND: &str = "r";
+
+/// Command typed to cancel a single file, followed by its relative path.
+pub const CANCEL_COMMAND: &str = "c";
+
+/// Shown once the transfer starts.
+pub const COMMAND_HINT: &str = "Type 'p' and press enter to pause the transfer, 'r' to resume it, 'c <file path>' to cancel a file";
+
+/// Command typed while transferring.
+pub enum Command<'a> {
+    Pause,
+    Resume,
+    /// Cancel the file being transferred at the relative path.
+    Cancel(&'a str),
+}
+
+pub fn parse_command(line: &str) -> Option<Command<'_>> {
+    let line = line.trim();
+    match line.split_once(char::is_whitespace) {
+        Some((CANCEL_COMMAND, path)) => Some(Command::Cancel(path.trim())),
+        Some(_) => None,
+        None => match line {
+            PAUSE_COMMAND => Some(Command::Pause),
+            RESUME_COMMAND => Some(Command::Resume),
+            _ => None,
+        },
+    }
+}
+
+/// Lines typed on stdin, read on a thread of their own so the answers to the prompts
+/// and the pause commands can be awaited along with the transfer.
+pub struct Input {
+    lines: mpsc::Receiver<String>,
+}
+
+impl Input {
+    pub fn spawn() -> Self {
+        let (tx, lines) = mpsc::channel(16);
+        std::thread::spawn(move || {
+            for line in stdin().lines() {
+                let Ok(line) = line else {
+                    return;
+                };
+                if tx.blocking_send(line).is_err() {
+                    return;
+                }
+            }
+        });
+        Self {
+            lines,
+        }
+    }
+
+    /// No lines at all, for when stdin carries the data sent.
+    pub fn closed() -> Self {
+        let (_, lines) = mpsc::channel(1);
+        Self {
+            lines,
+        }
+    }
+
+    /// Next line typed, `None` once stdin is closed.
+    pub async fn next(&mut self) -> Option<String> {
+        self.lines.recv().await
+    }
+
+    /// Answer to a prompt, empty once stdin is closed.
+    pub async fn read_line(&mut self) -> String {
+        self.next().await.unwrap_or_default()
+    }
+}
@@ -1,3 +1,4 @@
+pub mod input;
 pub mod progress;
 pub mod receive;
 pub mod send;
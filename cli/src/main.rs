@@ -1,6 +1,9 @@
 use std::{
+    io::{read_to_string, stdin},
     net::{IpAddr, SocketAddr},
+    path::PathBuf,
     process::ExitCode,
+    time::Duration,
 };
 
 use anyhow::{Result, bail};
@@ -12,8 +15,23 @@ use tokio::signal::ctrl_c;
 use tokio::signal::unix::{SignalKind, signal};
 
 use flash_cat_cli::{built_info, receive::Receive, send::Send, update};
-use flash_cat_common::{VersionInfo, init_logger, utils::fs::is_file};
-use flash_cat_relay::relay::Relay;
+use flash_cat_common::{
+    VersionInfo,
+    consts::MAX_RECEIVERS,
+    format::parse_duration,
+    init_logger,
+    proto::ClientType,
+    utils::{
+        fs::{CollectOptions, STDIO_PATH, is_file},
+        parse_size,
+        rate_limit::parse_rate,
+    },
+};
+use flash_cat_core::{
+    receiver::ReceiveOptions,
+    sender::{DEFAULT_CONCURRENT_FILES, KeepAlive, SendOptions},
+};
+use flash_cat_relay::{mailbox::MailboxConfig, relay::Relay};
 
 #[derive(Parser, Debug)]
 #[clap(name = "flash-cat-cli")]
@@ -44,6 +62,10 @@ struct SendCmd {
     #[clap(long)]
     zip: bool,
 
+    /// Stream folders as tar archives built on the fly, without a temporary archive file
+    #[clap(long, conflicts_with = "zip")]
+    tar: bool,
+
     /// Relay address (default: public relay [https://flashcat.yunisdu.com])
     #[clap(long, env = "FLASH_CAT_RELAY")]
     relay: Option<String>,
@@ -52,8 +74,72 @@ struct SendCmd {
     #[clap(long = "no-lan", action = ArgAction::SetFalse, default_value_t = true)]
     lan_broadcast: bool,
 
-    /// File(s) or folder(s) to send
-    #[clap(required = true, num_args = 1..)]
+    /// Send the file data uncompressed, otherwise compressible chunks are compressed with zstd
+    #[clap(long = "no-compress", action = ArgAction::SetFalse, default_value_t = true)]
+    compress: bool,
+
+    /// Number of files transferred concurrently
+    #[clap(long, default_value_t = DEFAULT_CONCURRENT_FILES)]
+    concurrency: usize,
+
+    /// Number of streams to the relay, the chunks of large files are striped across them
+    #[clap(long, default_value_t = 1)]
+    streams: usize,
+
+    /// Limit the sending rate, e.g. 5MiB/s or 500KB/s
+    #[clap(long, value_name = "RATE", value_parser = parse_rate)]
+    limit_rate: Option<u64>,
+
+    /// Number of receivers that may join the share, each file is read once for all of them
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..=MAX_RECEIVERS as i64))]
+    max_receivers: u32,
+
+    /// Keep the share open once a receiver is done, the next ones are served one after another
+    #[clap(long, conflicts_with = "max_receivers")]
+    keep_alive: bool,
+
+    /// Close the share after this many downloads, implies --keep-alive
+    #[clap(long, value_name = "N", conflicts_with = "max_receivers", value_parser = clap::value_parser!(u32).range(1..))]
+    max_downloads: Option<u32>,
+
+    /// Close the share after this long, e.g. 30m or 2h, implies --keep-alive
+    #[clap(long, value_name = "DURATION", conflicts_with = "max_receivers", value_parser = parse_duration)]
+    expire: Option<Duration>,
+
+    /// Store the transfer on the relay and exit, the receiver collects it later
+    #[clap(long, conflicts_with_all = ["max_receivers", "keep_alive", "max_downloads", "expire"])]
+    mailbox: bool,
+
+    /// Only send the files matching one of the globs (gitignore syntax)
+    #[clap(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip the files and folders matching one of the globs (gitignore syntax)
+    #[clap(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Skip the files ignored by .gitignore and .ignore files, and .git folders
+    #[clap(long)]
+    gitignore: bool,
+
+    /// Maximum depth to descend into folders
+    #[clap(long)]
+    max_depth: Option<usize>,
+
+    /// Send symbolic and hard links as links instead of following them
+    #[clap(long)]
+    preserve_links: bool,
+
+    /// Send the timestamps, ownership and extended attributes of the files and folders
+    #[clap(long)]
+    preserve: bool,
+
+    /// Send a short text instead of files, printed by the receiver, `-` reads it from stdin
+    #[clap(long, conflicts_with_all = ["files", "zip", "tar"])]
+    text: Option<String>,
+
+    /// File(s) or folder(s) to send, `-` sends the data read from stdin
+    #[clap(required_unless_present = "text", num_args = 1..)]
     files: Vec<String>,
 }
 
@@ -67,10 +153,14 @@ struct RecvCmd {
     #[clap(long, env = "FLASH_CAT_RELAY")]
     relay: Option<String>,
 
-    /// The save path of the received file(s) or folder(s)
+    /// The save path of the received file(s) or folder(s), `-` writes the received file to stdout
     #[clap(short = 'o', long)]
     output: Option<String>,
 
+    /// Same as --output, e.g. `flash-cat recv CODE -` to write the received file to stdout
+    #[clap(conflicts_with = "output")]
+    target: Option<String>,
+
     /// Automatically answer yes for all questions
     #[clap(short = 'y', long)]
     assumeyes: bool,
@@ -78,6 +168,30 @@ struct RecvCmd {
     /// Sender is in the same local area network
     #[clap(short, long)]
     lan: bool,
+
+    /// Only receive the files whose relative path matches one of the globs
+    #[clap(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Skip the files whose relative path matches one of the globs
+    #[clap(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Apply the timestamps, ownership and extended attributes sent along the files
+    #[clap(long)]
+    preserve: bool,
+
+    /// Extract the folders streamed as tar archives while they are received instead of keeping the archives
+    #[clap(long)]
+    extract: bool,
+
+    /// Keep the partial file of a cancelled file so a later transfer resumes it
+    #[clap(long)]
+    keep_partial: bool,
+
+    /// Limit the receiving rate, e.g. 5MiB/s or 500KB/s
+    #[clap(long, value_name = "RATE", value_parser = parse_rate)]
+    limit_rate: Option<u64>,
 }
 
 #[derive(Parser, Debug)]
@@ -101,6 +215,37 @@ struct RelayCmd {
     /// Log file path of the relay server.
     #[clap(long, default_value = "info", env = "RUST_LOG")]
     log_level: String,
+
+    /// Limit the rate each session is relayed at, e.g. 5MiB/s or 500KB/s
+    #[clap(long, value_name = "RATE", value_parser = parse_rate)]
+    session_limit_rate: Option<u64>,
+
+    /// Limit the rate all sessions together are relayed at
+    #[clap(long, value_name = "RATE", value_parser = parse_rate)]
+    limit_rate: Option<u64>,
+
+    /// Store the transfers of the senders sending with --mailbox in this folder until a receiver collects them.
+    /// They are encrypted with a key derived from the share code by Argon2id; the relay keeps the ciphertext,
+    /// the salt and costs of the key and a hash of the share code, so whoever runs it can try to guess the
+    /// share code offline, at the cost of one Argon2id run per guess
+    #[clap(long, value_name = "DIR", env = "FLASH_CAT_MAILBOX_DIR")]
+    mailbox_dir: Option<PathBuf>,
+
+    /// Largest transfer stored, e.g. 512MiB or 2GB
+    #[clap(long, value_name = "SIZE", default_value = "1GiB", value_parser = parse_size)]
+    mailbox_max_size: u64,
+
+    /// Most bytes the stored transfers take together, e.g. 10GiB
+    #[clap(long, value_name = "SIZE", default_value = "10GiB", value_parser = parse_size)]
+    mailbox_total_size: u64,
+
+    /// Most transfers stored at once
+    #[clap(long, value_name = "N", default_value_t = 100)]
+    mailbox_max_transfers: usize,
+
+    /// How long a stored transfer is kept, e.g. 30m or 2h
+    #[clap(long, value_name = "DURATION", default_value = "24h", value_parser = parse_duration)]
+    mailbox_ttl: Duration,
 }
 
 const VERSION_INFO: &'static VersionInfo = &VersionInfo {
@@ -124,7 +269,43 @@ async fn send(send_cmd: SendCmd) -> Result<()> {
     #[cfg(windows)]
     let sigint = ctrl_c();
 
-    let send = Send::new(send_cmd.zip, send_cmd.relay, send_cmd.files, send_cmd.lan_broadcast).await?;
+    let collect_options = CollectOptions {
+        include: send_cmd.include,
+        exclude: send_cmd.exclude,
+        respect_ignore: send_cmd.gitignore,
+        max_depth: send_cmd.max_depth,
+        preserve_links: send_cmd.preserve_links,
+        preserve_attributes: send_cmd.preserve,
+        archive_folders: send_cmd.tar,
+    };
+    let text = match send_cmd.text {
+        // e.g. the clipboard contents piped in, the receiver prints the line end itself
+        Some(text) if text == STDIO_PATH => {
+            let mut text = read_to_string(stdin())?;
+            text.truncate(text.trim_end_matches(['\r', '\n']).len());
+            Some(text)
+        }
+        text => text,
+    };
+    let keep_alive = (send_cmd.keep_alive || send_cmd.max_downloads.is_some() || send_cmd.expire.is_some()).then_some(KeepAlive {
+        max_downloads: send_cmd.max_downloads,
+        expires_in: send_cmd.expire,
+    });
+    if keep_alive.is_some() && send_cmd.files.iter().any(|file| file == STDIO_PATH) {
+        bail!("The data read from stdin can't be kept alive.");
+    }
+    let options = SendOptions {
+        specify_relay: send_cmd.relay,
+        client_type: ClientType::Cli,
+        lan_broadcast: send_cmd.lan_broadcast,
+        compress: send_cmd.compress,
+        concurrency: send_cmd.concurrency,
+        streams: send_cmd.streams,
+        limit_rate: send_cmd.limit_rate,
+        max_receivers: send_cmd.max_receivers,
+        keep_alive,
+    };
+    let send = Send::new(send_cmd.zip, send_cmd.files, text, collect_options, options, send_cmd.mailbox).await?;
 
     let send_task = async { send.run().await };
 
@@ -166,18 +347,27 @@ async fn recv(recv_cmd: RecvCmd) -> Result<()> {
     #[cfg(windows)]
     let sigint = ctrl_c();
 
-    if recv_cmd.output.is_some() {
-        if is_file(recv_cmd.output.clone().unwrap().as_str()) {
-            bail!("The output path is a file.");
-        }
+    let output = recv_cmd.output.or(recv_cmd.target);
+    if output.as_deref().is_some_and(|output| output != STDIO_PATH && is_file(output)) {
+        bail!("The output path is a file.");
     }
 
+    let options = ReceiveOptions {
+        specify_relay: recv_cmd.relay,
+        client_type: ClientType::Cli,
+        lan: recv_cmd.lan,
+        preserve_attributes: recv_cmd.preserve,
+        extract_archives: recv_cmd.extract,
+        keep_partial: recv_cmd.keep_partial,
+        limit_rate: recv_cmd.limit_rate,
+    };
     let receive = Receive::new(
         recv_cmd.share_code,
-        recv_cmd.relay,
-        recv_cmd.output,
+        output,
         recv_cmd.assumeyes,
-        recv_cmd.lan,
+        &recv_cmd.include,
+        &recv_cmd.exclude,
+        options,
     )?;
 
     let receive_task = async { receive.run().await };
@@ -215,6 +405,9 @@ async fn recv(recv_cmd: RecvCmd) -> Result<()> {
 async fn start_relay(
     addr: SocketAddr,
     external_ip: Option<IpAddr>,
+    session_limit_rate: Option<u64>,
+    limit_rate: Option<u64>,
+    mailbox: Option<MailboxConfig>,
 ) -> Result<()> {
     #[cfg(unix)]
     let mut sigterm = signal(SignalKind::terminate())?;
@@ -223,7 +416,7 @@ async fn start_relay(
     #[cfg(windows)]
     let sigint = ctrl_c();
 
-    let relay = Relay::new(external_ip, false)?;
+    let relay = Relay::new(external_ip, false, session_limit_rate, limit_rate, mailbox)?;
 
     let relay_task = async {
         info!("relay listening at {addr}");
@@ -280,7 +473,8 @@ fn main() -> ExitCode {
                 return match recv(recv_cmd) {
                     Ok(()) => ExitCode::SUCCESS,
                     Err(err) => {
-                        println!("{err:?}");
+                        // stdout may be receiving the file
+                        eprintln!("{err:?}");
                         ExitCode::FAILURE
                     }
                 };
@@ -288,7 +482,20 @@ fn main() -> ExitCode {
             SubCmd::Relay(relay_cmd) => {
                 init_logger(relay_cmd.log_level, relay_cmd.log_file);
                 let addr = SocketAddr::new(relay_cmd.ip, relay_cmd.port);
-                return match start_relay(addr, relay_cmd.external_ip) {
+                let mailbox = relay_cmd.mailbox_dir.map(|dir| MailboxConfig {
+                    dir,
+                    max_size: relay_cmd.mailbox_max_size,
+                    max_total_size: relay_cmd.mailbox_total_size,
+                    max_transfers: relay_cmd.mailbox_max_transfers,
+                    ttl: relay_cmd.mailbox_ttl,
+                });
+                return match start_relay(
+                    addr,
+                    relay_cmd.external_ip,
+                    relay_cmd.session_limit_rate,
+                    relay_cmd.limit_rate,
+                    mailbox,
+                ) {
                     Ok(()) => ExitCode::SUCCESS,
                     Err(err) => {
                         println!("{err:?}");
@@ -11,9 +11,13 @@ pub struct Progress {
     multi: MultiProgress,
     total_bar: Option<ProgressBar>,
     file_info: HashMap<u64, (String, u64)>,
+    /// Relative paths of the files, the names of several files may be the same.
+    relative_paths: HashMap<u64, String>,
     file_positions: HashMap<u64, u64>,
     progress_bar_map: HashMap<u64, ProgressBar>,
     finished_count: u64,
+    /// Shown after the files being transferred, e.g. the chunk size of the sender.
+    stats: String,
 }
 
 impl Progress {
@@ -29,9 +33,11 @@ impl Progress {
             multi: MultiProgress::new(),
             total_bar: None,
             file_info: HashMap::new(),
+            relative_paths: HashMap::new(),
             file_positions: HashMap::new(),
             progress_bar_map: HashMap::new(),
             finished_count: 0,
+            stats: String::new(),
         }
     }
 
@@ -50,20 +56,24 @@ impl Progress {
     pub fn register_file(
         &mut self,
         file_name: &str,
+        relative_path: &str,
         file_id: u64,
         total_size: u64,
     ) {
         self.file_info.insert(file_id, (file_name.to_string(), total_size));
+        self.relative_paths.insert(file_id, relative_path.to_string());
     }
 
     /// Create and immediately show a progress bar (used by receiver on-demand).
     pub fn add_progress(
         &mut self,
         file_name: &str,
+        relative_path: &str,
         file_id: u64,
         total_size: u64,
     ) {
         self.file_info.insert(file_id, (file_name.to_string(), total_size));
+        self.relative_paths.insert(file_id, relative_path.to_string());
         self.ensure_bar(file_id);
     }
 
@@ -95,14 +105,16 @@ impl Progress {
         let bar_info = self.file_info.get(&file_id).cloned();
         if let Some((name, total_size)) = bar_info {
             let file_name = format!("{:<width$}", name, width = self.max_file_name_len);
-            let pb = ProgressBar::new(total_size).with_prefix(file_name);
+            let pb = ProgressBar::new(total_size).with_prefix(file_name).with_message(self.stats.clone());
             pb.set_style(
-                ProgressStyle::with_template("{spinner:.green} {prefix:.bold.green} [{bar:50.cyan/blue}] {bytes}/{total_bytes} • {bytes_per_sec} • ETA {eta}")
-                    .unwrap()
-                    .with_key("eta", |state: &ProgressState, w: &mut dyn Write| {
-                        write!(w, "{:#}", HumanDuration(state.eta())).unwrap()
-                    })
-                    .progress_chars("#>-"),
+                ProgressStyle::with_template(
+                    "{spinner:.green} {prefix:.bold.green} [{bar:50.cyan/blue}] {bytes}/{total_bytes} • {bytes_per_sec} • ETA {eta}{msg}",
+                )
+                .unwrap()
+                .with_key("eta", |state: &ProgressState, w: &mut dyn Write| {
+                    write!(w, "{:#}", HumanDuration(state.eta())).unwrap()
+                })
+                .progress_chars("#>-"),
             );
             let pb = if let Some(total_bar) = &self.total_bar {
                 self.multi.insert_before(total_bar, pb)
@@ -155,6 +167,10 @@ impl Progress {
             if progress_bar.position() == 0 {
                 progress_bar.reset();
             }
+            // the size of an archive or of the data read from stdin is only known once it is sent
+            if progress_bar.length().is_some_and(|length| pos > length) {
+                progress_bar.set_length(pos);
+            }
             progress_bar.set_position(pos);
         }
         self.update_total(file_id, pos);
@@ -163,16 +179,43 @@ impl Progress {
     pub fn finish(
         &mut self,
         file_id: u64,
+    ) {
+        self.finish_file(file_id, None);
+    }
+
+    /// Finish a file whose content matches the digest of the sender.
+    pub fn verified(
+        &mut self,
+        file_id: u64,
+    ) {
+        self.finish_file(file_id, Some("\x1b[32mverified\x1b[0m"));
+    }
+
+    /// Finish a file whose content does not match the digest of the sender.
+    pub fn verify_failed(
+        &mut self,
+        file_id: u64,
+    ) {
+        self.finish_file(file_id, Some("\x1b[1;31mverification failed\x1b[0m"));
+    }
+
+    fn finish_file(
+        &mut self,
+        file_id: u64,
+        status: Option<&str>,
     ) {
         self.ensure_bar(file_id);
         if let Some(progress_bar) = self.progress_bar_map.remove(&file_id) {
-            let summary = format!(
+            let mut summary = format!(
                 "  \x1b[1;32m{}\x1b[0m [\x1b[36m{}\x1b[0m] {} • in {:#}",
                 progress_bar.prefix(),
                 "#".repeat(50),
                 HumanBytes(progress_bar.length().unwrap_or(0)),
                 HumanDuration(progress_bar.elapsed()),
             );
+            if let Some(status) = status {
+                summary.push_str(&format!(" • {status}"));
+            }
             progress_bar.finish_and_clear();
             let _ = self.multi.println(summary);
         }
@@ -204,6 +247,25 @@ impl Progress {
         self.finish_total_if_done();
     }
 
+    /// Show `stats` after the files being transferred.
+    pub fn set_stats(
+        &mut self,
+        stats: &str,
+    ) {
+        self.stats = format!(" • {stats}");
+        for progress_bar in self.progress_bar_map.values() {
+            progress_bar.set_message(self.stats.clone());
+        }
+    }
+
+    /// Id of the file being transferred at `relative_path`.
+    pub fn file_id(
+        &self,
+        relative_path: &str,
+    ) -> Option<u64> {
+        self.progress_bar_map.keys().copied().find(|file_id| self.relative_paths.get(file_id).is_some_and(|path| path == relative_path))
+    }
+
     pub fn finish_with_message(
         &mut self,
         file_id: u64,
@@ -222,3 +284,25 @@ impl Progress {
         let _ = self.multi.println(msg);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::Progress;
+
+    #[test]
+    fn file_id_matches_relative_path() {
+        let mut progress = Progress::new(3, 10, 30);
+        progress.register_file("a.txt", "docs/a.txt", 0, 10);
+        progress.register_file("a.txt", "notes/a.txt", 1, 10);
+        progress.register_file("b.txt", "docs/b.txt", 2, 10);
+        // only the files being transferred are found
+        assert_eq!(progress.file_id("notes/a.txt"), None);
+        progress.set_position(0, 5);
+        progress.set_position(1, 5);
+        assert_eq!(progress.file_id("notes/a.txt"), Some(1));
+        assert_eq!(progress.file_id("docs/a.txt"), Some(0));
+        assert_eq!(progress.file_id("a.txt"), None);
+        progress.finish(1);
+        assert_eq!(progress.file_id("notes/a.txt"), None);
+    }
+}
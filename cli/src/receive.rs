@@ -1,22 +1,34 @@
 use std::{
-    io::{Write, stdin, stdout},
+    io::{Write, stderr},
+    path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
 
 use anyhow::{Result, anyhow};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use indicatif::HumanBytes;
 use tokio_stream::StreamExt;
 
-use flash_cat_common::{Shutdown, proto::ClientType};
-use flash_cat_core::{ReceiverConfirm, ReceiverInteractionMessage, receiver::FlashCatReceiver};
+use flash_cat_common::Shutdown;
+use flash_cat_core::{
+    ManifestEntry, ReceiverConfirm, ReceiverInteractionMessage,
+    receiver::{FlashCatReceiver, ReceiveOptions},
+};
 
-use crate::progress::Progress;
+use crate::{
+    input::{COMMAND_HINT, Command, Input, RESUME_COMMAND, parse_command},
+    progress::Progress,
+};
 
 #[derive(Clone)]
 pub struct Receive {
     receiver: FlashCatReceiver,
+    /// The paths of the files received are shown under it.
+    output_dir: PathBuf,
     assumeyes: bool,
+    include: Option<GlobSet>,
+    exclude: Option<GlobSet>,
 
     shutdown: Shutdown,
 }
@@ -24,15 +36,20 @@ pub struct Receive {
 impl Receive {
     pub fn new(
         share_code: String,
-        specify_relay: Option<String>,
         output: Option<String>,
         assumeyes: bool,
-        lan: bool,
+        include: &[String],
+        exclude: &[String],
+        options: ReceiveOptions,
     ) -> Result<Self> {
-        let receiver = FlashCatReceiver::new(share_code, specify_relay, output, ClientType::Cli, lan)?;
+        let output_dir = output.as_deref().map(PathBuf::from).unwrap_or_default();
+        let receiver = FlashCatReceiver::new(share_code, output, options)?;
         Ok(Self {
             receiver,
+            output_dir,
             assumeyes,
+            include: build_glob_set(include)?,
+            exclude: build_glob_set(exclude)?,
             shutdown: Shutdown::new(),
         })
     }
@@ -47,49 +64,79 @@ impl Receive {
             }
         })?;
         let mut progress = Progress::new(1, 10, 0);
+        let mut max_file_name_length = 10;
+        let mut lines = Input::spawn();
         while !self.shutdown.is_terminated() {
-            if let Some(receiver_msg) = stream.next().await {
+            let receiver_msg = tokio::select! {
+                receiver_msg = stream.next() => receiver_msg,
+                Some(line) = lines.next() => {
+                    match parse_command(&line) {
+                        Some(Command::Pause) => self.receiver.pause(),
+                        Some(Command::Resume) => self.receiver.resume(),
+                        Some(Command::Cancel(path)) => match progress.file_id(path) {
+                            Some(file_id) => self.receiver.send_confirm(ReceiverConfirm::CancelFile(file_id)).await?,
+                            None => progress.println(&format!("No file '{path}' is being received")),
+                        },
+                        None => (),
+                    }
+                    continue;
+                }
+            };
+            if let Some(receiver_msg) = receiver_msg {
                 match receiver_msg {
-                    ReceiverInteractionMessage::Message(msg) => println!("{msg}"),
+                    ReceiverInteractionMessage::Message(msg) => eprintln!("{msg}"),
                     ReceiverInteractionMessage::Error(e) => {
-                        println!("An error occurred: {}", e.to_string());
+                        eprintln!("An error occurred: {}", e);
                         self.shutdown();
                     }
                     ReceiverInteractionMessage::SendFilesRequest(send_req) => {
-                        print!("Receiving {} files", send_req.num_files);
+                        max_file_name_length = send_req.max_file_name_length as usize;
+                        eprint!("Receiving {} files", send_req.num_files);
                         if send_req.num_folders > 0 {
-                            print!(" and {} folders", send_req.num_folders);
+                            eprint!(" and {} folders", send_req.num_folders);
                         }
                         if self.assumeyes {
-                            println!();
+                            eprintln!();
+                            eprintln!("{COMMAND_HINT}");
                             progress.update(send_req.num_files, send_req.max_file_name_length as usize, send_req.total_size);
                             self.receiver.send_confirm(ReceiverConfirm::ReceiveConfirm(true)).await?;
                             continue;
                         }
-                        print!(" ({})? (Y/n) ", HumanBytes(send_req.total_size).to_string());
-                        stdout().flush()?;
-                        let mut input = String::new();
-                        stdin().read_line(&mut input)?;
+                        eprint!(" ({})? (Y/n) ", HumanBytes(send_req.total_size));
+                        stderr().flush()?;
+                        let input = lines.read_line().await;
                         let input = input.trim();
                         if input.to_lowercase() == "y" || input.to_lowercase() == "yes" {
+                            eprintln!("{COMMAND_HINT}");
                             progress.update(send_req.num_files, send_req.max_file_name_length as usize, send_req.total_size);
                             self.receiver.send_confirm(ReceiverConfirm::ReceiveConfirm(true)).await?;
                         } else {
                             self.receiver.send_confirm(ReceiverConfirm::ReceiveConfirm(false)).await?;
                             self.shutdown();
-                            println!("Refuse to receive, exit...");
+                            eprintln!("Refuse to receive, exit...");
                             tokio::time::sleep(Duration::from_millis(200)).await;
                         }
                     }
+                    ReceiverInteractionMessage::Manifest(entries) => {
+                        let selected = entries.iter().filter(|entry| self.is_selected(entry)).collect::<Vec<_>>();
+                        if selected.len() < entries.len() {
+                            let num_files = selected.iter().filter(|entry| !entry.is_empty_dir).count() as u64;
+                            let total_size = selected.iter().map(|entry| entry.size).sum();
+                            let total_files = entries.iter().filter(|entry| !entry.is_empty_dir).count();
+                            eprintln!("Selected {} of {} files ({})", num_files, total_files, HumanBytes(total_size));
+                            progress.update(num_files, max_file_name_length, total_size);
+                        }
+                        let file_ids = selected.iter().map(|entry| entry.file_id).collect();
+                        self.receiver.send_confirm(ReceiverConfirm::SelectFiles(file_ids)).await?;
+                    }
                     ReceiverInteractionMessage::FileDuplication(file_duplication) => {
                         if self.assumeyes {
                             self.receiver.send_confirm(ReceiverConfirm::FileConfirm((true, file_duplication.file_id))).await?;
                             continue;
                         }
-                        print!("overwrite '{}'? (Y/n) ", file_duplication.path);
-                        stdout().flush()?;
-                        let mut input = String::new();
-                        stdin().read_line(&mut input)?;
+                        eprint!("overwrite '{}'? (Y/n) ", file_duplication.path);
+                        stderr().flush()?;
+                        let input = lines.read_line().await;
                         let input = input.trim();
                         if input.to_lowercase() == "y" || input.to_lowercase() == "yes" {
                             self.receiver.send_confirm(ReceiverConfirm::FileConfirm((true, file_duplication.file_id))).await?;
@@ -98,44 +145,87 @@ impl Receive {
                             self.receiver.send_confirm(ReceiverConfirm::FileConfirm((false, file_duplication.file_id))).await?;
                         }
                     }
+                    ReceiverInteractionMessage::BatchDuplication(batch_duplication) => {
+                        if self.assumeyes {
+                            self.receiver.send_confirm(ReceiverConfirm::BatchConfirm((true, batch_duplication.batch_id))).await?;
+                            continue;
+                        }
+                        for file_duplication in batch_duplication.files.iter() {
+                            progress.println(&format!("'{}' already exists", file_duplication.path));
+                        }
+                        eprint!("overwrite these {} files? (Y/n) ", batch_duplication.files.len());
+                        stderr().flush()?;
+                        let input = lines.read_line().await;
+                        let input = input.trim();
+                        if input.to_lowercase() == "y" || input.to_lowercase() == "yes" {
+                            self.receiver.send_confirm(ReceiverConfirm::BatchConfirm((true, batch_duplication.batch_id))).await?;
+                        } else {
+                            for file_duplication in batch_duplication.files.iter() {
+                                progress.skip(file_duplication.file_id);
+                            }
+                            self.receiver.send_confirm(ReceiverConfirm::BatchConfirm((false, batch_duplication.batch_id))).await?;
+                        }
+                    }
+                    ReceiverInteractionMessage::ExtractArchive(extract_archive) => {
+                        if self.assumeyes {
+                            self.receiver.send_confirm(ReceiverConfirm::ExtractConfirm((true, extract_archive.file_id))).await?;
+                            continue;
+                        }
+                        eprint!("extract '{}'? (Y/n) ", extract_archive.path);
+                        stderr().flush()?;
+                        let input = lines.read_line().await;
+                        let input = input.trim();
+                        let extract = input.to_lowercase() == "y" || input.to_lowercase() == "yes";
+                        self.receiver.send_confirm(ReceiverConfirm::ExtractConfirm((extract, extract_archive.file_id))).await?;
+                    }
                     ReceiverInteractionMessage::RecvNewFile(recv_new_file) => {
-                        progress.add_progress(recv_new_file.filename.as_str(), recv_new_file.file_id, recv_new_file.size);
+                        let path = Path::new(&recv_new_file.path);
+                        let relative_path = path.strip_prefix(&self.output_dir).unwrap_or(path).to_string_lossy();
+                        progress.add_progress(
+                            recv_new_file.filename.as_str(),
+                            &relative_path,
+                            recv_new_file.file_id,
+                            recv_new_file.size,
+                        );
                     }
                     ReceiverInteractionMessage::BreakPoint(break_point) => {
-                        print!(
+                        eprint!(
                             "File '{}' is {:.2}% complete. Resume transfer? (Y/n) ",
                             break_point.filename, break_point.percent
                         );
-                        stdout().flush()?;
-                        let mut input = String::new();
-                        stdin().read_line(&mut input)?;
+                        stderr().flush()?;
+                        let input = lines.read_line().await;
                         let input = input.trim();
                         if input.to_lowercase() == "y" || input.to_lowercase() == "yes" {
-                            self.receiver
-                                .send_confirm(ReceiverConfirm::BreakPointConfirm((
-                                    true,
-                                    break_point.file_id,
-                                    break_point.position,
-                                )))
-                                .await?;
+                            self.receiver.send_confirm(ReceiverConfirm::BreakPointConfirm((true, break_point.file_id))).await?;
                         } else {
-                            self.receiver
-                                .send_confirm(ReceiverConfirm::BreakPointConfirm((
-                                    false,
-                                    break_point.file_id,
-                                    break_point.position,
-                                )))
-                                .await?;
+                            self.receiver.send_confirm(ReceiverConfirm::BreakPointConfirm((false, break_point.file_id))).await?;
                         }
                     }
                     ReceiverInteractionMessage::FileProgress(fp) => {
                         progress.set_position(fp.file_id, fp.position);
                     }
                     ReceiverInteractionMessage::FileProgressFinish(file_id) => {
-                        progress.finish(file_id);
+                        progress.verified(file_id);
+                    }
+                    ReceiverInteractionMessage::FileVerifyFailed(file_id) => {
+                        progress.verify_failed(file_id);
+                    }
+                    ReceiverInteractionMessage::FileCancelled(file_id) => {
+                        progress.skip(file_id);
+                    }
+                    ReceiverInteractionMessage::Paused(true) => {
+                        progress.println(&format!("Paused, type '{RESUME_COMMAND}' and press enter to resume"));
+                    }
+                    ReceiverInteractionMessage::Paused(false) => progress.println("Resumed"),
+                    ReceiverInteractionMessage::PausedByPeer(true) => progress.println("Paused by the sender"),
+                    ReceiverInteractionMessage::PausedByPeer(false) => progress.println("Resumed by the sender"),
+                    ReceiverInteractionMessage::Text(text) => {
+                        // the only output on stdout, so the text can be piped on
+                        println!("{text}");
                     }
                     ReceiverInteractionMessage::OtherClose => {
-                        println!("The send end is interrupted. exit...");
+                        eprintln!("The send end is interrupted. exit...");
                         self.shutdown();
                     }
                     ReceiverInteractionMessage::ReceiveDone => {
@@ -148,6 +238,15 @@ impl Receive {
         Ok(())
     }
 
+    /// Whether the manifest entry passes the `--include`/`--exclude` globs.
+    fn is_selected(
+        &self,
+        entry: &ManifestEntry,
+    ) -> bool {
+        let path = entry.relative_path.replace('\\', "/");
+        self.include.as_ref().is_none_or(|include| include.is_match(&path)) && !self.exclude.as_ref().is_some_and(|exclude| exclude.is_match(&path))
+    }
+
     pub fn shutdown(&self) {
         self.receiver.shutdown();
         self.shutdown.shutdown();
@@ -157,3 +256,14 @@ impl Receive {
         self.shutdown.wait().await
     }
 }
+
+fn build_glob_set(globs: &[String]) -> Result<Option<GlobSet>> {
+    if globs.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(Glob::new(glob).map_err(|e| anyhow!("invalid glob '{glob}': {e}"))?);
+    }
+    Ok(Some(builder.build()?))
+}
@@ -1,18 +1,34 @@
-use std::{env, path::PathBuf, process, sync::Arc};
+use std::{collections::HashMap, env, path::PathBuf, process, sync::Arc};
 
 use anyhow::Result;
+use indicatif::HumanBytes;
 use tokio_stream::StreamExt;
 
-use flash_cat_common::{Shutdown, proto::ClientType, utils::gen_share_code};
-use flash_cat_core::{RelayType, SenderInteractionMessage, sender::FlashCatSender};
+use flash_cat_common::{
+    Shutdown,
+    format::HumanDuration,
+    utils::{
+        fs::{CollectOptions, FileKind},
+        gen_share_code,
+    },
+};
+use flash_cat_core::{
+    Download, RelayType, SenderInteractionMessage,
+    sender::{FlashCatSender, JOIN_WINDOW, SendOptions},
+};
 
-use crate::progress::Progress;
+use crate::{
+    input::{COMMAND_HINT, Command, Input, RESUME_COMMAND, parse_command},
+    progress::Progress,
+};
 
 #[derive(Clone)]
 pub struct Send {
     share_code: String,
     sender: FlashCatSender,
     relay: Option<String>,
+    /// Store the transfer on the relay instead of waiting for the receiver.
+    mailbox: bool,
 
     shutdown: Shutdown,
 }
@@ -20,9 +36,11 @@ pub struct Send {
 impl Send {
     pub async fn new(
         zip: bool,
-        relay: Option<String>,
         files: Vec<String>,
-        lan_broadcast: bool,
+        text: Option<String>,
+        collect_options: CollectOptions,
+        options: SendOptions,
+        mailbox: bool,
     ) -> Result<Self> {
         let files = files
             .into_iter()
@@ -34,32 +52,64 @@ impl Send {
             })
             .collect::<Vec<_>>();
         let share_code = gen_share_code();
-        let sender = FlashCatSender::new(share_code.clone(), relay.clone(), files, zip, ClientType::Cli, lan_broadcast).await?;
+        let relay = options.specify_relay.clone();
+        let sender = match text {
+            Some(text) => FlashCatSender::new_with_text(share_code.clone(), text, options)?,
+            None => FlashCatSender::new(share_code.clone(), files, zip, collect_options, options).await?,
+        };
         Ok(Self {
             share_code,
             sender,
             relay,
+            mailbox,
             shutdown: Shutdown::new(),
         })
     }
 
     pub async fn run(&self) -> Result<()> {
         let file_collector = self.sender.get_file_collector();
-        if file_collector.num_files == 1 {
-            print!("Sending {} file ", file_collector.num_files);
+        let text = self.sender.get_text();
+        if let Some(text) = text {
+            println!("Sending text ({})", HumanBytes(text.len() as u64));
         } else {
-            print!("Sending {} files ", file_collector.num_files);
-        }
-
-        if file_collector.num_folders > 0 {
             if file_collector.num_files == 1 {
-                print!("and {} folder ", file_collector.num_folders);
+                print!("Sending {} file ", file_collector.num_files);
             } else {
-                print!("and {} folders ", file_collector.num_folders);
+                print!("Sending {} files ", file_collector.num_files);
+            }
+
+            if file_collector.num_folders > 0 {
+                if file_collector.num_files == 1 {
+                    print!("and {} folder ", file_collector.num_folders);
+                } else {
+                    print!("and {} folders ", file_collector.num_folders);
+                }
             }
+            println!("({})", file_collector.total_size_to_human_readable());
         }
-        println!("({})", file_collector.total_size_to_human_readable());
+        let max_receivers = self.sender.max_receivers();
+        let broadcast = max_receivers > 1;
         println!("Share code is: {}", self.share_code);
+        if broadcast {
+            println!(
+                "Up to {max_receivers} receivers may join, within {} of the first",
+                HumanDuration(JOIN_WINDOW)
+            );
+        }
+        let keep_alive = self.sender.keep_alive();
+        if let Some(keep_alive) = keep_alive {
+            match keep_alive.max_downloads {
+                Some(max_downloads) => print!("The share stays open for {max_downloads} downloads"),
+                None => print!("The share stays open until closed"),
+            }
+            match keep_alive.expires_in {
+                Some(expires_in) => println!(", for {} at most", HumanDuration(expires_in)),
+                None => println!(),
+            }
+        }
+        if self.mailbox {
+            println!("The transfer is stored on the relay, the receiver may collect it later");
+        }
         println!("On the other computer run:");
         println!();
         if let Some(relay) = &self.relay {
@@ -67,21 +117,69 @@ impl Send {
         } else {
             println!("flash-cat recv {}", self.share_code);
         }
-
-        let mut progress = Progress::new(
-            file_collector.num_files,
-            file_collector.max_file_name_length,
-            file_collector.total_size,
-        );
-
-        for file in file_collector.files.iter() {
-            progress.register_file(&file.name, file.file_id, file.size);
+        println!();
+        // stdin carries the data, the transfer can't be controlled from it, and a text is sent at once
+        let from_stdin = file_collector.files.iter().any(|file| file.kind == FileKind::Stream);
+        if !from_stdin && text.is_none() {
+            println!("{COMMAND_HINT}");
         }
 
-        match Arc::new(self.sender.clone()).start().await {
+        // a bar for every receiver of a broadcast session, numbered as the files would be
+        let mut progress = if broadcast {
+            let mut progress = Progress::new(
+                max_receivers as u64,
+                receiver_name(max_receivers).len(),
+                file_collector.total_size * max_receivers as u64,
+            );
+            for receiver in 1..=max_receivers {
+                let name = receiver_name(receiver);
+                progress.register_file(&name, &name, receiver as u64, file_collector.total_size);
+            }
+            progress
+        } else {
+            self.file_progress()
+        };
+        let file_sizes: HashMap<u64, u64> = file_collector.files.iter().map(|file| (file.file_id, file.size)).collect();
+        let mut receiver_positions = ReceiverPositions::default();
+        // what the receivers of a kept alive share downloaded
+        let mut downloads: Vec<Download> = Vec::new();
+
+        let sender = Arc::new(self.sender.clone());
+        let stream = if self.mailbox {
+            sender.deposit().await
+        } else {
+            sender.start().await
+        };
+        match stream {
             Ok(mut stream) => {
+                let mut lines = if from_stdin {
+                    Input::closed()
+                } else {
+                    Input::spawn()
+                };
                 while !self.shutdown.is_terminated() {
-                    if let Some(sender_msg) = stream.next().await {
+                    let sender_msg = tokio::select! {
+                        sender_msg = stream.next() => sender_msg,
+                        Some(line) = lines.next() => {
+                            match parse_command(&line) {
+                                Some(Command::Pause) => self.sender.pause(),
+                                Some(Command::Resume) => self.sender.resume(),
+                                Some(Command::Cancel(path)) if broadcast => {
+                                    match file_collector.files.iter().find(|file| file.relative_path == path) {
+                                        Some(file) => self.sender.cancel_file(file.file_id),
+                                        None => progress.println(&format!("No file '{path}' is being sent")),
+                                    }
+                                }
+                                Some(Command::Cancel(path)) => match progress.file_id(path) {
+                                    Some(file_id) => self.sender.cancel_file(file_id),
+                                    None => progress.println(&format!("No file '{path}' is being sent")),
+                                },
+                                None => (),
+                            }
+                            continue;
+                        }
+                    };
+                    if let Some(sender_msg) = sender_msg {
                         match sender_msg {
                             SenderInteractionMessage::Message(msg) => progress.println(&msg),
                             SenderInteractionMessage::Error(e) => {
@@ -108,6 +206,50 @@ impl Send {
                             SenderInteractionMessage::FileProgressFinish(file_id) => {
                                 progress.finish(file_id);
                             }
+                            SenderInteractionMessage::FileCancelled(file_id) => {
+                                progress.skip(file_id);
+                            }
+                            SenderInteractionMessage::Stats(stats) => {
+                                progress.set_stats(&format!("chunk {}", HumanBytes(stats.chunk_size as u64)));
+                            }
+                            SenderInteractionMessage::Paused(true) => {
+                                progress.println(&format!("Paused, type '{RESUME_COMMAND}' and press enter to resume"));
+                            }
+                            SenderInteractionMessage::Paused(false) => progress.println("Resumed"),
+                            SenderInteractionMessage::PausedByPeer(true) => progress.println("Paused by the receiver"),
+                            SenderInteractionMessage::PausedByPeer(false) => progress.println("Resumed by the receiver"),
+                            SenderInteractionMessage::ReceiverJoined(receiver) => {
+                                if keep_alive.is_some() {
+                                    // the bars of the receiver before are done with
+                                    progress = self.file_progress();
+                                }
+                                progress.println(&format!("{} joined", receiver_name(receiver)));
+                            }
+                            SenderInteractionMessage::ReceiverProgress((receiver, file_progress)) => {
+                                let position = receiver_positions.set(receiver, file_progress.file_id, file_progress.position);
+                                progress.set_position(receiver as u64, position);
+                            }
+                            SenderInteractionMessage::ReceiverFileFinished((receiver, file_id))
+                            | SenderInteractionMessage::ReceiverFileSkipped((receiver, file_id)) => {
+                                let size = file_sizes.get(&file_id).copied().unwrap_or(0);
+                                let position = receiver_positions.set(receiver, file_id, size);
+                                progress.set_position(receiver as u64, position);
+                            }
+                            SenderInteractionMessage::ReceiverLeft(receiver) => {
+                                progress.println(&format!("{} left", receiver_name(receiver)));
+                                if broadcast {
+                                    progress.skip(receiver as u64);
+                                }
+                            }
+                            SenderInteractionMessage::ReceiverCompleted(receiver) => progress.finish(receiver as u64),
+                            SenderInteractionMessage::Downloaded(download) => {
+                                progress.println(&format!("{} done", download_summary(&download)));
+                                downloads.push(download);
+                            }
+                            SenderInteractionMessage::Expired => {
+                                progress.println("The share expired. exit...");
+                                self.shutdown();
+                            }
                             SenderInteractionMessage::OtherClose => {
                                 progress.println("The receive end is interrupted. exit...");
                                 self.shutdown();
@@ -129,9 +271,34 @@ impl Send {
             }
         }
 
+        if keep_alive.is_some() {
+            println!();
+            match downloads.len() {
+                1 => println!("1 download:"),
+                n => println!("{n} downloads:"),
+            }
+            for download in downloads.iter() {
+                println!("  {}", download_summary(download));
+            }
+        }
+
         Ok(())
     }
 
+    /// A bar for every file, registered to be shown once it is sent.
+    fn file_progress(&self) -> Progress {
+        let file_collector = self.sender.get_file_collector();
+        let mut progress = Progress::new(
+            file_collector.num_files,
+            file_collector.max_file_name_length,
+            file_collector.total_size,
+        );
+        for file in file_collector.files.iter() {
+            progress.register_file(&file.name, &file.relative_path, file.file_id, file.size);
+        }
+        progress
+    }
+
     pub fn shutdown(&self) {
         self.sender.shutdown();
         self.shutdown.shutdown();
@@ -141,3 +308,39 @@ impl Send {
         self.shutdown.wait().await
     }
 }
+
+fn receiver_name(receiver: u32) -> String {
+    format!("Receiver {receiver}")
+}
+
+fn download_summary(download: &Download) -> String {
+    if download.text {
+        return format!("{}: text ({})", receiver_name(download.receiver), HumanBytes(download.size));
+    }
+    match download.num_files {
+        1 => format!("{}: 1 file ({})", receiver_name(download.receiver), HumanBytes(download.size)),
+        n => format!(
+            "{}: {n} files ({})",
+            receiver_name(download.receiver),
+            HumanBytes(download.size)
+        ),
+    }
+}
+
+/// Position of every file sent to each receiver of a broadcast session.
+#[derive(Default)]
+struct ReceiverPositions(HashMap<u32, HashMap<u64, u64>>);
+
+impl ReceiverPositions {
+    /// Set the position of a file sent to `receiver`, returns the bytes sent to it in all.
+    fn set(
+        &mut self,
+        receiver: u32,
+        file_id: u64,
+        position: u64,
+    ) -> u64 {
+        let positions = self.0.entry(receiver).or_default();
+        positions.insert(file_id, position);
+        positions.values().sum()
+    }
+}
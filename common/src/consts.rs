@@ -6,9 +6,6 @@ pub const DEFAULT_RELAY_PORT: u16 = 20018;
 /// Domain for pubilc relay.
 pub const PUBLIC_RELAY: &'static str = "flashcat.yunisdu.com";
 
-/// The default cryptographic private key | 256-bit (32 bytes) key.
-pub const DEFAULT_SECRET_KEY: &'static str = "FLASH-CAT.UYfvV4jQOW.OtUuM38b0iD";
-
 /// The default http2 keepalive interval.
 pub const DEFAULT_HTTP2_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
 
@@ -21,6 +18,21 @@ pub const DEFAULT_TCP_KEEPALIVE: Duration = Duration::from_secs(30);
 /// Send buffer size: 256KiB.
 pub const SEND_BUFF_SIZE: usize = 256 * 1024;
 
+/// Smallest chunk size the sender adapts down to: 64KiB.
+pub const MIN_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Largest chunk size the sender adapts up to, well below the 4MiB gRPC message limit: 2MiB.
+pub const MAX_CHUNK_SIZE: usize = 2 * 1024 * 1024;
+
+/// Maximum size of a text sent instead of files: 64KiB.
+pub const MAX_TEXT_SIZE: usize = 64 * 1024;
+
+/// Maximum number of parallel data streams of a session, besides the control stream.
+pub const MAX_DATA_STREAMS: u32 = 8;
+
+/// Maximum number of receivers of a broadcast session, the sender opens a relay channel for each.
+pub const MAX_RECEIVERS: u32 = 32;
+
 /// Max reconnect retries.
 pub const MAX_RECONNECT_RETRIES: u32 = 5;
 
@@ -1,15 +1,95 @@
-use std::fmt::Debug;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
 
 use aes_gcm::{
-    aead::{Aead, generic_array::GenericArray},
+    aead::{Aead, Payload, generic_array::GenericArray},
     {Aes256Gcm, KeyInit},
 };
 use anyhow::{Result, anyhow, bail};
-use bytes::Bytes;
+use argon2::{Algorithm, Argon2, Params, Version};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use hex::encode_upper;
+use hkdf::Hkdf;
+use prost::Message;
 use sha2::{Digest, Sha256};
 
-use crate::consts;
+use super::pake::{KeyExchange, Role};
+
+/// Length of the random nonce prefixed to every ciphertext.
+const NONCE_LEN: usize = 12;
+
+/// Length of the random session salt at the head of every chunk nonce.
+const CHUNK_SALT_LEN: usize = 4;
+
+const FILE_KEY_INFO: &[u8] = b"flash-cat file key";
+
+const MAILBOX_KEY_INFO: &[u8] = b"flash-cat mailbox key";
+
+/// Length of the salt the key of a transfer stored on the relay is derived with.
+const MAILBOX_SALT_LEN: usize = 16;
+
+/// Memory in KiB, passes and lanes of Argon2id deriving the key of a stored transfer.
+const MAILBOX_KDF_COSTS: (u32, u32, u32) = (64 * 1024, 3, 1);
+
+/// Highest costs accepted from the relay, so it can't make a receiver run out of memory.
+const MAX_MAILBOX_KDF_COSTS: (u32, u32, u32) = (1024 * 1024, 16, 8);
+
+/// How the key of a transfer stored on the relay is derived from the share code: Argon2id
+/// with the costs and salt stored along with the transfer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailboxKdf {
+    memory: u32,
+    passes: u32,
+    lanes: u32,
+    salt: [u8; MAILBOX_SALT_LEN],
+}
+
+impl MailboxKdf {
+    /// Default costs with a random salt.
+    pub fn generate() -> Self {
+        let (memory, passes, lanes) = MAILBOX_KDF_COSTS;
+        let mut salt = [0u8; MAILBOX_SALT_LEN];
+        rand::fill(&mut salt);
+        Self {
+            memory,
+            passes,
+            lanes,
+            salt,
+        }
+    }
+
+    /// The costs in big endian followed by the salt, as stored on the relay.
+    pub fn encode(&self) -> Bytes {
+        let mut buf = BytesMut::with_capacity(12 + MAILBOX_SALT_LEN);
+        buf.put_u32(self.memory);
+        buf.put_u32(self.passes);
+        buf.put_u32(self.lanes);
+        buf.put_slice(&self.salt);
+        buf.freeze()
+    }
+
+    pub fn decode(mut data: &[u8]) -> Result<Self> {
+        if data.len() != 12 + MAILBOX_SALT_LEN {
+            bail!("invalid key parameters of the stored transfer");
+        }
+        let (memory, passes, lanes) = (data.get_u32(), data.get_u32(), data.get_u32());
+        let (max_memory, max_passes, max_lanes) = MAX_MAILBOX_KDF_COSTS;
+        if memory > max_memory || passes > max_passes || lanes > max_lanes {
+            bail!("key parameters of the stored transfer are too costly");
+        }
+        let mut salt = [0u8; MAILBOX_SALT_LEN];
+        salt.copy_from_slice(data);
+        Ok(Self {
+            memory,
+            passes,
+            lanes,
+            salt,
+        })
+    }
+}
 
 #[derive(Clone)]
 pub struct CustomAes256Gcm(Aes256Gcm);
@@ -17,7 +97,6 @@ pub struct CustomAes256Gcm(Aes256Gcm);
 #[derive(Debug, Clone)]
 pub struct Encryptor {
     share_code: String,
-    cipher: CustomAes256Gcm,
 }
 
 impl Encryptor {
@@ -27,24 +106,32 @@ impl Encryptor {
         }
         Ok(Self {
             share_code,
-            cipher: CustomAes256Gcm(aes_gcm::Aes256Gcm::new(consts::DEFAULT_SECRET_KEY.as_bytes().into())),
         })
     }
 
-    pub fn encrypt(
+    /// Start a key exchange keyed by the share code, the session key is derived from it.
+    pub fn key_exchange(
         &self,
-        plaintext: &[u8],
-    ) -> Result<Vec<u8>> {
-        let ciphertext = self.cipher.0.encrypt(GenericArray::from_slice(self.share_code.as_bytes()), plaintext).map_err(|op| anyhow!(op.to_string()))?;
-        Ok(ciphertext)
+        role: Role,
+    ) -> KeyExchange {
+        KeyExchange::new(&self.share_code, &self.encrypt_share_code_bytes(), role)
     }
 
-    pub fn decrypt(
+    /// Cipher of a transfer stored on the relay, there is no peer to exchange keys with so
+    /// the key is derived from the share code by `kdf`. Memory-hard, as the relay holds all it
+    /// takes to guess the share code offline; run it off the async runtime.
+    pub fn mailbox_cipher(
         &self,
-        ciphertext: &[u8],
-    ) -> Result<Vec<u8>> {
-        let plaintext = self.cipher.0.decrypt(GenericArray::from_slice(self.share_code.as_bytes()), ciphertext).map_err(|op| anyhow!(op.to_string()))?;
-        Ok(plaintext)
+        kdf: &MailboxKdf,
+    ) -> Result<SessionCipher> {
+        let params = Params::new(kdf.memory, kdf.passes, kdf.lanes, Some(32)).map_err(|e| anyhow!(e.to_string()))?;
+        let mut stretched = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(self.share_code.as_bytes(), &kdf.salt, &mut stretched)
+            .map_err(|e| anyhow!(e.to_string()))?;
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, &stretched).expand(MAILBOX_KEY_INFO, &mut key).map_err(|e| anyhow!(e.to_string()))?;
+        Ok(SessionCipher::new(&key))
     }
 
     pub fn get_share_code(&self) -> String {
@@ -66,6 +153,215 @@ impl Encryptor {
     }
 }
 
+/// AES-256-GCM with the per-session key derived from the key exchange.
+#[derive(Debug, Clone)]
+pub struct SessionCipher {
+    key: [u8; 32],
+    cipher: CustomAes256Gcm,
+    chunk_salt: [u8; CHUNK_SALT_LEN],
+    chunk_counters: Arc<Mutex<HashMap<u64, u64>>>,
+}
+
+impl SessionCipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        let mut chunk_salt = [0u8; CHUNK_SALT_LEN];
+        rand::fill(&mut chunk_salt);
+        Self {
+            key: *key,
+            cipher: CustomAes256Gcm(Aes256Gcm::new(key.into())),
+            chunk_salt,
+            chunk_counters: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Cipher for the data chunks of one file, keyed by a subkey of the session key.
+    pub fn file_cipher(
+        &self,
+        file_id: u64,
+    ) -> Result<FileCipher> {
+        let mut info = FILE_KEY_INFO.to_vec();
+        info.extend_from_slice(&file_id.to_be_bytes());
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, &self.key).expand(&info, &mut key).map_err(|e| anyhow!(e.to_string()))?;
+        Ok(FileCipher {
+            file_id,
+            cipher: CustomAes256Gcm(Aes256Gcm::new((&key).into())),
+        })
+    }
+
+    /// Next chunk nonce of a file: the session salt followed by a per-file counter,
+    /// so a nonce is never reused under the same file key, not even after a resume.
+    pub fn next_chunk_nonce(
+        &self,
+        file_id: u64,
+    ) -> Result<[u8; NONCE_LEN]> {
+        let mut counters = self.chunk_counters.lock().map_err(|_| anyhow!("chunk counters poisoned"))?;
+        let counter = counters.entry(file_id).or_insert(0);
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce[..CHUNK_SALT_LEN].copy_from_slice(&self.chunk_salt);
+        nonce[CHUNK_SALT_LEN..].copy_from_slice(&counter.to_be_bytes());
+        *counter = counter.checked_add(1).ok_or_else(|| anyhow!("chunk counter exhausted"))?;
+        Ok(nonce)
+    }
+
+    /// Encrypt with a random nonce, the nonce is prefixed to the ciphertext.
+    pub fn encrypt(
+        &self,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>> {
+        self.encrypt_with_aad(plaintext, &[])
+    }
+
+    pub fn decrypt(
+        &self,
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>> {
+        self.decrypt_with_aad(ciphertext, &[])
+    }
+
+    /// Seal a protobuf message, `aad` binds it to its context (e.g. the file id).
+    pub fn seal<M: Message>(
+        &self,
+        message: &M,
+        aad: &[u8],
+    ) -> Result<Bytes> {
+        Ok(Bytes::from(self.encrypt_with_aad(&message.encode_to_vec(), aad)?))
+    }
+
+    pub fn open<M: Message + Default>(
+        &self,
+        sealed: &[u8],
+        aad: &[u8],
+    ) -> Result<M> {
+        let plaintext = self.decrypt_with_aad(sealed, aad)?;
+        Ok(M::decode(plaintext.as_slice())?)
+    }
+
+    pub fn encrypt_with_aad(
+        &self,
+        plaintext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::fill(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .0
+            .encrypt(
+                GenericArray::from_slice(&nonce),
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .map_err(|op| anyhow!(op.to_string()))?;
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    pub fn decrypt_with_aad(
+        &self,
+        ciphertext: &[u8],
+        aad: &[u8],
+    ) -> Result<Vec<u8>> {
+        if ciphertext.len() < NONCE_LEN {
+            bail!("ciphertext too short");
+        }
+        let (nonce, ciphertext) = ciphertext.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .0
+            .decrypt(
+                GenericArray::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|op| anyhow!(op.to_string()))?;
+        Ok(plaintext)
+    }
+}
+
+/// Chunk cipher of a single file, the AAD binds every chunk to its file id and byte offset.
+#[derive(Debug, Clone)]
+pub struct FileCipher {
+    file_id: u64,
+    cipher: CustomAes256Gcm,
+}
+
+impl FileCipher {
+    pub fn file_id(&self) -> u64 {
+        self.file_id
+    }
+
+    pub fn encrypt_chunk(
+        &self,
+        nonce: &[u8],
+        offset: u64,
+        compressed: bool,
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>> {
+        if nonce.len() != NONCE_LEN {
+            bail!("invalid chunk nonce length");
+        }
+        let aad = self.aad(offset, compressed);
+        let ciphertext = self
+            .cipher
+            .0
+            .encrypt(
+                GenericArray::from_slice(nonce),
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|op| anyhow!(op.to_string()))?;
+        Ok(ciphertext)
+    }
+
+    /// Decrypt a chunk expected at `offset`, fails for chunks of another file or position, or
+    /// with the `compressed` flag flipped.
+    pub fn decrypt_chunk(
+        &self,
+        nonce: &[u8],
+        offset: u64,
+        compressed: bool,
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>> {
+        if nonce.len() != NONCE_LEN {
+            bail!("invalid chunk nonce length");
+        }
+        let aad = self.aad(offset, compressed);
+        let plaintext = self
+            .cipher
+            .0
+            .decrypt(
+                GenericArray::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|op| anyhow!(op.to_string()))?;
+        Ok(plaintext)
+    }
+
+    fn aad(
+        &self,
+        offset: u64,
+        compressed: bool,
+    ) -> [u8; 17] {
+        let mut aad = [0u8; 17];
+        aad[..8].copy_from_slice(&self.file_id.to_be_bytes());
+        aad[8..16].copy_from_slice(&offset.to_be_bytes());
+        aad[16] = compressed as u8;
+        aad
+    }
+}
+
 impl Debug for CustomAes256Gcm {
     fn fmt(
         &self,
@@ -79,25 +375,112 @@ impl Debug for CustomAes256Gcm {
 mod test {
     use anyhow::{Result, bail};
 
-    use crate::utils::gen_share_code;
+    use crate::{crypt::pake::Role, utils::gen_share_code};
 
-    use super::Encryptor;
+    use super::{Encryptor, MailboxKdf, SessionCipher};
 
     #[test]
     fn encryptor_test() -> Result<()> {
         let plaintext = b"Hello, Bob! This is a secret message.";
 
         let share_code = gen_share_code();
-        let encryptor = Encryptor::new(share_code.clone())?;
-        let encrypted_text = encryptor.encrypt(plaintext)?;
+        let sender = Encryptor::new(share_code.clone())?.key_exchange(Role::Sender);
+        let receiver = Encryptor::new(share_code.clone())?.key_exchange(Role::Receiver);
+
+        let sender_key = sender.finish(&receiver.public_share())?;
+        let receiver_key = receiver.finish(&sender.public_share())?;
+        sender_key.verify_peer(&receiver_key.confirmation())?;
+        receiver_key.verify_peer(&sender_key.confirmation())?;
+
+        let encrypted_text = sender_key.cipher().encrypt(plaintext)?;
         println!("encrypted_text len: {}", encrypted_text.len());
 
-        let encryptor = Encryptor::new(share_code.clone())?;
-        let decrypted_text = match encryptor.decrypt(&encrypted_text) {
+        let decrypted_text = match receiver_key.cipher().decrypt(&encrypted_text) {
             Ok(decrypted_text) => decrypted_text,
             Err(err) => bail!(err.to_string()),
         };
-        println!("Decrypted Text: {:?}", String::from_utf8_lossy(&decrypted_text));
+        assert_eq!(decrypted_text, plaintext);
+        Ok(())
+    }
+
+    #[test]
+    fn mailbox_cipher_test() -> Result<()> {
+        let plaintext = b"Stored for later.";
+        let share_code = gen_share_code();
+        let kdf = MailboxKdf {
+            memory: 64,
+            passes: 1,
+            lanes: 1,
+            salt: [7u8; 16],
+        };
+
+        let sealed = Encryptor::new(share_code.clone())?.mailbox_cipher(&kdf)?.encrypt(plaintext)?;
+        let opened = Encryptor::new(share_code.clone())?.mailbox_cipher(&MailboxKdf::decode(&kdf.encode())?)?.decrypt(&sealed)?;
+        assert_eq!(opened, plaintext);
+        let other_salt = MailboxKdf {
+            salt: [8u8; 16],
+            ..kdf.clone()
+        };
+        assert!(Encryptor::new(share_code.clone())?.mailbox_cipher(&other_salt)?.decrypt(&sealed).is_err());
+        let other_costs = MailboxKdf {
+            passes: 2,
+            ..kdf
+        };
+        assert!(Encryptor::new(share_code)?.mailbox_cipher(&other_costs)?.decrypt(&sealed).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn mailbox_kdf_decode_rejects_costly_params() {
+        let mut costly = MailboxKdf::generate();
+        costly.memory = 1 << 30;
+        assert!(MailboxKdf::decode(&costly.encode()).is_err());
+        assert!(MailboxKdf::decode(&costly.encode()[..12]).is_err());
+        assert_eq!(
+            MailboxKdf::decode(&MailboxKdf::generate().encode()).map(|kdf| kdf.memory).ok(),
+            Some(64 * 1024)
+        );
+    }
+
+    #[test]
+    fn key_exchange_wrong_share_code() -> Result<()> {
+        let share_code = gen_share_code();
+        let sender = Encryptor::new(share_code.clone())?.key_exchange(Role::Sender);
+        // same session id, different share code
+        let receiver = super::KeyExchange::new(
+            &gen_share_code(),
+            &Encryptor::new(share_code)?.encrypt_share_code_bytes(),
+            Role::Receiver,
+        );
+
+        let sender_key = sender.finish(&receiver.public_share())?;
+        let receiver_key = receiver.finish(&sender.public_share())?;
+        assert!(sender_key.verify_peer(&receiver_key.confirmation()).is_err());
+        assert!(receiver_key.verify_peer(&sender_key.confirmation()).is_err());
+        assert!(receiver_key.cipher().decrypt(&sender_key.cipher().encrypt(b"secret")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn file_chunk_test() -> Result<()> {
+        let sender = SessionCipher::new(&[7u8; 32]);
+        let receiver = SessionCipher::new(&[7u8; 32]);
+
+        let first_nonce = sender.next_chunk_nonce(1)?;
+        let second_nonce = sender.next_chunk_nonce(1)?;
+        assert_ne!(first_nonce, second_nonce);
+
+        let chunk = sender.file_cipher(1)?.encrypt_chunk(&second_nonce, 4096, false, b"chunk")?;
+        assert_eq!(
+            receiver.file_cipher(1)?.decrypt_chunk(&second_nonce, 4096, false, &chunk)?,
+            b"chunk"
+        );
+        // reordered or replayed at another offset
+        assert!(receiver.file_cipher(1)?.decrypt_chunk(&second_nonce, 0, false, &chunk).is_err());
+        // cross-file
+        assert!(receiver.file_cipher(2)?.decrypt_chunk(&second_nonce, 4096, false, &chunk).is_err());
+        // compression flag flipped
+        assert!(receiver.file_cipher(1)?.decrypt_chunk(&second_nonce, 4096, true, &chunk).is_err());
         Ok(())
     }
 }
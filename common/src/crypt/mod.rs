@@ -1 +1,2 @@
 pub mod encryptor;
+pub mod pake;
@@ -0,0 +1,165 @@
+//! Password authenticated key exchange keyed by the share code.
+//!
+//! This is CPace over ristretto255: both peers derive a secret generator from the
+//! share code, exchange ephemeral public shares through the relay and end up with the
+//! same session key only if they used the same share code. The relay only ever sees
+//! the public shares and the key confirmation tags, neither of which can be used to
+//! recover the share code or the session key.
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use curve25519_dalek::{
+    ristretto::{CompressedRistretto, RistrettoPoint},
+    scalar::Scalar,
+    traits::IsIdentity,
+};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+
+use super::encryptor::SessionCipher;
+
+const DSI: &[u8] = b"FLASH-CAT-CPace-Ristretto255";
+const ISK_DSI: &[u8] = b"FLASH-CAT-CPace-Ristretto255_ISK";
+const SESSION_KEY_INFO: &[u8] = b"flash-cat session key";
+const SENDER_CONFIRM_INFO: &[u8] = b"flash-cat sender confirm";
+const RECEIVER_CONFIRM_INFO: &[u8] = b"flash-cat receiver confirm";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Which side of the transfer is running the key exchange.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Role {
+    Sender,
+    Receiver,
+}
+
+/// One side of the key exchange, holding the ephemeral secret until the peer share arrives.
+pub struct KeyExchange {
+    role: Role,
+    sid: Vec<u8>,
+    secret: Scalar,
+    public_share: [u8; 32],
+}
+
+impl KeyExchange {
+    /// Start a new key exchange. `sid` binds the exchange to the session, the
+    /// hashed share code is used for that.
+    pub fn new(
+        share_code: &str,
+        sid: &[u8],
+        role: Role,
+    ) -> Self {
+        let generator = generator(share_code.as_bytes(), sid);
+        let mut wide = [0u8; 64];
+        rand::fill(&mut wide);
+        let secret = Scalar::from_bytes_mod_order_wide(&wide);
+        let public_share = (generator * secret).compress().to_bytes();
+        Self {
+            role,
+            sid: sid.to_vec(),
+            secret,
+            public_share,
+        }
+    }
+
+    pub fn public_share(&self) -> Bytes {
+        Bytes::copy_from_slice(&self.public_share)
+    }
+
+    /// Combine our secret with the peer share and derive the session keys.
+    pub fn finish(
+        &self,
+        peer_share: &[u8],
+    ) -> Result<SessionKey> {
+        let peer_share: [u8; 32] = peer_share.try_into().map_err(|_| anyhow!("invalid key exchange share length"))?;
+        let peer_point = CompressedRistretto(peer_share).decompress().ok_or_else(|| anyhow!("invalid key exchange share"))?;
+        if peer_point.is_identity() {
+            bail!("invalid key exchange share");
+        }
+        let shared = peer_point * self.secret;
+        if shared.is_identity() {
+            bail!("invalid key exchange share");
+        }
+
+        // transcript is always ordered (sender share, receiver share)
+        let (sender_share, receiver_share) = match self.role {
+            Role::Sender => (self.public_share, peer_share),
+            Role::Receiver => (peer_share, self.public_share),
+        };
+
+        let mut hasher = Sha512::new();
+        for part in [ISK_DSI, &self.sid, shared.compress().as_bytes(), &sender_share, &receiver_share] {
+            hasher.update((part.len() as u64).to_be_bytes());
+            hasher.update(part);
+        }
+        let isk = hasher.finalize();
+
+        let hkdf = Hkdf::<Sha256>::new(Some(&self.sid), &isk);
+        let mut key = [0u8; 32];
+        let mut sender_confirm_key = [0u8; 32];
+        let mut receiver_confirm_key = [0u8; 32];
+        hkdf.expand(SESSION_KEY_INFO, &mut key).map_err(|e| anyhow!(e.to_string()))?;
+        hkdf.expand(SENDER_CONFIRM_INFO, &mut sender_confirm_key).map_err(|e| anyhow!(e.to_string()))?;
+        hkdf.expand(RECEIVER_CONFIRM_INFO, &mut receiver_confirm_key).map_err(|e| anyhow!(e.to_string()))?;
+
+        let mut transcript = Vec::with_capacity(64);
+        transcript.extend_from_slice(&sender_share);
+        transcript.extend_from_slice(&receiver_share);
+
+        let (local_confirm_key, peer_confirm_key) = match self.role {
+            Role::Sender => (sender_confirm_key, receiver_confirm_key),
+            Role::Receiver => (receiver_confirm_key, sender_confirm_key),
+        };
+
+        Ok(SessionKey {
+            key,
+            local_confirm_key,
+            peer_confirm_key,
+            transcript,
+        })
+    }
+}
+
+/// Result of a successful key exchange.
+pub struct SessionKey {
+    key: [u8; 32],
+    local_confirm_key: [u8; 32],
+    peer_confirm_key: [u8; 32],
+    transcript: Vec<u8>,
+}
+
+impl SessionKey {
+    /// Key confirmation tag to send to the peer.
+    pub fn confirmation(&self) -> Bytes {
+        let mut mac = HmacSha256::new_from_slice(&self.local_confirm_key).expect("HMAC can take key of any size");
+        mac.update(&self.transcript);
+        Bytes::copy_from_slice(&mac.finalize().into_bytes())
+    }
+
+    /// Verify the peer key confirmation tag, fails if the peer used another share code.
+    pub fn verify_peer(
+        &self,
+        confirm: &[u8],
+    ) -> Result<()> {
+        let mut mac = HmacSha256::new_from_slice(&self.peer_confirm_key).expect("HMAC can take key of any size");
+        mac.update(&self.transcript);
+        mac.verify_slice(confirm).map_err(|_| anyhow!("key confirmation failed"))
+    }
+
+    pub fn cipher(&self) -> SessionCipher {
+        SessionCipher::new(&self.key)
+    }
+}
+
+fn generator(
+    password: &[u8],
+    sid: &[u8],
+) -> RistrettoPoint {
+    let mut hasher = Sha512::new();
+    for part in [DSI, password, sid] {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    RistrettoPoint::from_uniform_bytes(&hasher.finalize().into())
+}
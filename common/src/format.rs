@@ -1,5 +1,7 @@
 use std::{fmt, time::Duration};
 
+use anyhow::{Result, bail};
+
 const MILLISECOND: Duration = Duration::from_millis(1);
 const SECOND: Duration = Duration::from_secs(1);
 const MINUTE: Duration = Duration::from_secs(60);
@@ -100,3 +102,55 @@ impl fmt::Display for HumanDuration {
         }
     }
 }
+
+/// Parse a duration such as `30m`, `2h`, `1h30m` or `90`, in seconds when no unit is given.
+///
+/// The units are the short ones of [`HumanDuration`]: `y`, `w`, `d`, `h`, `m`, `s` and `ms`.
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let duration = duration.trim();
+    if let Ok(secs) = duration.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+    let mut total = Duration::ZERO;
+    let mut rest = duration;
+    while !rest.is_empty() {
+        let split = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let (value, tail) = rest.split_at(split);
+        let value: u32 = match value.parse() {
+            Ok(value) => value,
+            Err(_) => bail!("invalid duration: {duration}"),
+        };
+        let split = tail.find(|c: char| c.is_ascii_digit()).unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(split);
+        let Some(&(unit, _, _)) = UNITS.iter().find(|(_, _, alt)| *alt == unit) else {
+            bail!("unknown duration unit: {unit}");
+        };
+        total = match unit.checked_mul(value).and_then(|value| total.checked_add(value)) {
+            Some(total) => total,
+            None => bail!("duration too long: {duration}"),
+        };
+        rest = tail;
+    }
+    if total.is_zero() {
+        bail!("duration must be longer than zero");
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::parse_duration;
+
+    #[test]
+    fn parse_durations() {
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(90 * 60));
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("5x").is_err());
+    }
+}
@@ -6,7 +6,7 @@ use std::{
         atomic::{AtomicBool, Ordering},
     },
 };
-use tokio::sync::Notify;
+use tokio::sync::{Notify, watch};
 
 pub mod consts;
 pub mod crypt;
@@ -74,6 +74,66 @@ impl Default for Shutdown {
     }
 }
 
+/// Pause signal of a transfer, the clones share the same state.
+#[derive(Clone)]
+pub struct Pause {
+    inner: Arc<watch::Sender<bool>>,
+}
+
+impl Pause {
+    /// Construct a new, not paused [`Pause`] object.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    /// Pause or resume, returns whether the state changed.
+    pub fn set(
+        &self,
+        paused: bool,
+    ) -> bool {
+        self.inner.send_if_modified(|state| {
+            let changed = *state != paused;
+            *state = paused;
+            changed
+        })
+    }
+
+    /// Returns whether currently paused.
+    pub fn is_paused(&self) -> bool {
+        *self.inner.borrow()
+    }
+
+    /// Receiver notified every time the state changes.
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.inner.subscribe()
+    }
+
+    /// Wait until resumed, if currently paused.
+    pub fn resumed(&self) -> impl Future<Output = ()> + Send {
+        let mut rx = self.inner.subscribe();
+        async move {
+            let _ = rx.wait_for(|paused| !*paused).await;
+        }
+    }
+}
+
+impl Default for Pause {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for Pause {
+    fn fmt(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+    ) -> std::fmt::Result {
+        f.debug_struct("Pause").field("is_paused", &self.is_paused()).finish()
+    }
+}
+
 impl Debug for Shutdown {
     fn fmt(
         &self,
@@ -82,3 +142,48 @@ impl Debug for Shutdown {
         f.debug_struct("Shutdown").field("is_terminated", &self.inner.0.load(Ordering::Relaxed)).finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Pause;
+
+    #[test]
+    fn set_reports_changes_only() {
+        let pause = Pause::new();
+        assert!(!pause.is_paused());
+        assert!(pause.set(true));
+        assert!(!pause.set(true));
+        assert!(pause.is_paused());
+        assert!(pause.set(false));
+        assert!(!pause.is_paused());
+    }
+
+    #[tokio::test]
+    async fn subscribers_see_every_change() {
+        let pause = Pause::new();
+        let mut rx = pause.subscribe();
+        pause.set(true);
+        rx.changed().await.unwrap();
+        assert!(*rx.borrow_and_update());
+        // the same state again is no change
+        pause.set(true);
+        assert!(!rx.has_changed().unwrap());
+        pause.set(false);
+        rx.changed().await.unwrap();
+        assert!(!*rx.borrow_and_update());
+    }
+
+    #[tokio::test]
+    async fn resumed_waits_until_resumed() {
+        let pause = Pause::new();
+        tokio::time::timeout(Duration::from_millis(100), pause.resumed()).await.unwrap();
+
+        pause.set(true);
+        assert!(tokio::time::timeout(Duration::from_millis(50), pause.resumed()).await.is_err());
+        let resumed = pause.resumed();
+        pause.set(false);
+        tokio::time::timeout(Duration::from_millis(100), resumed).await.unwrap();
+    }
+}
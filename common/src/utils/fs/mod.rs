@@ -2,21 +2,31 @@
 use std::os::unix::fs::MetadataExt;
 use std::{
     cmp::max,
+    collections::{HashMap, HashSet},
     fs::{self, File},
     io::{self, Read},
     path::{Component, Path, PathBuf},
 };
 
 use anyhow::{Result, bail};
+use bytes::Bytes;
+use filetime::FileTime;
+use ignore::{Walk, WalkBuilder, overrides::OverrideBuilder};
 #[cfg(feature = "progress")]
 use indicatif::{MultiProgress, ProgressBar, ProgressState, ProgressStyle};
+use sha2::{Digest, Sha256};
+use tokio::{io::DuplexStream, task::JoinHandle};
+use tokio_util::io::SyncIoBridge;
 use walkdir::WalkDir;
 use zip::{CompressionMethod, ZipArchive, ZipWriter, write::SimpleFileOptions};
 
-use crate::Shutdown;
+use crate::{Shutdown, consts::SEND_BUFF_SIZE, proto};
 
 use super::human_bytes;
 
+/// Path standing for stdin on the sender and for stdout on the receiver.
+pub const STDIO_PATH: &str = "-";
+
 #[derive(Debug, Default, Clone)]
 pub struct FileInfo {
     pub file_id: u64,
@@ -27,6 +37,173 @@ pub struct FileInfo {
     pub mode: u32,
     pub size: u64,
     pub empty_dir: bool,
+    pub kind: FileKind,
+    pub attributes: Option<FileAttributes>,
+}
+
+/// Timestamps, ownership and extended attributes of a file, only collected when preserved.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FileAttributes {
+    pub mtime: Option<FileTime>,
+    pub atime: Option<FileTime>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub xattrs: Vec<(String, Vec<u8>)>,
+}
+
+impl FileAttributes {
+    /// Read the attributes of the path, a symbolic link is not followed unless the metadata is of its target.
+    pub fn read(
+        path: &Path,
+        metadata: &fs::Metadata,
+    ) -> Self {
+        Self {
+            mtime: Some(FileTime::from_last_modification_time(metadata)),
+            atime: Some(FileTime::from_last_access_time(metadata)),
+            #[cfg(unix)]
+            uid: Some(metadata.uid()),
+            #[cfg(not(unix))]
+            uid: None,
+            #[cfg(unix)]
+            gid: Some(metadata.gid()),
+            #[cfg(not(unix))]
+            gid: None,
+            xattrs: read_xattrs(path, metadata.file_type().is_symlink()),
+        }
+    }
+
+    /// Apply the attributes and the `mode` to the path without following a symbolic link.
+    ///
+    /// The ownership is changed on a best effort basis, as usually only root may give files away,
+    /// and so are the extended attributes, which the file system may not support.
+    pub fn apply(
+        &self,
+        path: &Path,
+        mode: Option<u32>,
+    ) -> Result<()> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            // changing the owner clears the setuid and setgid bits, the mode goes afterwards
+            if self.uid.is_some() || self.gid.is_some() {
+                let _ = std::os::unix::fs::lchown(path, self.uid, self.gid);
+            }
+            // a symbolic link has no mode of its own
+            if let Some(mode) = mode.filter(|_| !path.is_symlink()) {
+                fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+            }
+            for (name, value) in self.xattrs.iter() {
+                if let Err(e) = xattr::set(path, name, value) {
+                    log::warn!("Set extended attribute {name} of {} failed: {e}", path.display());
+                }
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+        // the times go last, as changing the other attributes may touch them
+        if let Some(mtime) = self.mtime {
+            filetime::set_symlink_file_times(path, self.atime.unwrap_or(mtime), mtime)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<FileAttributes> for proto::FileAttributes {
+    fn from(attributes: FileAttributes) -> Self {
+        let timestamp = |time: FileTime| proto::Timestamp {
+            seconds: time.unix_seconds(),
+            nanos: time.nanoseconds(),
+        };
+        Self {
+            mtime: attributes.mtime.map(timestamp),
+            atime: attributes.atime.map(timestamp),
+            uid: attributes.uid,
+            gid: attributes.gid,
+            xattrs: attributes
+                .xattrs
+                .into_iter()
+                .map(|(name, value)| proto::ExtendedAttribute {
+                    name,
+                    value: Bytes::from(value),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<proto::FileAttributes> for FileAttributes {
+    fn from(attributes: proto::FileAttributes) -> Self {
+        let file_time = |timestamp: proto::Timestamp| FileTime::from_unix_time(timestamp.seconds, timestamp.nanos);
+        Self {
+            mtime: attributes.mtime.map(file_time),
+            atime: attributes.atime.map(file_time),
+            uid: attributes.uid,
+            gid: attributes.gid,
+            xattrs: attributes.xattrs.into_iter().map(|xattr| (xattr.name, xattr.value.to_vec())).collect(),
+        }
+    }
+}
+
+/// Extended attributes of the path, unreadable ones and ones with a non UTF-8 name are skipped.
+#[cfg(unix)]
+fn read_xattrs(
+    path: &Path,
+    is_symlink: bool,
+) -> Vec<(String, Vec<u8>)> {
+    let names = if is_symlink {
+        xattr::list(path)
+    } else {
+        xattr::list_deref(path)
+    };
+    let Ok(names) = names else {
+        // not supported by the file system
+        return vec![];
+    };
+    names
+        .filter_map(|name| {
+            let value = if is_symlink {
+                xattr::get(path, &name)
+            } else {
+                xattr::get_deref(path, &name)
+            };
+            Some((name.into_string().ok()?, value.ok()??))
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+fn read_xattrs(
+    _path: &Path,
+    _is_symlink: bool,
+) -> Vec<(String, Vec<u8>)> {
+    vec![]
+}
+
+/// Kind of a collected file, links are only collected when they are preserved.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum FileKind {
+    #[default]
+    Regular,
+    /// Empty directory.
+    Dir,
+    /// Symbolic link with its target as read from the link.
+    Symlink(String),
+    /// Hard link to the file of the group with the given relative path, which is collected first.
+    Hardlink(String),
+    /// Tar archive of a folder, built while it is sent so its size is unknown.
+    Archive,
+    /// Zip archive of a folder made by the sender before the transfer.
+    ZippedFolder,
+    /// Data read from stdin, its size is only known once it is read.
+    Stream,
+}
+
+impl FileKind {
+    /// Whether the file is a link and has no content of its own to send.
+    pub fn is_link(&self) -> bool {
+        matches!(self, FileKind::Symlink(_) | FileKind::Hardlink(_))
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -36,6 +213,8 @@ pub struct FileCollector {
     pub num_files: u64,
     pub num_folders: u64,
     pub max_file_name_length: usize,
+    /// Attributes of the collected directories by relative path, only when preserved.
+    pub dir_attributes: Vec<(String, FileAttributes)>,
 }
 
 impl FileCollector {
@@ -48,6 +227,7 @@ impl FileCollector {
         self.num_folders += pc.num_folders;
         self.calc_max_file_name_length(pc.max_file_name_length);
         self.files.append(&mut pc.files);
+        self.dir_attributes.append(&mut pc.dir_attributes);
     }
 
     fn add_total_size(
@@ -90,75 +270,271 @@ impl FileCollector {
     pub fn folder_count(&self) -> u64 {
         self.num_folders
     }
+
+    /// Fingerprint of the collected files, the same as long as the ids, paths and sizes are unchanged.
+    pub fn fingerprint(&self) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        for file in self.files.iter() {
+            hasher.update(file.file_id.to_be_bytes());
+            hasher.update((file.relative_path.len() as u64).to_be_bytes());
+            hasher.update(file.relative_path.as_bytes());
+            hasher.update(file.size.to_be_bytes());
+            hasher.update([file.empty_dir as u8]);
+            if let FileKind::Symlink(target) | FileKind::Hardlink(target) = &file.kind {
+                hasher.update((target.len() as u64).to_be_bytes());
+                hasher.update(target.as_bytes());
+            }
+        }
+        hasher.finalize().to_vec()
+    }
+}
+
+/// Options to filter the files collected from the paths.
+#[derive(Debug, Default, Clone)]
+pub struct CollectOptions {
+    /// Only collect the files matching one of the globs (gitignore syntax).
+    pub include: Vec<String>,
+    /// Skip the files and folders matching one of the globs (gitignore syntax).
+    pub exclude: Vec<String>,
+    /// Honor `.gitignore` and `.ignore` files, `.git` folders are skipped as well.
+    pub respect_ignore: bool,
+    /// Maximum depth to descend below each path.
+    pub max_depth: Option<usize>,
+    /// Collect symbolic and hard links as links instead of following them, special files are skipped either way.
+    pub preserve_links: bool,
+    /// Collect the timestamps, ownership and extended attributes of the files and directories.
+    pub preserve_attributes: bool,
+    /// Collect each folder path as a single tar archive streamed on the fly instead of its files.
+    pub archive_folders: bool,
 }
 
 /// Collect how many files exist in the paths, how many folders, and the total size.
-pub fn collect_files<P: AsRef<Path>>(paths: &[P]) -> FileCollector {
+pub fn collect_files<P: AsRef<Path>>(paths: &[P]) -> Result<FileCollector> {
+    collect_files_with(paths, &CollectOptions::default())
+}
+
+/// Collect the files of the paths like `collect_files`, the totals only count the files passing the options.
+pub fn collect_files_with<P: AsRef<Path>>(
+    paths: &[P],
+    options: &CollectOptions,
+) -> Result<FileCollector> {
+    for path in paths {
+        let path = path.as_ref();
+        if let Err(e) = fs::symlink_metadata(path) {
+            bail!("{}: {e}", path.to_string_lossy());
+        }
+    }
+    let walkers = paths.iter().map(|path| Ok((walker(path.as_ref(), options)?, path.as_ref().to_owned()))).collect::<Result<Vec<_>>>()?;
     let mut file_id = 1;
-    paths
+    let mut hard_links = HashMap::new();
+    // folders with a child collected, the others are sent as empty folders
+    let mut non_empty = HashSet::new();
+    let mut fc = walkers
         .into_iter()
-        .map(|path| {
-            WalkDir::new(path)
-                .follow_links(true)
-                .into_iter()
-                .filter_map(|entry| entry.ok())
-                .filter_map(|entry| match entry.metadata() {
-                    Ok(metadata) => Some((metadata, entry.path().to_owned(), path.as_ref().to_owned())),
-                    Err(_) => None,
+        .map(|(walker, root)| {
+            if options.archive_folders && root.is_dir() {
+                let mut fc = FileCollector::default();
+                let name = format!("{}.tar", root.file_name().unwrap_or("archive".as_ref()).to_string_lossy());
+                fc.calc_max_file_name_length(name.len());
+                fc.add_file(FileInfo {
+                    file_id,
+                    name: name.clone(),
+                    access_path: root.to_string_lossy().to_string(),
+                    relative_path: name,
+                    #[cfg(unix)]
+                    mode: 0o644,
+                    size: 0,
+                    empty_dir: false,
+                    kind: FileKind::Archive,
+                    attributes: None,
+                });
+                file_id += 1;
+                fc.count_num_files();
+                return anyhow::Ok(fc);
+            }
+            // an entry below the root that can't be read fails the collection like the root itself
+            walker
+                .map(|entry| {
+                    let entry = entry?;
+                    let metadata = entry.metadata()?;
+                    anyhow::Ok((metadata, entry.path().to_owned(), root.clone()))
                 })
-                .fold(FileCollector::default(), |mut fc: FileCollector, (metadata, path, root)| {
+                .try_fold(FileCollector::default(), |mut fc: FileCollector, entry| {
+                    let (metadata, path, root) = entry?;
+                    if metadata.is_file() || metadata.is_dir() || metadata.file_type().is_symlink() {
+                        if let Some(parent) = path.parent() {
+                            non_empty.insert(parent.to_owned());
+                        }
+                    }
                     let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
                     let mut root_clone = root.clone();
                     root_clone.pop();
                     let relative_path = path.strip_prefix(root_clone.as_path()).unwrap();
                     if metadata.is_file() {
                         let file_name_legnth = file_name.len();
-                        let file_size = metadata.len();
+                        let relative_path = relative_path.to_string_lossy().to_string();
+                        let kind = if options.preserve_links {
+                            hard_link_kind(&mut hard_links, &metadata, &relative_path)
+                        } else {
+                            FileKind::Regular
+                        };
+                        let attributes = options.preserve_attributes.then(|| FileAttributes::read(&path, &metadata));
+                        // a hard link shares the content of the first file of its group
+                        let file_size = if kind.is_link() {
+                            0
+                        } else {
+                            metadata.len()
+                        };
                         let file_info = FileInfo {
                             file_id,
                             name: file_name,
                             access_path: path.to_string_lossy().to_string(),
-                            relative_path: relative_path.to_string_lossy().to_string(),
+                            relative_path,
                             #[cfg(unix)]
                             mode: metadata.mode(),
                             size: file_size,
                             empty_dir: false,
+                            kind,
+                            attributes,
                         };
                         file_id += 1;
                         fc.add_file(file_info);
                         fc.calc_max_file_name_length(file_name_legnth);
                         fc.add_total_size(file_size);
                         fc.count_num_files();
-                        fc
+                        Ok(fc)
                     } else if metadata.is_dir() {
-                        if let Ok(directory) = path.as_path().read_dir() {
-                            if directory.count() == 0 {
-                                // empty directory
-                                let file_info = FileInfo {
-                                    file_id,
-                                    name: file_name,
-                                    access_path: path.to_string_lossy().to_string(),
-                                    relative_path: relative_path.to_string_lossy().to_string(),
-                                    #[cfg(unix)]
-                                    mode: metadata.mode(),
-                                    size: 0,
-                                    empty_dir: true,
-                                };
-                                file_id += 1;
-                                fc.add_file(file_info);
-                            }
+                        // dropped at the end unless the directory turns out to be empty
+                        let file_info = FileInfo {
+                            file_id,
+                            name: file_name,
+                            access_path: path.to_string_lossy().to_string(),
+                            relative_path: relative_path.to_string_lossy().to_string(),
+                            #[cfg(unix)]
+                            mode: metadata.mode(),
+                            size: 0,
+                            empty_dir: true,
+                            kind: FileKind::Dir,
+                            attributes: None,
+                        };
+                        file_id += 1;
+                        fc.add_file(file_info);
+                        if options.preserve_attributes {
+                            // applied by the receiver once the children are written
+                            fc.dir_attributes.push((
+                                relative_path.to_string_lossy().to_string(),
+                                FileAttributes::read(&path, &metadata),
+                            ));
                         }
                         fc.count_num_folders();
-                        fc
+                        Ok(fc)
+                    } else if metadata.file_type().is_symlink() {
+                        // only seen when links are preserved, otherwise the target is walked
+                        let target = fs::read_link(&path).map_err(|e| anyhow::anyhow!("{}: {e}", path.to_string_lossy()))?;
+                        let file_name_legnth = file_name.len();
+                        let file_info = FileInfo {
+                            file_id,
+                            name: file_name,
+                            access_path: path.to_string_lossy().to_string(),
+                            relative_path: relative_path.to_string_lossy().to_string(),
+                            #[cfg(unix)]
+                            mode: metadata.mode(),
+                            size: 0,
+                            empty_dir: false,
+                            kind: FileKind::Symlink(target.to_string_lossy().to_string()),
+                            attributes: options.preserve_attributes.then(|| FileAttributes::read(&path, &metadata)),
+                        };
+                        file_id += 1;
+                        fc.add_file(file_info);
+                        fc.calc_max_file_name_length(file_name_legnth);
+                        fc.count_num_files();
+                        Ok(fc)
                     } else {
-                        fc
+                        Ok(fc)
                     }
                 })
         })
-        .fold(FileCollector::default(), |mut fc, cur| {
-            fc.acc(cur);
-            fc
-        })
+        .try_fold(FileCollector::default(), |mut fc, cur| {
+            fc.acc(cur?);
+            anyhow::Ok(fc)
+        })?;
+    // only the children passing the options count, a folder whose children are all left out is empty
+    fc.files.retain(|file| !file.empty_dir || !non_empty.contains(Path::new(&file.access_path)));
+    for (file_id, file) in fc.files.iter_mut().enumerate() {
+        file.file_id = file_id as u64 + 1;
+    }
+    Ok(fc)
+}
+
+/// Walker of a path to collect, the path itself is always yielded.
+fn walker(
+    path: &Path,
+    options: &CollectOptions,
+) -> Result<Walk> {
+    let mut overrides = OverrideBuilder::new(path);
+    for glob in options.include.iter() {
+        overrides.add(glob)?;
+    }
+    for glob in options.exclude.iter() {
+        overrides.add(&format!("!{glob}"))?;
+    }
+
+    let mut builder = WalkBuilder::new(path);
+    builder.standard_filters(false).follow_links(!options.preserve_links).max_depth(options.max_depth).overrides(overrides.build()?);
+    if options.respect_ignore {
+        builder.git_ignore(true).ignore(true).parents(true).require_git(false).filter_entry(|entry| entry.file_name() != ".git");
+    }
+    Ok(builder.build())
+}
+
+/// Kind of a file with more than one hard link, the first file seen of a group is regular.
+#[cfg(unix)]
+fn hard_link_kind(
+    groups: &mut HashMap<(u64, u64), String>,
+    metadata: &fs::Metadata,
+    relative_path: &str,
+) -> FileKind {
+    use std::collections::hash_map::Entry;
+
+    if metadata.nlink() <= 1 {
+        return FileKind::Regular;
+    }
+    match groups.entry((metadata.dev(), metadata.ino())) {
+        Entry::Occupied(first) => FileKind::Hardlink(first.get().clone()),
+        Entry::Vacant(vacant) => {
+            vacant.insert(relative_path.to_string());
+            FileKind::Regular
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn hard_link_kind(
+    _groups: &mut HashMap<(u64, u64), String>,
+    _metadata: &fs::Metadata,
+    _relative_path: &str,
+) -> FileKind {
+    FileKind::Regular
+}
+
+/// Collect the data read from stdin as a single file named `name`.
+pub fn collect_stdin(name: &str) -> FileCollector {
+    let mut fc = FileCollector::default();
+    fc.calc_max_file_name_length(name.len());
+    fc.add_file(FileInfo {
+        file_id: 1,
+        name: name.to_string(),
+        access_path: STDIO_PATH.to_string(),
+        relative_path: name.to_string(),
+        #[cfg(unix)]
+        mode: 0o644,
+        size: 0,
+        empty_dir: false,
+        kind: FileKind::Stream,
+        attributes: None,
+    });
+    fc.count_num_files();
+    fc
 }
 
 /// Check whether the paths exists.
@@ -296,6 +672,12 @@ pub fn zip_folder<P: AsRef<Path>>(
                 spinner.set_prefix(format!("Compressing {:<width$}", display_path, width = 50));
             }
 
+            // the receiver restores the mode when extracting
+            #[cfg(unix)]
+            let options = match entry.metadata() {
+                Ok(metadata) => options.unix_permissions(metadata.mode() & 0o7777),
+                Err(_) => options,
+            };
             zip.start_file(&path_in_zip, options)?;
             let mut file = File::open(entry_path)?;
             io::copy(&mut file, &mut zip)?;
@@ -320,48 +702,62 @@ pub fn zip_folder<P: AsRef<Path>>(
     Ok(())
 }
 
-/// Unzip given zip file.
-pub fn unzip<P: AsRef<Path>>(zip_files: &[P]) -> Result<()> {
-    for zip_file in zip_files {
-        let zip_file_path = zip_file.as_ref();
-        let root_dir = match zip_file_path.file_name() {
-            Some(file_name) => {
-                let mut root_dir = "";
-                let file_name = file_name.to_str().unwrap_or_default().split(".").collect::<Vec<_>>();
-                if file_name.len() > 0 {
-                    root_dir = file_name.get(0).unwrap();
-                }
-                root_dir
-            }
-            None => "",
-        };
-        // create root directory
-        fs::create_dir(root_dir)?;
-        let root_path = Path::new(root_dir);
-
-        let file = File::open(zip_file_path)?;
-        let mut archive = ZipArchive::new(file)?;
-
-        for i in 0..archive.len() {
-            let mut file = archive.by_index(i)?;
+/// Stream a folder as a tar archive built on a blocking task, which fails if the archive could not be completed.
+pub fn stream_tar_archive(path: PathBuf) -> (DuplexStream, JoinHandle<Result<()>>) {
+    let (reader, writer) = tokio::io::duplex(SEND_BUFF_SIZE);
+    let builder = tokio::task::spawn_blocking(move || {
+        let root_name = path.file_name().unwrap_or("archive".as_ref()).to_owned();
+        let mut builder = tar::Builder::new(SyncIoBridge::new(writer));
+        builder.append_dir_all(&root_name, &path)?;
+        builder.into_inner()?.shutdown()?;
+        Ok(())
+    });
+    (reader, builder)
+}
 
-            // create the decompressed file path
-            let outpath = root_path.join(file.mangled_name());
-            println!("outpath: {outpath:?}");
+/// Extract a tar archive read from `reader` into `output_dir`, entries escaping the folder are rejected.
+pub fn extract_tar<R: Read>(
+    reader: R,
+    output_dir: &Path,
+) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        // refuses `..` components and writing through links pointing outside of the folder
+        if !entry.unpack_in(output_dir)? {
+            bail!("{}: escapes the output directory", entry.path()?.to_string_lossy());
+        }
+    }
+    // the padding after the end of the archive
+    io::copy(&mut archive.into_inner(), &mut io::sink())?;
+    Ok(())
+}
 
-            // if the extracted file is a directory, create the corresponding directory
-            if (&*file.name()).ends_with('/') {
-                std::fs::create_dir_all(&outpath)?;
-            } else {
-                // create the decompressed file
-                if let Some(p) = outpath.parent() {
-                    if !p.exists() {
-                        std::fs::create_dir_all(&p)?;
-                    }
-                }
-                let mut outfile = File::create(&outpath)?;
-                io::copy(&mut file, &mut outfile)?;
+/// Unzip given zip file into `output_dir`, entries escaping the folder are refused and unix modes are restored
+/// without the setuid, setgid and sticky bits.
+pub fn unzip(
+    zip_file: impl AsRef<Path>,
+    output_dir: impl AsRef<Path>,
+) -> Result<()> {
+    let output_dir = output_dir.as_ref();
+    let mut archive = ZipArchive::new(File::open(zip_file.as_ref())?)?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        let outpath = safe_join_relative_path(output_dir, file.name())?;
+        if file.is_dir() {
+            fs::create_dir_all(&outpath)?;
+        } else {
+            if let Some(parent) = outpath.parent() {
+                fs::create_dir_all(parent)?;
             }
+            let mut outfile = File::create(&outpath)?;
+            io::copy(&mut file, &mut outfile)?;
+        }
+        #[cfg(unix)]
+        if let Some(mode) = file.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+
+            fs::set_permissions(&outpath, fs::Permissions::from_mode(mode & 0o777))?;
         }
     }
     Ok(())
@@ -423,64 +819,64 @@ pub fn safe_join_relative_path(
         bail!("empty relative path");
     }
 
+    // an entry under a symbolic link received before would land wherever the link points
+    for ancestor in safe_relative.ancestors().skip(1).filter(|ancestor| !ancestor.as_os_str().is_empty()) {
+        if is_symlink(base.as_ref().join(ancestor)) {
+            bail!("unsafe relative path through a symbolic link: {relative_path}");
+        }
+    }
+
     Ok(base.as_ref().join(safe_relative))
 }
 
-/// Check the file for missing (all-zero) chunks and calculate the percentage of saved data.
-///
-/// # Arguments
-/// * `path` - The path to the file to be checked.
-/// * `chunk_size` - The size of each chunk (in bytes) to check for missing data.
-///
-/// # Returns
-/// * `Ok((saved_chunks, missing_chunks, percent))`
-///     - `saved_chunks`: Number of non-empty (saved) chunks found in the file.
-///     - `missing_chunks`: Number of empty (all-zero) chunks found at the end of the file.
-///     - `percent`: Percentage of the file that is non-empty (saved), rounded to two decimal places.
-///
-/// # Errors
-/// Returns an error if the file cannot be opened or read.
-pub fn missing_chunks(
-    path: impl AsRef<Path>,
-    chunk_size: usize,
-) -> Result<(usize, usize, f64)> {
-    let f = File::open(path)?;
-    let fsize = f.metadata()?.len();
-
-    let empty_buffer = vec![0u8; chunk_size];
-    let mut saved_chunks = 0;
-    let mut missing_chunks = 0;
-
-    let mut reader = io::BufReader::new(f);
-    let mut buffer = vec![0u8; chunk_size];
-
-    loop {
-        let bytes_read = reader.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
-        }
+fn is_symlink(path: impl AsRef<Path>) -> bool {
+    path.as_ref().symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
 
-        if buffer[..bytes_read] == empty_buffer[..bytes_read] {
-            // empty chunk
-            missing_chunks += 1;
-        } else {
-            saved_chunks += 1;
-            if missing_chunks > 0 {
-                // add missing chunks to saved chunks
-                saved_chunks += missing_chunks;
-                missing_chunks = 0;
+/// Path a symbolic link at `link_relative_path` points to, a target outside of `base` is refused.
+pub fn safe_link_target(
+    base: impl AsRef<Path>,
+    link_relative_path: &str,
+    target: &str,
+) -> Result<PathBuf> {
+    let link_relative_path = reset_path(link_relative_path);
+    let normalized = reset_path(target);
+    let parent = Path::new(&link_relative_path).parent().unwrap_or(Path::new(""));
+    let mut resolved = PathBuf::new();
+    let components: Vec<_> = parent.components().chain(Path::new(&normalized).components()).collect();
+
+    for (index, component) in components.iter().enumerate() {
+        match component {
+            Component::Normal(part) => {
+                resolved.push(part);
+                // the target is resolved on disk, a link on the way may lead out of `base`
+                if index + 1 < components.len() && is_symlink(base.as_ref().join(&resolved)) {
+                    bail!("unsafe link target through a symbolic link: {target}");
+                }
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    bail!("unsafe link target: {target}");
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                bail!("unsafe link target: {target}");
             }
         }
     }
 
-    let percent = ((saved_chunks * chunk_size) as f64 / fsize as f64 * 100.0 * 100.0).round() / 100.0;
-
-    Ok((saved_chunks, missing_chunks, percent))
+    safe_join_relative_path(base, resolved.to_string_lossy().as_ref())
 }
 
 #[cfg(test)]
 mod tests {
-    use super::safe_join_relative_path;
+    use std::path::Path;
+
+    use filetime::FileTime;
+
+    use super::{CollectOptions, FileAttributes, collect_files, collect_files_with, safe_join_relative_path, safe_link_target, unzip};
+    use crate::proto;
 
     #[test]
     fn safe_join_accepts_normal_relative_path() {
@@ -493,4 +889,111 @@ mod tests {
         assert!(safe_join_relative_path("/tmp/out", "../secret.txt").is_err());
         assert!(safe_join_relative_path("/tmp/out", "/tmp/secret.txt").is_err());
     }
+
+    #[test]
+    fn safe_link_target_stays_in_base() {
+        let path = safe_link_target("/tmp/out", "folder/sub/link", "../file.txt").unwrap();
+        assert!(path.ends_with("folder/file.txt"));
+        assert!(safe_link_target("/tmp/out", "folder/link", "../../secret.txt").is_err());
+        assert!(safe_link_target("/tmp/out", "folder/link", "/etc/passwd").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn safe_paths_refuse_symlinks_on_the_way() {
+        let base = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(base.path().join("d/sub")).unwrap();
+        // points to `d`, inside the base
+        assert!(safe_link_target(base.path(), "d/sub/up", "..").is_ok());
+        std::os::unix::fs::symlink("..", base.path().join("d/sub/up")).unwrap();
+
+        // `d/sub/up/x` is `d/x` on disk, `../..` from there is out of the base
+        assert!(safe_link_target(base.path(), "d/sub/up/x", "../..").is_err());
+        assert!(safe_join_relative_path(base.path(), "d/sub/up/x").is_err());
+        assert!(safe_link_target(base.path(), "d/link", "sub/up/../..").is_err());
+        // a link to a link is fine
+        assert!(safe_link_target(base.path(), "d/link", "sub/up").is_ok());
+        assert!(safe_join_relative_path(base.path(), "d/sub/up").is_ok());
+    }
+
+    #[test]
+    fn collect_files_with_filters_totals() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(root.join("target")).unwrap();
+        std::fs::create_dir_all(root.join("src/nested")).unwrap();
+        std::fs::create_dir_all(root.join("logs")).unwrap();
+        std::fs::write(root.join("target/out.bin"), [0u8; 8]).unwrap();
+        std::fs::write(root.join("src/main.rs"), [0u8; 4]).unwrap();
+        std::fs::write(root.join("src/nested/deep.rs"), [0u8; 2]).unwrap();
+        std::fs::write(root.join("logs/debug.log"), [0u8; 1]).unwrap();
+
+        let options = CollectOptions {
+            exclude: vec!["target".to_string(), "*.log".to_string()],
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        let collector = collect_files_with(&[&root], &options).unwrap();
+
+        assert_eq!(collector.num_files, 1);
+        assert_eq!(collector.total_size, 4);
+        assert!(collector.files.iter().all(|file| !file.relative_path.contains("target")));
+        // every child of the folder is left out, it is sent empty
+        assert!(collector.files.iter().any(|file| file.empty_dir && Path::new(&file.relative_path) == Path::new("root/logs")));
+        assert!(!collector.files.iter().any(|file| file.empty_dir && Path::new(&file.relative_path) == Path::new("root/src")));
+        assert!(collector.files.iter().enumerate().all(|(i, file)| file.file_id == i as u64 + 1));
+
+        assert!(collect_files(&[dir.path().join("missing")]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn collect_files_fails_on_entry_below_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("ok.txt"), b"ok").unwrap();
+        // followed as links aren't preserved, the target is missing
+        std::os::unix::fs::symlink("missing", root.join("dangling")).unwrap();
+
+        assert!(collect_files(&[&root]).is_err());
+        let options = CollectOptions {
+            preserve_links: true,
+            ..Default::default()
+        };
+        assert_eq!(collect_files_with(&[&root], &options).unwrap().num_files, 2);
+    }
+
+    #[test]
+    fn file_attributes_round_trip_through_proto() {
+        let attributes = FileAttributes {
+            mtime: Some(FileTime::from_unix_time(1_700_000_000, 123)),
+            atime: None,
+            uid: Some(1000),
+            gid: None,
+            xattrs: vec![("user.comment".to_string(), b"flash".to_vec())],
+        };
+        let message: proto::FileAttributes = attributes.clone().into();
+        assert_eq!(FileAttributes::from(message), attributes);
+    }
+
+    #[test]
+    fn unzip_refuses_path_escape() {
+        use std::io::Write;
+
+        use zip::{ZipWriter, write::SimpleFileOptions};
+
+        let root = tempfile::tempdir().unwrap();
+        let zip_path = root.path().join("evil.zip");
+        let mut zip = ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        zip.start_file("folder/ok.txt", SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"ok").unwrap();
+        zip.start_file("../escaped.txt", SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"escaped").unwrap();
+        zip.finish().unwrap();
+
+        assert!(unzip(&zip_path, root.path().join("out")).is_err());
+        assert!(root.path().join("out/folder/ok.txt").exists());
+        assert!(!root.path().join("escaped.txt").exists());
+    }
 }
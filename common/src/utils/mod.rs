@@ -1,10 +1,13 @@
 use std::time::{Duration, SystemTime};
 
+use anyhow::{Result, bail};
+
 use indicatif::{HumanBytes, HumanDuration};
 use rand::{RngExt, distr::Alphanumeric};
 
 pub mod fs;
 pub mod net;
+pub mod rate_limit;
 
 pub fn convert_bytes_to_human_readable(bytes: u64) -> String {
     let units = ["B", "KB", "MB", "GB", "TB", "PB", "EB", "ZB", "YB"];
@@ -19,6 +22,35 @@ pub fn convert_bytes_to_human_readable(bytes: u64) -> String {
     format!("{:.2}{}", bytes, units[unit_index])
 }
 
+/// Parse a size such as `5MiB`, `500KB` or `1048576`, in bytes.
+///
+/// `K`, `M` and `G` are binary multiples like `KiB`, `MiB` and `GiB`, `KB`, `MB` and
+/// `GB` are decimal ones.
+pub fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let split = size.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(size.len());
+    let (value, unit) = size.split_at(split);
+    let value: f64 = match value.parse() {
+        Ok(value) => value,
+        Err(_) => bail!("invalid size: {size}"),
+    };
+    let multiple: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        unit => bail!("unknown size unit: {unit}"),
+    };
+    let bytes = (value * multiple as f64) as u64;
+    if bytes == 0 {
+        bail!("size must be at least 1 byte");
+    }
+    Ok(bytes)
+}
+
 pub fn gen_share_code() -> String {
     let mut rng = rand::rng();
     format!(
@@ -43,7 +75,16 @@ pub fn human_duration(duration: Duration) -> String {
 
 #[cfg(test)]
 mod test {
-    use crate::utils::gen_share_code;
+    use crate::utils::{gen_share_code, parse_size};
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!(parse_size("2GiB").unwrap(), 2 << 30);
+        assert_eq!(parse_size("500KB").unwrap(), 500_000);
+        assert_eq!(parse_size("1048576").unwrap(), 1 << 20);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("5TB").is_err());
+    }
 
     #[test]
     fn t1() {
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::{sync::Mutex, time::Instant};
+
+use super::parse_size;
+
+/// Token bucket limiting the bytes passed through it per second, shared by every
+/// transfer it applies to.
+///
+/// Up to one second of bytes may be passed at once, a request larger than the tokens
+/// left is let through and the next one waits until the debt is paid back.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: u64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1);
+        Self {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Bytes per second let through.
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Wait until `bytes` may be passed, the waiters are served in order.
+    pub async fn acquire(
+        &self,
+        bytes: usize,
+    ) {
+        let mut bucket = self.bucket.lock().await;
+        let now = Instant::now();
+        let refill = now.duration_since(bucket.updated).as_secs_f64() * self.rate as f64;
+        bucket.tokens = (bucket.tokens + refill).min(self.rate as f64) - bytes as f64;
+        bucket.updated = now;
+        if bucket.tokens < 0.0 {
+            // the lock is held on purpose, the others queue up behind this one
+            tokio::time::sleep(Duration::from_secs_f64(-bucket.tokens / self.rate as f64)).await;
+        }
+    }
+}
+
+/// Parse a rate such as `5MiB/s`, `500KB/s` or `1048576`, in bytes per second.
+///
+/// The units are the ones of [`parse_size`].
+pub fn parse_rate(rate: &str) -> Result<u64> {
+    let rate = rate.trim();
+    parse_size(rate.strip_suffix("/s").unwrap_or(rate))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::{RateLimiter, parse_rate};
+
+    #[tokio::test]
+    async fn lets_a_second_of_bytes_through_at_once() {
+        let limiter = RateLimiter::new(1000);
+        let start = Instant::now();
+        limiter.acquire(600).await;
+        limiter.acquire(400).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_debt_to_be_paid_back() {
+        let limiter = RateLimiter::new(1000);
+        let start = Instant::now();
+        // more than the bucket holds, the bytes missing are waited for
+        limiter.acquire(1300).await;
+        assert!(start.elapsed() >= Duration::from_millis(250));
+        let paid = Instant::now();
+        limiter.acquire(100).await;
+        assert!(paid.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn parse_rates() {
+        assert_eq!(parse_rate("5MiB/s").unwrap(), 5 * 1024 * 1024);
+        assert_eq!(parse_rate("500KB/s").unwrap(), 500_000);
+        assert_eq!(parse_rate("1.5M").unwrap(), 1536 * 1024);
+        assert_eq!(parse_rate("2048").unwrap(), 2048);
+        assert!(parse_rate("0").is_err());
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("5TB/s").is_err());
+    }
+}
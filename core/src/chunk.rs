@@ -0,0 +1,145 @@
+//! Adaptive chunk size of the sender, tuned from the round trip time measured with
+//! the relay pings and the throughput of the chunks sent.
+
+use std::time::{Duration, Instant};
+
+use flash_cat_common::consts::{MAX_CHUNK_SIZE, MIN_CHUNK_SIZE, SEND_BUFF_SIZE};
+
+use crate::TransferStats;
+
+/// Interval the throughput is measured over before the chunk size is adjusted.
+const ADJUST_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Time sending a single chunk should take at the measured throughput.
+const TARGET_CHUNK_TIME: Duration = Duration::from_millis(20);
+
+/// Number of chunks read ahead of the one being sent.
+pub const PIPELINE_DEPTH: usize = 4;
+
+#[derive(Debug)]
+pub struct ChunkSizer {
+    chunk_size: usize,
+    /// Smoothed round trip time to the relay.
+    rtt: Option<Duration>,
+    /// Smoothed throughput in bytes per second.
+    throughput: u64,
+    window_start: Instant,
+    window_bytes: u64,
+}
+
+impl ChunkSizer {
+    pub fn new() -> Self {
+        Self {
+            chunk_size: SEND_BUFF_SIZE,
+            rtt: None,
+            throughput: 0,
+            window_start: Instant::now(),
+            window_bytes: 0,
+        }
+    }
+
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    pub fn record_rtt(
+        &mut self,
+        rtt: Duration,
+    ) {
+        self.rtt = Some(match self.rtt {
+            Some(smoothed) => (smoothed * 7 + rtt) / 8,
+            None => rtt,
+        });
+    }
+
+    /// Account a sent chunk, returns the stats whenever the chunk size was reconsidered.
+    pub fn record_sent(
+        &mut self,
+        bytes: usize,
+    ) -> Option<TransferStats> {
+        self.window_bytes += bytes as u64;
+        let elapsed = self.window_start.elapsed();
+        if elapsed < ADJUST_INTERVAL {
+            return None;
+        }
+        let throughput = (self.window_bytes as f64 / elapsed.as_secs_f64()) as u64;
+        self.throughput = match self.throughput {
+            0 => throughput,
+            smoothed => (smoothed * 3 + throughput) / 4,
+        };
+        self.window_start = Instant::now();
+        self.window_bytes = 0;
+
+        // large enough for the chunks in flight to cover the bandwidth-delay product
+        let chunk_time = self.rtt.map_or(TARGET_CHUNK_TIME, |rtt| (rtt / PIPELINE_DEPTH as u32).max(TARGET_CHUNK_TIME));
+        let target = (self.throughput as f64 * chunk_time.as_secs_f64()) as usize;
+        // move at most by a factor of two at a time, in steps of the minimum size
+        let target = target.clamp(self.chunk_size / 2, self.chunk_size * 2);
+        self.chunk_size = (target / MIN_CHUNK_SIZE * MIN_CHUNK_SIZE).clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE);
+        Some(TransferStats {
+            chunk_size: self.chunk_size,
+            rtt: self.rtt,
+            throughput: self.throughput,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use flash_cat_common::consts::{MAX_CHUNK_SIZE, MIN_CHUNK_SIZE, SEND_BUFF_SIZE};
+
+    use super::ChunkSizer;
+
+    /// Account `bytes` sent over the last second, the chunk size is reconsidered.
+    fn sent_in_a_second(
+        sizer: &mut ChunkSizer,
+        bytes: usize,
+    ) -> usize {
+        sizer.window_start = Instant::now() - Duration::from_secs(1);
+        sizer.record_sent(bytes).unwrap().chunk_size
+    }
+
+    #[test]
+    fn kept_until_the_throughput_is_measured() {
+        let mut sizer = ChunkSizer::new();
+        assert!(sizer.record_sent(SEND_BUFF_SIZE).is_none());
+        assert_eq!(sizer.chunk_size(), SEND_BUFF_SIZE);
+    }
+
+    #[test]
+    fn grows_on_a_fast_link_up_to_the_max() {
+        let mut sizer = ChunkSizer::new();
+        sizer.record_rtt(Duration::from_millis(200));
+        // doubled at most at a time
+        assert_eq!(sent_in_a_second(&mut sizer, 100 * 1024 * 1024), 2 * SEND_BUFF_SIZE);
+        assert_eq!(sent_in_a_second(&mut sizer, 100 * 1024 * 1024), 4 * SEND_BUFF_SIZE);
+        for _ in 0..4 {
+            sent_in_a_second(&mut sizer, 100 * 1024 * 1024);
+        }
+        assert_eq!(sizer.chunk_size(), MAX_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn shrinks_on_a_slow_link_down_to_the_min() {
+        let mut sizer = ChunkSizer::new();
+        assert_eq!(sent_in_a_second(&mut sizer, 100 * 1024), SEND_BUFF_SIZE / 2);
+        for _ in 0..4 {
+            sent_in_a_second(&mut sizer, 100 * 1024);
+        }
+        assert_eq!(sizer.chunk_size(), MIN_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn larger_for_a_longer_round_trip() {
+        let mut near = ChunkSizer::new();
+        near.record_rtt(Duration::from_millis(10));
+        let mut far = ChunkSizer::new();
+        far.record_rtt(Duration::from_millis(400));
+        let near = sent_in_a_second(&mut near, 10 * 1024 * 1024);
+        let far = sent_in_a_second(&mut far, 10 * 1024 * 1024);
+        assert!(near < SEND_BUFF_SIZE);
+        assert_eq!(far, 2 * SEND_BUFF_SIZE);
+    }
+}
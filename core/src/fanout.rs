@@ -0,0 +1,333 @@
+//! Broadcast sessions, where a sender sends its files to several receivers. A file is
+//! read once, the chunks read for one receiver are held for the others to take.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+use flash_cat_common::utils::fs::stream_tar_archive;
+use tokio::sync::OnceCell;
+
+use crate::chunk::ChunkSizer;
+
+/// Most bytes of chunks held for the receivers behind, the chunks dropped meanwhile are
+/// read again from the file: 64MiB.
+const MAX_HELD_SIZE: usize = 64 * 1024 * 1024;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Receivers of a broadcast session, shared by the relay channels of every slot.
+#[derive(Debug)]
+pub struct Broadcast {
+    max_receivers: u32,
+    receivers: Mutex<Receivers>,
+    /// Shared by every receiver, the chunks all go over the uplink of the sender and line
+    /// up for the others to take.
+    chunk_sizer: Arc<Mutex<ChunkSizer>>,
+    chunks: SharedChunks,
+    /// Tells the archives of the sessions of a process apart.
+    id: u64,
+    /// Folders streamed as tar archives are built once into a file, by file id.
+    archives: Mutex<HashMap<u64, Arc<OnceCell<PathBuf>>>>,
+}
+
+#[derive(Debug, Default)]
+struct Receivers {
+    /// Receivers joined so far, across the relays, including the ones gone.
+    joined: u32,
+    /// Receivers receiving, the slot of a receiver gone is taken by the next one to join.
+    active: u32,
+    /// Receivers done with the files.
+    completed: u32,
+    /// Set once the join window is over, the receivers joined are the last.
+    joins_closed: bool,
+    /// Set once the share is done, no receiver joins afterwards.
+    closed: bool,
+}
+
+impl Broadcast {
+    pub fn new(max_receivers: u32) -> Self {
+        Self {
+            max_receivers,
+            receivers: Mutex::new(Receivers::default()),
+            chunk_sizer: Arc::new(Mutex::new(ChunkSizer::new())),
+            chunks: SharedChunks::default(),
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            archives: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn max_receivers(&self) -> u32 {
+        self.max_receivers
+    }
+
+    /// Number of a receiver joining, `None` while every slot is taken or once the share
+    /// is done.
+    pub fn join(&self) -> Option<u32> {
+        let mut receivers = self.receivers.lock().unwrap();
+        if receivers.closed || receivers.joins_closed || receivers.active >= self.max_receivers {
+            return None;
+        }
+        receivers.joined += 1;
+        receivers.active += 1;
+        Some(receivers.joined)
+    }
+
+    /// Count a receiver done with the files or gone, `true` once the share is done: none is
+    /// left receiving, and either as many receivers as accepted completed or the join window
+    /// is over.
+    pub fn finish(
+        &self,
+        completed: bool,
+    ) -> bool {
+        let mut receivers = self.receivers.lock().unwrap();
+        receivers.active = receivers.active.saturating_sub(1);
+        if completed {
+            receivers.completed += 1;
+        }
+        let done = receivers.completed >= self.max_receivers || receivers.joins_closed;
+        receivers.try_close(done)
+    }
+
+    /// End the join window, `true` if the share is done as no receiver is left receiving.
+    pub fn close_joins(&self) -> bool {
+        let mut receivers = self.receivers.lock().unwrap();
+        receivers.joins_closed = true;
+        receivers.try_close(true)
+    }
+
+    /// Receivers still receiving besides the one asking.
+    fn other_receivers(&self) -> usize {
+        self.receivers.lock().unwrap().active.saturating_sub(1) as usize
+    }
+
+    pub fn chunk_sizer(&self) -> Arc<Mutex<ChunkSizer>> {
+        self.chunk_sizer.clone()
+    }
+
+    /// Chunk of a file at `position` read for another receiver, at most `limit` bytes of it.
+    pub fn take_chunk(
+        &self,
+        file_id: u64,
+        position: u64,
+        limit: usize,
+    ) -> Option<Bytes> {
+        self.chunks.take(file_id, position, limit)
+    }
+
+    /// Tar archive of the folder at `path`, built by the first receiver to reach it while
+    /// the others wait, so the folder is read once whatever the receivers.
+    pub async fn archive(
+        &self,
+        file_id: u64,
+        path: &str,
+    ) -> Result<PathBuf> {
+        let cell = self.archives.lock().unwrap().entry(file_id).or_default().clone();
+        let archive = cell
+            .get_or_try_init(|| async {
+                let archive = std::env::temp_dir().join(format!("flash-cat-{}-{}-{file_id}.tar", std::process::id(), self.id));
+                let (mut reader, builder) = stream_tar_archive(PathBuf::from(path));
+                let mut file = tokio::fs::File::create(&archive).await?;
+                tokio::io::copy(&mut reader, &mut file).await?;
+                builder.await??;
+                file.sync_all().await?;
+                anyhow::Ok(archive)
+            })
+            .await?;
+        Ok(archive.clone())
+    }
+
+    /// Hold a chunk read from a file for the other receivers.
+    pub fn hold_chunk(
+        &self,
+        file_id: u64,
+        position: u64,
+        data: Bytes,
+    ) {
+        self.chunks.put(file_id, position, data, self.other_receivers());
+    }
+}
+
+impl Receivers {
+    fn try_close(
+        &mut self,
+        done: bool,
+    ) -> bool {
+        if self.closed || !done || self.active > 0 {
+            return false;
+        }
+        self.closed = true;
+        true
+    }
+}
+
+impl Drop for Broadcast {
+    fn drop(&mut self) {
+        for cell in self.archives.get_mut().unwrap().values() {
+            if let Some(archive) = cell.get() {
+                let _ = std::fs::remove_file(archive);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct SharedChunks(Mutex<HeldChunks>);
+
+#[derive(Debug, Default)]
+struct HeldChunks {
+    /// Chunks by file id and position, with the number of receivers yet to take them.
+    chunks: HashMap<(u64, u64), (Bytes, usize)>,
+    /// Keys of the chunks in the order they were read, the oldest are dropped first.
+    order: VecDeque<(u64, u64)>,
+    size: usize,
+}
+
+impl SharedChunks {
+    fn take(
+        &self,
+        file_id: u64,
+        position: u64,
+        limit: usize,
+    ) -> Option<Bytes> {
+        let mut held = self.0.lock().unwrap();
+        let key = (file_id, position);
+        let (data, readers) = held.chunks.get_mut(&key)?;
+        let size = data.len();
+        let taken = data.slice(..size.min(limit));
+        *readers -= 1;
+        if *readers == 0 {
+            held.chunks.remove(&key);
+            held.size -= size;
+        }
+        Some(taken)
+    }
+
+    fn put(
+        &self,
+        file_id: u64,
+        position: u64,
+        data: Bytes,
+        readers: usize,
+    ) {
+        if readers == 0 {
+            return;
+        }
+        let mut held = self.0.lock().unwrap();
+        let key = (file_id, position);
+        held.size += data.len();
+        match held.chunks.insert(key, (data, readers)) {
+            Some((replaced, _)) => held.size -= replaced.len(),
+            None => held.order.push_back(key),
+        }
+        while held.size > MAX_HELD_SIZE {
+            let Some(oldest) = held.order.pop_front() else {
+                break;
+            };
+            if let Some((data, _)) = held.chunks.remove(&oldest) {
+                held.size -= data.len();
+            }
+        }
+        // the keys of the chunks taken by everyone are left behind
+        if held.order.len() > 2 * held.chunks.len() + 64 {
+            let HeldChunks {
+                chunks,
+                order,
+                ..
+            } = &mut *held;
+            order.retain(|key| chunks.contains_key(key));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use super::Broadcast;
+
+    #[test]
+    fn completes_once_every_receiver_did() {
+        let broadcast = Broadcast::new(2);
+        assert_eq!(broadcast.join(), Some(1));
+        assert_eq!(broadcast.join(), Some(2));
+        assert_eq!(broadcast.join(), None);
+        assert!(!broadcast.finish(true));
+        assert!(broadcast.finish(true));
+        assert_eq!(broadcast.join(), None);
+    }
+
+    #[test]
+    fn slot_of_receiver_gone_is_taken_again() {
+        let broadcast = Broadcast::new(2);
+        broadcast.join();
+        broadcast.join();
+        assert!(!broadcast.finish(false));
+        assert_eq!(broadcast.join(), Some(3));
+        assert!(!broadcast.finish(true));
+        assert!(broadcast.finish(true));
+    }
+
+    #[test]
+    fn completes_with_fewer_receivers_after_join_window() {
+        let broadcast = Broadcast::new(3);
+        broadcast.join();
+        broadcast.join();
+        assert!(!broadcast.finish(true));
+        assert!(!broadcast.close_joins());
+        assert_eq!(broadcast.join(), None);
+        assert!(broadcast.finish(true));
+
+        let broadcast = Broadcast::new(3);
+        broadcast.join();
+        assert!(!broadcast.finish(true));
+        assert!(broadcast.close_joins());
+        assert!(!broadcast.close_joins());
+    }
+
+    #[test]
+    fn receivers_take_chunks_at_their_own_positions() {
+        let broadcast = Broadcast::new(3);
+        for _ in 0..3 {
+            broadcast.join();
+        }
+        broadcast.hold_chunk(1, 0, Bytes::from_static(b"first"));
+        broadcast.hold_chunk(1, 5, Bytes::from_static(b"second"));
+        // a receiver behind takes a part of the chunk only
+        assert_eq!(broadcast.take_chunk(1, 0, 3), Some(Bytes::from_static(b"fir")));
+        assert_eq!(broadcast.take_chunk(1, 5, 1024), Some(Bytes::from_static(b"second")));
+        assert_eq!(broadcast.take_chunk(1, 0, 1024), Some(Bytes::from_static(b"first")));
+        // taken by both other receivers
+        assert_eq!(broadcast.take_chunk(1, 0, 1024), None);
+        assert_eq!(broadcast.take_chunk(1, 5, 1024), Some(Bytes::from_static(b"second")));
+        assert_eq!(broadcast.take_chunk(1, 5, 1024), None);
+        assert_eq!(broadcast.take_chunk(2, 0, 1024), None);
+    }
+
+    #[test]
+    fn receiver_leaving_mid_file() {
+        let broadcast = Broadcast::new(3);
+        for _ in 0..3 {
+            broadcast.join();
+        }
+        broadcast.hold_chunk(1, 0, Bytes::from_static(b"first"));
+        assert!(!broadcast.finish(false));
+        // held for the one receiver left
+        broadcast.hold_chunk(1, 5, Bytes::from_static(b"second"));
+        assert_eq!(broadcast.take_chunk(1, 5, 1024), Some(Bytes::from_static(b"second")));
+        assert_eq!(broadcast.take_chunk(1, 5, 1024), None);
+        assert!(!broadcast.finish(true));
+        // nothing is held once a single receiver is left
+        broadcast.hold_chunk(1, 11, Bytes::from_static(b"third"));
+        assert_eq!(broadcast.take_chunk(1, 11, 1024), None);
+        assert!(!broadcast.finish(true));
+        assert!(broadcast.close_joins());
+    }
+}
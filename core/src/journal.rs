@@ -0,0 +1,148 @@
+//! Resume journal of a receive, persisted in the output directory so an interrupted
+//! receive can be picked up by a new receiver process.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+/// File name of the journal in the output directory.
+pub const JOURNAL_FILE_NAME: &str = ".flashcat-partial";
+
+/// Interval for syncing received data to disk and saving the journal.
+pub const JOURNAL_SYNC_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResumeJournal {
+    /// Fingerprint of the sender manifest, the same for every session of the same transfer.
+    fingerprint: String,
+    files: BTreeMap<u64, JournalEntry>,
+    /// Files selected from the manifest, the sender only sends these on resume.
+    #[serde(default)]
+    selected: Option<Vec<u64>>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub relative_path: String,
+    pub size: u64,
+    /// Bytes from the start of the file that are synced to disk.
+    pub confirmed: u64,
+    pub completed: bool,
+}
+
+impl ResumeJournal {
+    /// Open the journal of `output_dir`, a journal of another transfer is discarded.
+    pub async fn open(
+        output_dir: &Path,
+        fingerprint: &[u8],
+    ) -> Self {
+        let path = output_dir.join(JOURNAL_FILE_NAME);
+        let fingerprint = hex::encode(fingerprint);
+        let journal = match fs::read(&path).await {
+            Ok(content) => serde_json::from_slice::<ResumeJournal>(&content).ok().filter(|journal| journal.fingerprint == fingerprint),
+            Err(_) => None,
+        };
+        match journal {
+            Some(journal) => Self {
+                path,
+                ..journal
+            },
+            None => Self {
+                fingerprint,
+                files: BTreeMap::new(),
+                selected: None,
+                path,
+            },
+        }
+    }
+
+    /// Files tracked by the journal, left over from a previous session when just opened.
+    pub fn entries(&self) -> impl Iterator<Item = (u64, &JournalEntry)> {
+        self.files.iter().map(|(&file_id, entry)| (file_id, entry))
+    }
+
+    pub fn selected(&self) -> Option<&[u64]> {
+        self.selected.as_deref()
+    }
+
+    pub fn select(
+        &mut self,
+        file_ids: &[u64],
+    ) {
+        self.selected = Some(file_ids.to_vec());
+    }
+
+    pub fn track(
+        &mut self,
+        file_id: u64,
+        relative_path: &str,
+        size: u64,
+        confirmed: u64,
+    ) {
+        self.files.insert(
+            file_id,
+            JournalEntry {
+                relative_path: relative_path.to_string(),
+                size,
+                confirmed,
+                completed: false,
+            },
+        );
+    }
+
+    pub fn untrack(
+        &mut self,
+        file_id: u64,
+    ) {
+        self.files.remove(&file_id);
+    }
+
+    pub fn confirm(
+        &mut self,
+        file_id: u64,
+        confirmed: u64,
+    ) {
+        if let Some(entry) = self.files.get_mut(&file_id) {
+            entry.confirmed = confirmed;
+        }
+    }
+
+    pub fn complete(
+        &mut self,
+        file_id: u64,
+    ) {
+        if let Some(entry) = self.files.get_mut(&file_id) {
+            entry.confirmed = entry.size;
+            entry.completed = true;
+        }
+    }
+
+    /// Save the journal, written to a temporary file first so it is never left half written.
+    pub async fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent).await?;
+            }
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_vec(self)?).await?;
+        fs::rename(&tmp_path, &self.path).await?;
+        Ok(())
+    }
+
+    /// Remove the journal once the whole transfer is done.
+    pub async fn remove(&self) -> Result<()> {
+        if fs::try_exists(&self.path).await? {
+            fs::remove_file(&self.path).await?;
+        }
+        Ok(())
+    }
+}
@@ -2,23 +2,43 @@ use std::{net::SocketAddr, time::Duration};
 
 use anyhow::Result;
 use bytes::Bytes;
-use tokio::sync::mpsc;
-use tonic::transport::Endpoint;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    sync::mpsc,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Streaming, transport::Endpoint};
 
 use flash_cat_common::{
     consts::{
-        DEFAULT_CONNECT_TIMEOUT, DEFAULT_HTTP2_KEEPALIVE_INTERVAL, DEFAULT_HTTP2_KEEPALIVE_TIMEOUT, DEFAULT_TCP_KEEPALIVE, INITIAL_WINDOW_SIZE,
-        MAX_RECONNECT_RETRIES, RECONNECT_BASE_DELAY, RECONNECT_MAX_DELAY,
+        DEFAULT_CONNECT_TIMEOUT, DEFAULT_HTTP2_KEEPALIVE_INTERVAL, DEFAULT_HTTP2_KEEPALIVE_TIMEOUT, DEFAULT_TCP_KEEPALIVE, INITIAL_WINDOW_SIZE, MAX_CHUNK_SIZE,
+        MAX_RECONNECT_RETRIES, RECONNECT_BASE_DELAY, RECONNECT_MAX_DELAY, SEND_BUFF_SIZE,
     },
-    proto::{RelayUpdate, relay_update::RelayMessage},
+    crypt::encryptor::Encryptor,
+    proto::{Character, Id, RelayUpdate, relay_service_client::RelayServiceClient, relay_update::RelayMessage},
 };
 
+mod chunk;
+mod fanout;
+mod journal;
 pub mod receiver;
 pub mod sender;
 
 /// Interval for ping.
 pub const PING_INTERVAL: Duration = Duration::from_secs(2);
 
+/// Maximum number of chunk hashes offered for resuming a single file.
+pub const MAX_RESUME_CHUNKS: u64 = 16384;
+
+/// zstd level of compressed chunks, fast enough to keep up with the network.
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Bytes of a chunk probed for entropy before it is compressed.
+const ENTROPY_PROBE_SIZE: usize = 4096;
+
+/// Entropy in bits per byte above which a chunk is considered incompressible.
+const MAX_COMPRESSIBLE_ENTROPY: f64 = 7.5;
+
 #[derive(Debug, Clone)]
 pub enum SenderInteractionMessage {
     Message(String),
@@ -28,6 +48,34 @@ pub enum SenderInteractionMessage {
     ContinueFile(u64),
     FileProgress(Progress),
     FileProgressFinish(u64),
+    /// Chunk size chosen for the measured link, sent every time it is reconsidered.
+    Stats(TransferStats),
+    /// The transfer was paused (`true`) or resumed by this end.
+    Paused(bool),
+    /// The receiver paused (`true`) or resumed the transfer.
+    PausedByPeer(bool),
+    /// A file was cancelled by either end, the rest of the transfer goes on.
+    FileCancelled(u64),
+    /// A receiver joined a broadcast session or a kept alive share, numbered from 1 in the
+    /// order they joined.
+    ReceiverJoined(u32),
+    /// Progress of a file sent to a receiver of a broadcast session.
+    ReceiverProgress((u32, Progress)),
+    /// A file was sent to a receiver of a broadcast session.
+    ReceiverFileFinished((u32, u64)),
+    /// A file was skipped or cancelled for a receiver of a broadcast session.
+    ReceiverFileSkipped((u32, u64)),
+    /// A receiver of a broadcast session or of a kept alive share rejected the share or
+    /// went away.
+    ReceiverLeft(u32),
+    /// A receiver of a broadcast session received everything, the send is `Completed`
+    /// once every receiver it accepts is done.
+    ReceiverCompleted(u32),
+    /// A receiver of a kept alive share received everything, the send is `Completed` once
+    /// the most downloads allowed are done.
+    Downloaded(Download),
+    /// The kept alive share is open no longer.
+    Expired,
     OtherClose,
     SendDone,
     Completed,
@@ -38,11 +86,27 @@ pub enum ReceiverInteractionMessage {
     Message(String),
     Error(String),
     SendFilesRequest(SendFilesRequest),
+    /// Every file of the transfer, answered with `ReceiverConfirm::SelectFiles`.
+    Manifest(Vec<ManifestEntry>),
     FileDuplication(FileDuplication),
+    /// Files of a batch that already exist, answered with `ReceiverConfirm::BatchConfirm`.
+    BatchDuplication(BatchDuplication),
+    /// A zipped folder was received, answered with `ReceiverConfirm::ExtractConfirm`.
+    ExtractArchive(ExtractArchive),
     RecvNewFile(RecvNewFile),
     BreakPoint(BreakPoint),
     FileProgress(Progress),
     FileProgressFinish(u64),
+    /// The received content does not match the digest of the sender.
+    FileVerifyFailed(u64),
+    /// The transfer was paused (`true`) or resumed by this end.
+    Paused(bool),
+    /// The sender paused (`true`) or resumed the transfer.
+    PausedByPeer(bool),
+    /// A file was cancelled by either end, its partial file is removed or kept.
+    FileCancelled(u64),
+    /// A text was sent instead of files, the transfer is done once it is shown.
+    Text(String),
     OtherClose,
     ReceiveDone,
 }
@@ -70,11 +134,35 @@ pub struct Progress {
     pub position: u64,
 }
 
+/// What a receiver of a kept alive share downloaded.
+#[derive(Debug, Clone)]
+pub struct Download {
+    pub receiver: u32,
+    /// Whether the text was downloaded rather than files.
+    pub text: bool,
+    /// Files sent to the receiver, none for a text.
+    pub num_files: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransferStats {
+    pub chunk_size: usize,
+    /// Round trip time to the relay, unknown until the first pong.
+    pub rtt: Option<Duration>,
+    /// Bytes per second.
+    pub throughput: u64,
+}
+
 #[derive(Debug, Clone)]
 pub enum ReceiverConfirm {
     ReceiveConfirm(bool),
     FileConfirm((bool, u64)),
-    BreakPointConfirm((bool, u64, u64)), // (accept, file_id, start_position)
+    BreakPointConfirm((bool, u64)), // (accept, file_id)
+    SelectFiles(Vec<u64>),          // file ids to receive
+    ExtractConfirm((bool, u64)),    // (extract, file_id)
+    BatchConfirm((bool, u64)),      // (overwrite, batch_id)
+    CancelFile(u64),                // file id to stop receiving
 }
 
 #[derive(Debug, Clone)]
@@ -85,6 +173,14 @@ pub struct SendFilesRequest {
     pub max_file_name_length: u64,
 }
 
+#[derive(Debug, Clone)]
+pub struct ManifestEntry {
+    pub file_id: u64,
+    pub relative_path: String,
+    pub size: u64,
+    pub is_empty_dir: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct FileDuplication {
     pub file_id: u64,
@@ -92,6 +188,19 @@ pub struct FileDuplication {
     pub path: String,
 }
 
+#[derive(Debug, Clone)]
+pub struct BatchDuplication {
+    pub batch_id: u64,
+    pub files: Vec<FileDuplication>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExtractArchive {
+    pub file_id: u64,
+    pub filename: String,
+    pub path: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct BreakPoint {
     pub file_id: u64,
@@ -143,6 +252,141 @@ pub fn should_retry(attempt: u32) -> bool {
     attempt < MAX_RECONNECT_RETRIES
 }
 
+/// Open a parallel data stream of the session, on a connection of its own so it is
+/// not limited by the congestion window of the control stream.
+async fn open_data_stream(
+    encryptor: &Encryptor,
+    endpoint: &Endpoint,
+    character: Character,
+    slot: u32,
+    stream: u32,
+) -> Result<(
+    RelayServiceClient<tonic::transport::Channel>,
+    mpsc::Sender<RelayUpdate>,
+    Streaming<RelayUpdate>,
+)> {
+    let mut client = RelayServiceClient::connect(endpoint.clone()).await?;
+    let (tx, rx) = mpsc::channel(256);
+    let join = RelayMessage::Join(Id {
+        encrypted_share_code: encryptor.encrypt_share_code_bytes(),
+        character: character.into(),
+        stream,
+        slot,
+    });
+    send_msg_to_relay(&tx, join).await?;
+    let messages = client.channel(ReceiverStream::new(rx)).await?.into_inner();
+    Ok((client, tx, messages))
+}
+
+/// Chunk size used to hash a pre-existing file of `file_size` bytes for resuming.
+fn resume_chunk_size(file_size: u64) -> u64 {
+    let mut chunk_size = SEND_BUFF_SIZE as u64;
+    while file_size.div_ceil(chunk_size) > MAX_RESUME_CHUNKS {
+        chunk_size *= 2;
+    }
+    chunk_size
+}
+
+/// BLAKE3 hash of every `chunk_size` chunk read from `reader`, the last chunk may be shorter.
+async fn hash_chunks<R: AsyncRead + Unpin>(
+    mut reader: R,
+    chunk_size: u64,
+) -> Result<Vec<Bytes>> {
+    let mut hashes = Vec::new();
+    let mut buffer = Vec::with_capacity(SEND_BUFF_SIZE);
+    loop {
+        let mut hasher = blake3::Hasher::new();
+        let mut chunk_len = 0;
+        while chunk_len < chunk_size {
+            buffer.clear();
+            let read_length = (&mut reader).take((chunk_size - chunk_len).min(SEND_BUFF_SIZE as u64)).read_to_end(&mut buffer).await?;
+            if read_length == 0 {
+                break;
+            }
+            hasher.update(&buffer);
+            chunk_len += read_length as u64;
+        }
+        if chunk_len == 0 {
+            return Ok(hashes);
+        }
+        hashes.push(Bytes::copy_from_slice(hasher.finalize().as_bytes()));
+        if chunk_len < chunk_size {
+            return Ok(hashes);
+        }
+    }
+}
+
+/// Byte ranges whose chunk hashes differ between `local` and `remote`, adjacent chunks are merged.
+fn mismatched_ranges(
+    local: &[Bytes],
+    remote: &[Bytes],
+    chunk_size: u64,
+) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for (i, hash) in local.iter().enumerate() {
+        if remote.get(i) == Some(hash) {
+            continue;
+        }
+        let start = i as u64 * chunk_size;
+        match ranges.last_mut() {
+            Some((_, end)) if *end == start => *end = start + chunk_size,
+            _ => ranges.push((start, start + chunk_size)),
+        }
+    }
+    ranges
+}
+
+/// Compress a chunk with zstd, `None` when the chunk is incompressible or saves less than an eighth.
+fn compress_chunk(chunk: &[u8]) -> Option<Vec<u8>> {
+    if chunk_entropy(&chunk[..chunk.len().min(ENTROPY_PROBE_SIZE)]) > MAX_COMPRESSIBLE_ENTROPY {
+        // already compressed or encrypted data
+        return None;
+    }
+    let compressed = zstd::bulk::compress(chunk, COMPRESSION_LEVEL).ok()?;
+    (compressed.len() < chunk.len() - chunk.len() / 8).then_some(compressed)
+}
+
+/// Decompress a chunk, chunks never exceed the max chunk size which bounds the output.
+fn decompress_chunk(chunk: &[u8]) -> Result<Vec<u8>> {
+    Ok(zstd::bulk::decompress(chunk, MAX_CHUNK_SIZE)?)
+}
+
+/// Decompress a batch of small files, bounded by the most bytes a sender encodes in a batch.
+fn decompress_batch(batch: &[u8]) -> Result<Vec<u8>> {
+    Ok(zstd::bulk::decompress(batch, sender::MAX_BATCH_DATA_SIZE)?)
+}
+
+/// Associated data of a sealed batch, binds the batch id and whether it is compressed.
+fn batch_aad(
+    batch_id: u64,
+    compressed: bool,
+) -> [u8; 9] {
+    let mut aad = [0u8; 9];
+    aad[..8].copy_from_slice(&batch_id.to_be_bytes());
+    aad[8] = compressed as u8;
+    aad
+}
+
+/// Shannon entropy of the bytes in bits per byte, the maximum for no bytes.
+fn chunk_entropy(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 8.0;
+    }
+    let mut counts = [0u32; 256];
+    for &byte in bytes {
+        counts[byte as usize] += 1;
+    }
+    let len = bytes.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
 /// Send message to relay
 pub async fn send_msg_to_relay(
     tx: &mpsc::Sender<RelayUpdate>,
@@ -1,7 +1,8 @@
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    io,
     net::SocketAddr,
     path::{Path, PathBuf},
     pin::Pin,
@@ -10,38 +11,96 @@ use std::{
 };
 
 use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use prost::Message;
 use tokio::{
     fs,
-    io::{AsyncSeekExt, AsyncWriteExt, SeekFrom},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom},
     sync::{mpsc, oneshot},
 };
 use tokio_stream::{Stream, StreamExt, wrappers::ReceiverStream as TokioReceiverStream};
+use tokio_util::io::{StreamReader, SyncIoBridge};
 use tonic::transport::Endpoint;
 
 use flash_cat_common::{
-    Shutdown, compare_versions,
-    consts::{PUBLIC_RELAY, SEND_BUFF_SIZE},
-    crypt::encryptor::Encryptor,
+    Pause, Shutdown, compare_versions,
+    consts::{MAX_CHUNK_SIZE, MAX_DATA_STREAMS, MAX_TEXT_SIZE, PUBLIC_RELAY, SEND_BUFF_SIZE},
+    crypt::{
+        encryptor::{Encryptor, FileCipher, MailboxKdf, SessionCipher},
+        pake::{Role, SessionKey},
+    },
     proto::{
-        BreakPointConfirm, Character, ClientType, CloseRequest, Confirm, Done, FileConfirm, FileResumeProgress, Id, JoinRequest, NewFileConfirm,
-        ReceiverUpdate, RelayUpdate, ResumeState, file_confirm::ConfirmMessage, join_response, receiver_update::ReceiverMessage,
-        relay_service_client::RelayServiceClient, relay_update::RelayMessage, sender_update::SenderMessage,
+        BatchConfirm, BatchedFile, BatchedFiles, BreakPointConfirm, CancelFile, Capabilities, Character, ChunkHashes, ClientType, CloseRequest, Confirm, Done,
+        EntryKind, Features, FileConfirm, FileData, FileDone, FileManifest, FileMetadata, FileResumeProgress, FileSelection, Id, JoinRequest, KeyExchange,
+        NewFileConfirm, PauseState, ReceiverUpdate, RelayUpdate, ResumeState, SelectedFiles, SenderUpdate, TextPayload, TransferSummary,
+        file_confirm::ConfirmMessage, join_response, receiver_update::ReceiverMessage, relay_service_client::RelayServiceClient, relay_update::RelayMessage,
+        sender_update::SenderMessage,
     },
     utils::{
-        fs::{missing_chunks, safe_join_relative_path},
+        fs::{FileAttributes, STDIO_PATH, extract_tar, reset_path, safe_join_relative_path, safe_link_target, unzip},
         net::net_scout::NetScout,
+        rate_limit::RateLimiter,
     },
 };
 use flash_cat_relay::built_info;
 
 use crate::{
-    BreakPoint, FileDuplication, PING_INTERVAL, Progress, ReceiverConfirm, ReceiverInteractionMessage, RecvNewFile, RelayType, SendFilesRequest, get_endpoint,
-    normalize_relay_endpoint, send_msg_to_relay,
+    BatchDuplication, BreakPoint, ExtractArchive, FileDuplication, ManifestEntry, PING_INTERVAL, Progress, ReceiverConfirm, ReceiverInteractionMessage,
+    RecvNewFile, RelayType, SendFilesRequest, batch_aad, decompress_batch, decompress_chunk, get_endpoint, hash_chunks,
+    journal::{JOURNAL_SYNC_INTERVAL, ResumeJournal},
+    normalize_relay_endpoint, open_data_stream, resume_chunk_size, send_msg_to_relay,
 };
 
+/// Suffix of the file a receiving file is written to until it is verified.
+pub const PART_FILE_SUFFIX: &str = ".flashcat.part";
+
+/// Most bytes of file data held while paused, the receiver stops reading from the relay
+/// once as much is held: 64MiB.
+const MAX_HELD_SIZE: usize = 64 * 1024 * 1024;
+
+/// Most bytes of the chunks of a file held back until the chunks before them, striped
+/// across other data streams, are received: 32MiB.
+const MAX_PENDING_SIZE: usize = 32 * 1024 * 1024;
+
 /// Receiver stream
 pub type ReceiverStream = Pin<Box<dyn Stream<Item = ReceiverInteractionMessage> + Send>>;
 
+/// Messages from the relay, the file data held back to the rate limit.
+type RelayMessages = Pin<Box<dyn Stream<Item = Result<RelayUpdate, tonic::Status>> + Send>>;
+
+/// Files of a batch rejected so far and the ones waiting to be overwritten, with their paths.
+type PendingBatch = (Vec<u64>, Vec<(PathBuf, BatchedFile)>);
+
+/// How the files are received, set by the user.
+#[derive(Debug, Clone)]
+pub struct ReceiveOptions {
+    /// Relay to connect to instead of the one found on the LAN or the public one.
+    pub specify_relay: Option<String>,
+    pub client_type: ClientType,
+    /// Look for the local relay of the sender on the LAN.
+    pub lan: bool,
+    pub preserve_attributes: bool,
+    pub extract_archives: bool,
+    /// Keep the partial file of a cancelled file for a later transfer to resume from.
+    pub keep_partial: bool,
+    /// Limit of the bytes received per second.
+    pub limit_rate: Option<u64>,
+}
+
+impl Default for ReceiveOptions {
+    fn default() -> Self {
+        Self {
+            specify_relay: None,
+            client_type: ClientType::Cli,
+            lan: false,
+            preserve_attributes: false,
+            extract_archives: false,
+            keep_partial: false,
+            limit_rate: None,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct FlashCatReceiver {
     encryptor: Arc<Encryptor>,
@@ -49,29 +108,44 @@ pub struct FlashCatReceiver {
     confirm_tx: async_channel::Sender<ReceiverConfirm>,
     confirm_rx: async_channel::Receiver<ReceiverConfirm>,
     output_dir: PathBuf,
+    /// The received file is written to stdout instead of under the output directory.
+    to_stdout: bool,
     client_type: ClientType,
     lan: bool,
+    preserve_attributes: bool,
+    extract_archives: bool,
+    /// Keep the partial file of a cancelled file for a later transfer to resume from.
+    keep_partial: bool,
+    /// Limit of the bytes received per second.
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Paused by this end, the relay channel stays open meanwhile.
+    pause: Pause,
     shutdown: Shutdown,
 }
 
 impl FlashCatReceiver {
     pub fn new(
         share_code: String,
-        specify_relay: Option<String>,
         output: Option<String>,
-        client_type: ClientType,
-        lan: bool,
+        options: ReceiveOptions,
     ) -> Result<Self> {
         let encryptor = Arc::new(Encryptor::new(share_code)?);
         let (confirm_tx, confirm_rx) = async_channel::bounded(10);
+        let to_stdout = output.as_deref() == Some(STDIO_PATH);
         Ok(Self {
             encryptor,
-            specify_relay,
+            specify_relay: options.specify_relay,
             confirm_tx,
             confirm_rx,
-            output_dir: output.map(PathBuf::from).unwrap_or_default(),
-            client_type,
-            lan,
+            output_dir: output.filter(|_| !to_stdout).map(PathBuf::from).unwrap_or_default(),
+            to_stdout,
+            client_type: options.client_type,
+            lan: options.lan,
+            preserve_attributes: options.preserve_attributes,
+            extract_archives: options.extract_archives,
+            keep_partial: options.keep_partial,
+            rate_limiter: options.limit_rate.map(|rate| Arc::new(RateLimiter::new(rate))),
+            pause: Pause::new(),
             shutdown: Shutdown::new(),
         })
     }
@@ -131,7 +205,7 @@ impl FlashCatReceiver {
     }
 
     async fn connect_relay(
-        &self,
+        self: &Arc<Self>,
         relay_type: RelayType,
         endpoint: Endpoint,
         receiver_stream_tx: mpsc::Sender<ReceiverInteractionMessage>,
@@ -144,9 +218,14 @@ impl FlashCatReceiver {
                 id: Some(Id {
                     encrypted_share_code: self.encryptor.encrypt_share_code_bytes(),
                     character: Character::Receiver.into(),
+                    stream: 0,
+                    slot: 0,
                 }),
                 client_type: self.client_type.into(),
                 sender_local_relay: None,
+                max_receivers: 0,
+                keep_alive: false,
+                mailbox_salt: Bytes::new(),
             })
             .await
         {
@@ -161,12 +240,15 @@ impl FlashCatReceiver {
             }
         };
 
-        let (relay, sender_local_relay, client_latest_version) = if let Some(join_response_message) = resp.into_inner().join_response_message {
+        let (relay, sender_local_relay, client_latest_version, slot, mailbox) = if let Some(join_response_message) = resp.into_inner().join_response_message {
             match join_response_message {
                 join_response::JoinResponseMessage::Success(join_success) => (
                     join_success.relay,
                     join_success.sender_local_relay,
                     join_success.client_latest_version,
+                    join_success.slot,
+                    // the sender is gone, the transfer was stored on the relay
+                    (!join_success.mailbox_salt.is_empty()).then_some(join_success.mailbox_salt),
                 ),
                 join_response::JoinResponseMessage::Failed(join_failed) => {
                     bail!(join_failed.error_msg);
@@ -243,11 +325,17 @@ impl FlashCatReceiver {
             }
         };
 
-        let encryptor = self.encryptor.clone();
-        let confirm_rx = self.confirm_rx.clone();
-        let output_dir = self.output_dir.clone();
+        if mailbox.is_some() {
+            let _ = Self::send_msg_to_stream(
+                &receiver_stream_tx,
+                ReceiverInteractionMessage::Message("Collecting the transfer stored on the relay".to_string()),
+            )
+            .await;
+        }
+
+        let receiver = self.clone();
         tokio::spawn(async move {
-            if let Err(e) = Self::relay_channel(encryptor, endpoint, &receiver_stream_tx, confirm_rx, output_dir, shutdown).await {
+            if let Err(e) = receiver.relay_channel(endpoint, &receiver_stream_tx, shutdown, slot, mailbox).await {
                 let _ = &receiver_stream_tx.send(ReceiverInteractionMessage::Error(e.to_string())).await;
             }
         });
@@ -258,10 +346,12 @@ impl FlashCatReceiver {
     async fn establish_channel(
         encryptor: &Encryptor,
         endpoint: &Endpoint,
+        slot: u32,
+        rate_limiter: Option<Arc<RateLimiter>>,
     ) -> Result<(
         RelayServiceClient<tonic::transport::Channel>,
         mpsc::Sender<RelayUpdate>,
-        tonic::Streaming<RelayUpdate>,
+        RelayMessages,
     )> {
         let mut client = RelayServiceClient::connect(endpoint.clone()).await?;
 
@@ -270,6 +360,8 @@ impl FlashCatReceiver {
         let join = RelayMessage::Join(Id {
             encrypted_share_code: encryptor.encrypt_share_code_bytes(),
             character: Character::Receiver.into(),
+            stream: 0,
+            slot,
         });
         tx.send(RelayUpdate {
             relay_message: Some(join),
@@ -277,30 +369,95 @@ impl FlashCatReceiver {
         .await?;
 
         let resp = client.channel(TokioReceiverStream::new(rx)).await?;
-        let messages = resp.into_inner();
+        let messages = rate_limited(resp.into_inner(), rate_limiter);
 
         Ok((client, tx, messages))
     }
 
     async fn relay_channel(
-        encryptor: Arc<Encryptor>,
+        &self,
         endpoint: Endpoint,
         receiver_stream_tx: &mpsc::Sender<ReceiverInteractionMessage>,
-        confirm_rx: async_channel::Receiver<ReceiverConfirm>,
-        output_dir: PathBuf,
         shutdown: Shutdown,
+        slot: u32,
+        mailbox: Option<Bytes>,
     ) -> Result<()> {
-        let (mut client, mut tx, mut messages) = Self::establish_channel(&encryptor, &endpoint).await?;
+        let (mut client, mut tx, mut messages) = Self::establish_channel(&self.encryptor, &endpoint, slot, self.rate_limiter.clone()).await?;
 
         let mut recv_files: HashMap<u64, RecvFile> = HashMap::new();
+        // applied once a file is verified, directories once all of their children are written
+        let mut file_attributes: HashMap<u64, (FileAttributes, u32)> = HashMap::new();
+        let mut dir_attributes: Vec<(PathBuf, FileAttributes)> = Vec::new();
+        // zipped folders being received, and the received ones waiting for the answer to extract them
+        let mut zipped_folders: HashSet<u64> = HashSet::new();
+        let mut pending_extractions: HashMap<u64, PathBuf> = HashMap::new();
+        let mut transfer_done = false;
+        // files of a batch that already exist, waiting for the answer to overwrite them
+        let mut pending_batches: HashMap<u64, PendingBatch> = HashMap::new();
+        // opened once the manifest fingerprint of the sender is known
+        let mut journal: Option<ResumeJournal> = None;
+
+        let mut pending_key: Option<SessionKey> = None;
+        // a stored transfer has no peer to exchange keys with, its key is derived from the share
+        // code with the parameters stored along
+        let mut cipher: Option<SessionCipher> = match mailbox.as_ref() {
+            Some(params) => {
+                let kdf = MailboxKdf::decode(params)?;
+                let encryptor = self.encryptor.clone();
+                Some(tokio::task::spawn_blocking(move || encryptor.mailbox_cipher(&kdf)).await??)
+            }
+            None => None,
+        };
+        // files selected from the manifest, a stored transfer holds them all
+        let mut selected: Option<HashSet<u64>> = None;
+        // set once resumed from the journal, the manifest is only resent for the directories
+        let mut resuming = false;
+
+        // chunks received on the parallel data streams, and file done messages held back
+        // until the chunks striped across them are all written
+        let mut relay_streams = false;
+        let (data_tx, mut data_rx) = mpsc::channel(256);
+        let (replay_tx, mut replay_rx) = mpsc::unbounded_channel();
+        let mut deferred_done: HashMap<u64, FileDone> = HashMap::new();
+        // partial files of a destination that exists, resumed once replacing it is confirmed
+        let mut pending_break_points: HashMap<u64, BreakPoint> = HashMap::new();
+        // hard links waiting for the files before them to be written out
+        let mut held_links: Vec<RelayMessage> = Vec::new();
+        // the file data received while paused, written in order once resumed
+        let mut pause_rx = self.pause.subscribe();
+        let mut held: VecDeque<RelayMessage> = VecDeque::new();
+        let mut held_size = 0;
+        // files cancelled by either end, the data still on its way for them is dropped
+        let mut cancelled: HashSet<u64> = HashSet::new();
 
         let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+        let mut journal_interval = tokio::time::interval(JOURNAL_SYNC_INTERVAL);
         let mut reconnect_attempt = 0u32;
         loop {
-            let message = tokio::select! {
+            if !held_links.is_empty() && deferred_done.is_empty() && held.is_empty() {
+                // after the file done messages replayed before
+                for message in held_links.drain(..) {
+                    replay_tx.send(message)?;
+                }
+            }
+            let resumed = if self.pause.is_paused() {
+                None
+            } else {
+                held.pop_front()
+            };
+            if let Some(message) = resumed.as_ref() {
+                held_size -= message.encoded_len();
+            }
+            // once as much is held, nothing is read until resumed and the sender is held back
+            let reading = held_size < MAX_HELD_SIZE;
+            let message = match resumed {
+                Some(message) => message,
+                None => tokio::select! {
                 _ = shutdown.wait() => {
                     let _ = client.close(CloseRequest {
-                        encrypted_share_code: encryptor.encrypt_share_code_bytes(),
+                        encrypted_share_code: self.encryptor.encrypt_share_code_bytes(),
+                        character: Character::Receiver.into(),
+                        slot,
                     })
                     .await;
                     return Ok(());
@@ -309,15 +466,74 @@ impl FlashCatReceiver {
                     let _ = send_msg_to_relay(&tx, RelayMessage::Ping(0)).await;
                     continue;
                 }
-                Ok(confirm) = confirm_rx.recv() => {
+                _ = journal_interval.tick(), if journal.is_some() => {
+                    if let Some(journal) = journal.as_mut() {
+                        for (&file_id, recv_file) in recv_files.iter_mut() {
+                            journal.confirm(file_id, recv_file.sync().await?);
+                        }
+                        Self::save_journal(journal, receiver_stream_tx).await;
+                    }
+                    continue;
+                }
+                Ok(()) = pause_rx.changed() => {
+                    let paused = *pause_rx.borrow_and_update();
+                    // the sender accepts nothing before the key exchange
+                    if cipher.is_some() {
+                        let _ = send_msg_to_relay(
+                            &tx,
+                            RelayMessage::Receiver(ReceiverUpdate {
+                                receiver_message: Some(ReceiverMessage::PauseState(PauseState {
+                                    paused,
+                                })),
+                            }),
+                        )
+                        .await;
+                    }
+                    Self::send_msg_to_stream(receiver_stream_tx, ReceiverInteractionMessage::Paused(paused)).await?;
+                    continue;
+                }
+                Some(message) = replay_rx.recv() => message,
+                Some(message) = data_rx.recv(), if reading => message,
+                Ok(confirm) = self.confirm_rx.recv() => {
                     match confirm {
                         ReceiverConfirm::ReceiveConfirm(accept) => {
                             if accept {
-                                let share_accept = RelayMessage::Receiver(ReceiverUpdate {
-                                    receiver_message: Some(ReceiverMessage::ShareConfirm(
-                                        Confirm::Accept.into(),
-                                    )),
-                                });
+                                // a stored transfer is replayed from the start
+                                let resume_files = match journal.as_mut().filter(|_| mailbox.is_none()) {
+                                    Some(journal) => {
+                                        Self::resume_from_journal(journal, &cipher, &self.output_dir, &mut recv_files, receiver_stream_tx).await?
+                                    }
+                                    None => Vec::new(),
+                                };
+                                let share_accept = if resume_files.is_empty() {
+                                    RelayMessage::Receiver(ReceiverUpdate {
+                                        receiver_message: Some(ReceiverMessage::ShareConfirm(
+                                            Confirm::Accept.into(),
+                                        )),
+                                    })
+                                } else {
+                                    // the sender skips completed files and continues partial ones from the journal,
+                                    // of the files selected before
+                                    let sealed_selection = match journal.as_ref().and_then(|journal| journal.selected()) {
+                                        Some(file_ids) => {
+                                            selected = Some(file_ids.iter().copied().collect());
+                                            session_cipher(&cipher)?.seal(
+                                                &SelectedFiles {
+                                                    file_ids: file_ids.to_vec(),
+                                                },
+                                                b"selection",
+                                            )?
+                                        }
+                                        None => Bytes::new(),
+                                    };
+                                    resuming = true;
+                                    RelayMessage::Receiver(ReceiverUpdate {
+                                        receiver_message: Some(ReceiverMessage::ResumeState(ResumeState {
+                                            files: resume_files,
+                                            sealed_selection,
+                                        })),
+                                    })
+                                };
                                 send_msg_to_relay(&tx, share_accept).await?;
                             } else {
                                 let share_reject = RelayMessage::Receiver(ReceiverUpdate {
@@ -329,6 +545,14 @@ impl FlashCatReceiver {
                             }
                         }
                         ReceiverConfirm::FileConfirm((accept, file_id)) => {
+                            if let Some(recv_file) = recv_files.get_mut(&file_id) {
+                                recv_file.overwrite = accept;
+                            }
+                            let break_point = pending_break_points.remove(&file_id);
+                            if let Some(break_point) = break_point.as_ref().filter(|_| accept) {
+                                Self::send_msg_to_stream(receiver_stream_tx, ReceiverInteractionMessage::BreakPoint(break_point.clone())).await?;
+                                continue;
+                            }
                             let file_confirm = if accept {
                                 RelayMessage::Receiver(ReceiverUpdate {
                                     receiver_message: Some(ReceiverMessage::FileConfirm(
@@ -358,13 +582,29 @@ impl FlashCatReceiver {
                             };
                             send_msg_to_relay(&tx, file_confirm).await?;
                             if !accept {
-                                if let Some(mut recv_file) = recv_files.remove(&file_id) {
-                                    recv_file.finish().await?;
+                                if let Some(recv_file) = recv_files.remove(&file_id) {
+                                    if break_point.is_some() {
+                                        // the partial file of an earlier transfer is left as it was
+                                        recv_file.keep().await?;
+                                    } else {
+                                        recv_file.discard().await?;
+                                    }
+                                }
+                                if let Some(journal) = journal.as_mut() {
+                                    journal.untrack(file_id);
                                 }
                             }
                         }
-                        ReceiverConfirm::BreakPointConfirm((accept, file_id, position)) => {
+                        ReceiverConfirm::BreakPointConfirm((accept, file_id)) => {
+                            if cancelled.contains(&file_id) {
+                                continue;
+                            }
+                            let Some(recv_file) = recv_files.get_mut(&file_id) else {
+                                bail!("receive file failed");
+                            };
                             let break_point_confirm = if accept {
+                                // the sender compares the hashes and only sends the chunks that differ
+                                let chunk_hashes = recv_file.chunk_hashes().await?;
                                 RelayMessage::Receiver(ReceiverUpdate {
                                     receiver_message: Some(ReceiverMessage::FileConfirm(
                                         FileConfirm {
@@ -372,13 +612,14 @@ impl FlashCatReceiver {
                                                 BreakPointConfirm {
                                                     file_id: file_id,
                                                     confirm: Confirm::Accept.into(),
-                                                    position: position,
+                                                    sealed_chunk_hashes: session_cipher(&cipher)?.seal(&chunk_hashes, &file_id.to_be_bytes())?,
                                                 },
                                             )),
                                         },
                                     )),
                                 })
                             } else {
+                                recv_file.restart().await?;
                                 RelayMessage::Receiver(ReceiverUpdate {
                                     receiver_message: Some(ReceiverMessage::FileConfirm(
                                         FileConfirm {
@@ -386,7 +627,7 @@ impl FlashCatReceiver {
                                                 BreakPointConfirm {
                                                     file_id: file_id,
                                                     confirm: Confirm::Reject.into(),
-                                                    position: 0,
+                                                    sealed_chunk_hashes: Bytes::new(),
                                                 },
                                             )),
                                         },
@@ -394,16 +635,74 @@ impl FlashCatReceiver {
                                 })
                             };
                             send_msg_to_relay(&tx, break_point_confirm).await?;
-                            if !accept {
-                                if let Some(recv_file) = recv_files.get_mut(&file_id) {
-                                    recv_file.restart().await?;
+                        }
+                        ReceiverConfirm::ExtractConfirm((extract, file_id)) => {
+                            if let Some(path) = pending_extractions.remove(&file_id) {
+                                if extract {
+                                    Self::extract_zipped_folder(path, self.output_dir.clone(), receiver_stream_tx).await;
+                                }
+                                if transfer_done && pending_extractions.is_empty() {
+                                    Self::send_msg_to_stream(receiver_stream_tx, ReceiverInteractionMessage::ReceiveDone).await?;
+                                }
+                            }
+                        }
+                        ReceiverConfirm::BatchConfirm((overwrite, batch_id)) => {
+                            let Some((mut rejected, files)) = pending_batches.remove(&batch_id) else {
+                                continue;
+                            };
+                            for (absolute_path, file) in files {
+                                if overwrite && !cancelled.contains(&file.file_id) {
+                                    Self::receive_batched_file(&absolute_path, file, self.preserve_attributes, &mut journal, receiver_stream_tx).await?;
+                                } else {
+                                    rejected.push(file.file_id);
                                 }
                             }
+                            if let Some(journal) = journal.as_ref() {
+                                Self::save_journal(journal, receiver_stream_tx).await;
+                            }
+                            Self::confirm_batch(&tx, batch_id, rejected).await?;
+                        }
+                        ReceiverConfirm::CancelFile(file_id) => {
+                            if cancelled.insert(file_id) {
+                                send_msg_to_relay(
+                                    &tx,
+                                    RelayMessage::Receiver(ReceiverUpdate {
+                                        receiver_message: Some(ReceiverMessage::CancelFile(CancelFile {
+                                            file_id,
+                                        })),
+                                    }),
+                                )
+                                .await?;
+                                deferred_done.remove(&file_id);
+                                pending_break_points.remove(&file_id);
+                                file_attributes.remove(&file_id);
+                                zipped_folders.remove(&file_id);
+                                Self::cancel_file(file_id, &mut recv_files, &mut journal, self.keep_partial, receiver_stream_tx).await?;
+                            }
+                        }
+                        ReceiverConfirm::SelectFiles(file_ids) => {
+                            selected = Some(file_ids.iter().copied().collect());
+                            if let Some(journal) = journal.as_mut() {
+                                journal.select(&file_ids);
+                                Self::save_journal(journal, receiver_stream_tx).await;
+                            }
+                            let selection = SelectedFiles {
+                                file_ids,
+                            };
+                            send_msg_to_relay(
+                                &tx,
+                                RelayMessage::Receiver(ReceiverUpdate {
+                                    receiver_message: Some(ReceiverMessage::FileSelection(FileSelection {
+                                        sealed_selection: session_cipher(&cipher)?.seal(&selection, b"selection")?,
+                                    })),
+                                }),
+                            )
+                            .await?;
                         }
                     }
                     continue;
                 }
-                item = messages.next() => {
+                item = messages.next(), if reading => {
                     match item {
                         Some(Ok(update)) => {
                             match update.relay_message {
@@ -418,6 +717,10 @@ impl FlashCatReceiver {
                             if shutdown.is_terminated() {
                                 return Ok(());
                             }
+                            if mailbox.is_some() {
+                                // the stored transfer is kept, collecting it again starts over
+                                bail!("connection to the relay lost, the stored transfer may be collected again");
+                            }
 
                             let result = loop {
                                 if !crate::should_retry(reconnect_attempt) {
@@ -441,7 +744,7 @@ impl FlashCatReceiver {
                                     return Ok(());
                                 }
 
-                                match Self::establish_channel(&encryptor, &endpoint).await {
+                                match Self::establish_channel(&self.encryptor, &endpoint, slot, self.rate_limiter.clone()).await {
                                     Ok(result) => break result,
                                     Err(e) => {
                                         let _ = Self::send_msg_to_stream(
@@ -470,112 +773,405 @@ impl FlashCatReceiver {
                         }
                     }
                 }
+                },
             };
 
+            if data_file_id(&message).is_some_and(|file_id| cancelled.contains(&file_id)) {
+                continue;
+            }
+
+            // only the file data waits, the files keep being confirmed meanwhile
+            if self.pause.is_paused() && data_file_id(&message).is_some() {
+                held_size += message.encoded_len();
+                held.push_back(message);
+                continue;
+            }
+
             match message {
                 RelayMessage::Join(_) => receiver_stream_tx.send(ReceiverInteractionMessage::Message("Invalid join message".to_string())).await?,
                 RelayMessage::Joined(_) => (),
-                RelayMessage::Ready(_) => (),
+                RelayMessage::Ready(ready) => {
+                    relay_streams = ready.parallel_streams;
+                }
                 RelayMessage::Sender(sender) => {
                     if let Some(sender_message) = sender.sender_message {
+                        if cipher.is_none() && !matches!(sender_message, SenderMessage::KeyExchange(_)) {
+                            // fail closed, nothing is accepted before the key exchange is confirmed
+                            bail!("received sender message before key exchange completed");
+                        }
                         match sender_message {
+                            SenderMessage::KeyExchange(peer) => {
+                                if !peer.public_share.is_empty() {
+                                    let exchange = self.encryptor.key_exchange(Role::Receiver);
+                                    let session_key = exchange.finish(&peer.public_share).map_err(|e| anyhow!("key exchange failed: {e}"))?;
+                                    send_msg_to_relay(
+                                        &tx,
+                                        RelayMessage::Receiver(ReceiverUpdate {
+                                            receiver_message: Some(ReceiverMessage::KeyExchange(KeyExchange {
+                                                public_share: exchange.public_share(),
+                                                confirm: session_key.confirmation(),
+                                            })),
+                                        }),
+                                    )
+                                    .await?;
+                                    pending_key = Some(session_key);
+                                } else {
+                                    let session_key = pending_key.take().ok_or_else(|| anyhow!("unexpected key exchange message"))?;
+                                    session_key.verify_peer(&peer.confirm).map_err(|e| anyhow!("key exchange failed: {e}"))?;
+                                    let established = session_key.cipher();
+                                    // the sender only compresses chunks for receivers announcing support,
+                                    // stdout takes a single file asked for on its own
+                                    let features = Features {
+                                        zstd_chunks: true,
+                                        file_batches: !self.to_stdout,
+                                        parallel_streams: relay_streams,
+                                    };
+                                    send_msg_to_relay(
+                                        &tx,
+                                        RelayMessage::Receiver(ReceiverUpdate {
+                                            receiver_message: Some(ReceiverMessage::Capabilities(Capabilities {
+                                                sealed_features: established.seal(&features, b"features")?,
+                                            })),
+                                        }),
+                                    )
+                                    .await?;
+                                    cipher = Some(established);
+                                    // paused before the key exchange or across a reconnect, the sender is only told now
+                                    if self.pause.is_paused() {
+                                        send_msg_to_relay(
+                                            &tx,
+                                            RelayMessage::Receiver(ReceiverUpdate {
+                                                receiver_message: Some(ReceiverMessage::PauseState(PauseState {
+                                                    paused: true,
+                                                })),
+                                            }),
+                                        )
+                                        .await?;
+                                    }
+                                }
+                            }
                             SenderMessage::SendRequest(send_req) => {
+                                let summary: TransferSummary = session_cipher(&cipher)?
+                                    .open(send_req.sealed_summary.as_ref(), b"summary")
+                                    .map_err(|e| anyhow!("decrypt send request failed: {e}"))?;
+                                if !send_req.sealed_text.is_empty() {
+                                    let payload: TextPayload = session_cipher(&cipher)?
+                                        .open(send_req.sealed_text.as_ref(), b"text")
+                                        .map_err(|e| anyhow!("decrypt text failed: {e}"))?;
+                                    if payload.text.len() > MAX_TEXT_SIZE {
+                                        bail!(
+                                            "the text sent is {} bytes, at most {MAX_TEXT_SIZE} bytes are accepted",
+                                            payload.text.len()
+                                        );
+                                    }
+                                    // the sender is done once the text is sent, no files follow
+                                    Self::send_msg_to_stream(receiver_stream_tx, ReceiverInteractionMessage::Text(payload.text)).await?;
+                                    continue;
+                                }
+                                if !self.to_stdout && journal.is_none() && !summary.manifest_fingerprint.is_empty() {
+                                    journal = Some(ResumeJournal::open(&self.output_dir, &summary.manifest_fingerprint).await);
+                                }
                                 Self::send_msg_to_stream(
                                     receiver_stream_tx,
                                     ReceiverInteractionMessage::SendFilesRequest(SendFilesRequest {
-                                        total_size: send_req.total_size,
-                                        num_files: send_req.num_files,
-                                        num_folders: send_req.num_folders,
-                                        max_file_name_length: send_req.max_file_name_length,
+                                        total_size: summary.total_size,
+                                        num_files: summary.num_files,
+                                        num_folders: summary.num_folders,
+                                        max_file_name_length: summary.max_file_name_length,
                                     }),
                                 )
                                 .await?;
                             }
+                            SenderMessage::Manifest(manifest) => {
+                                let manifest: FileManifest = session_cipher(&cipher)?
+                                    .open(manifest.sealed_manifest.as_ref(), b"manifest")
+                                    .map_err(|e| anyhow!("decrypt manifest failed: {e}"))?;
+                                let mut entries = Vec::with_capacity(manifest.entries.len());
+                                for entry in manifest.entries {
+                                    // refuse the manifest as a whole if any path would escape the output directory
+                                    safe_join_relative_path(&self.output_dir, entry.relative_path.as_str())?;
+                                    entries.push(ManifestEntry {
+                                        file_id: entry.file_id,
+                                        relative_path: entry.relative_path,
+                                        size: entry.size,
+                                        is_empty_dir: entry.is_empty_dir,
+                                    });
+                                }
+                                if self.preserve_attributes {
+                                    for directory in manifest.directories {
+                                        let absolute_path = safe_join_relative_path(&self.output_dir, directory.relative_path.as_str())?;
+                                        dir_attributes.push((absolute_path, directory.attributes.unwrap_or_default().into()));
+                                    }
+                                }
+                                if resuming {
+                                    continue;
+                                }
+                                let num_files = entries.iter().filter(|entry| !entry.is_empty_dir).count();
+                                if self.to_stdout && num_files > 1 {
+                                    bail!("only a single file can be written to stdout, {num_files} were sent");
+                                }
+                                Self::send_msg_to_stream(receiver_stream_tx, ReceiverInteractionMessage::Manifest(entries)).await?;
+                            }
                             SenderMessage::NewFileRequest(new_file_req) => {
-                                let accept_msg = RelayMessage::Receiver(ReceiverUpdate {
-                                    receiver_message: Some(ReceiverMessage::FileConfirm(FileConfirm {
-                                        confirm_message: Some(ConfirmMessage::NewFileConfirm(NewFileConfirm {
+                                // nothing about the file is known before the metadata is decrypted
+                                let metadata: FileMetadata = session_cipher(&cipher)?
+                                    .open(new_file_req.sealed_metadata.as_ref(), &new_file_req.file_id.to_be_bytes())
+                                    .map_err(|e| anyhow!("decrypt file metadata failed: {e}"))?;
+                                if cancelled.contains(&new_file_req.file_id)
+                                    || selected.as_ref().is_some_and(|selected| !selected.contains(&new_file_req.file_id))
+                                {
+                                    // sent before the sender learned about the cancel, or stored but not selected
+                                    send_msg_to_relay(&tx, new_file_confirm(new_file_req.file_id, Confirm::Reject)).await?;
+                                    continue;
+                                }
+                                // the file a hard link shares the content of may still be a part file, its
+                                // chunks still on their way or the data held while paused
+                                if metadata.kind() == EntryKind::Hardlink && (!deferred_done.is_empty() || !held.is_empty()) {
+                                    held_links.push(RelayMessage::Sender(SenderUpdate {
+                                        sender_message: Some(SenderMessage::NewFileRequest(new_file_req)),
+                                    }));
+                                    continue;
+                                }
+                                let accept_msg = new_file_confirm(new_file_req.file_id, Confirm::Accept);
+                                if self.to_stdout {
+                                    if metadata.is_empty_dir || matches!(metadata.kind(), EntryKind::Symlink | EntryKind::Hardlink) {
+                                        send_msg_to_relay(&tx, new_file_confirm(new_file_req.file_id, Confirm::Reject)).await?;
+                                        continue;
+                                    }
+                                    Self::send_msg_to_stream(
+                                        receiver_stream_tx,
+                                        ReceiverInteractionMessage::RecvNewFile(RecvNewFile {
                                             file_id: new_file_req.file_id,
-                                            confirm: Confirm::Accept.into(),
-                                        })),
-                                    })),
-                                });
+                                            filename: metadata.filename.clone(),
+                                            path: STDIO_PATH.to_string(),
+                                            size: metadata.total_size,
+                                        }),
+                                    )
+                                    .await?;
+                                    recv_files.insert(
+                                        new_file_req.file_id,
+                                        RecvFile::stdout(file_cipher(&cipher, new_file_req.file_id)?),
+                                    );
+                                    send_msg_to_relay(&tx, accept_msg).await?;
+                                    continue;
+                                }
 
-                                let absolute_path = safe_join_relative_path(&output_dir, new_file_req.relative_path.as_str())?;
-                                if new_file_req.is_empty_dir {
+                                let absolute_path = safe_join_relative_path(&self.output_dir, metadata.relative_path.as_str())?;
+                                let attributes = metadata.attributes.clone().filter(|_| self.preserve_attributes).map(FileAttributes::from);
+                                if metadata.is_empty_dir {
                                     tokio::fs::create_dir_all(&absolute_path).await?;
                                     send_msg_to_relay(&tx, accept_msg).await?;
                                     continue;
                                 }
+                                if matches!(metadata.kind(), EntryKind::Symlink | EntryKind::Hardlink) {
+                                    let confirm = match Self::create_link(&self.output_dir, &absolute_path, &metadata).await {
+                                        Ok(()) => {
+                                            // a hard link shares the attributes of the file it links to
+                                            if let Some(attributes) = attributes.filter(|_| metadata.kind() == EntryKind::Symlink) {
+                                                Self::apply_attributes(&absolute_path, &attributes, None, receiver_stream_tx).await;
+                                            }
+                                            Confirm::Accept
+                                        }
+                                        Err(e) => {
+                                            Self::send_msg_to_stream(
+                                                receiver_stream_tx,
+                                                ReceiverInteractionMessage::Message(format!("Skip link {}: {e}", absolute_path.to_string_lossy())),
+                                            )
+                                            .await?;
+                                            Confirm::Reject
+                                        }
+                                    };
+                                    let link_confirm = RelayMessage::Receiver(ReceiverUpdate {
+                                        receiver_message: Some(ReceiverMessage::FileConfirm(FileConfirm {
+                                            confirm_message: Some(ConfirmMessage::NewFileConfirm(NewFileConfirm {
+                                                file_id: new_file_req.file_id,
+                                                confirm: confirm.into(),
+                                            })),
+                                        })),
+                                    });
+                                    send_msg_to_relay(&tx, link_confirm).await?;
+                                    continue;
+                                }
 
                                 Self::send_msg_to_stream(
                                     receiver_stream_tx,
                                     ReceiverInteractionMessage::RecvNewFile(RecvNewFile {
                                         file_id: new_file_req.file_id,
-                                        filename: new_file_req.filename.clone(),
+                                        filename: metadata.filename.clone(),
                                         path: absolute_path.to_string_lossy().to_string(),
-                                        size: new_file_req.total_size,
+                                        size: metadata.total_size,
                                     }),
                                 )
                                 .await?;
 
-                                if absolute_path.exists() {
-                                    let recv_file_len = fs::metadata(&absolute_path).await?.len();
-
-                                    let recv_file = RecvFile::new(fs::File::options().write(true).read(true).open(&absolute_path).await?, 0).await?;
+                                let part_path = part_file_path(&absolute_path);
+                                let part_len = fs::metadata(&part_path).await.map(|metadata| metadata.len()).unwrap_or(0);
+                                if part_len > 0 && part_len < metadata.total_size {
+                                    let recv_file = RecvFile::new(
+                                        file_cipher(&cipher, new_file_req.file_id)?,
+                                        absolute_path.clone(),
+                                        fs::File::options().write(true).read(true).open(&part_path).await?,
+                                        0,
+                                    )
+                                    .await?;
                                     recv_files.insert(new_file_req.file_id, recv_file);
+                                    if let Some(journal) = journal.as_mut() {
+                                        journal.track(new_file_req.file_id, &metadata.relative_path, metadata.total_size, 0);
+                                    }
 
-                                    if recv_file_len == new_file_req.total_size {
-                                        // Breakpoint exists, continue receiving
-                                        if let Ok((saved_chunks, missing_chunks, percent)) = missing_chunks(&absolute_path, SEND_BUFF_SIZE) {
-                                            if missing_chunks > 0 && saved_chunks > 0 && percent > 0.0 {
-                                                Self::send_msg_to_stream(
-                                                    receiver_stream_tx,
-                                                    ReceiverInteractionMessage::BreakPoint(BreakPoint {
-                                                        file_id: new_file_req.file_id,
-                                                        filename: new_file_req.filename.clone(),
-                                                        position: (saved_chunks * SEND_BUFF_SIZE) as u64,
-                                                        percent,
-                                                    }),
-                                                )
-                                                .await?;
-                                                continue;
-                                            }
-                                        }
+                                    // Breakpoint exists, the chunks are compared by hash on resume
+                                    let percent = (part_len as f64 / metadata.total_size as f64 * 100.0 * 100.0).round() / 100.0;
+                                    let break_point = BreakPoint {
+                                        file_id: new_file_req.file_id,
+                                        filename: metadata.filename.clone(),
+                                        position: part_len,
+                                        percent,
+                                    };
+                                    if absolute_path.exists() {
+                                        // resumed once replacing the original is confirmed
+                                        pending_break_points.insert(new_file_req.file_id, break_point);
+                                        Self::send_msg_to_stream(
+                                            receiver_stream_tx,
+                                            ReceiverInteractionMessage::FileDuplication(FileDuplication {
+                                                file_id: new_file_req.file_id,
+                                                filename: metadata.filename.clone(),
+                                                path: absolute_path.to_string_lossy().to_string(),
+                                            }),
+                                        )
+                                        .await?;
+                                    } else {
+                                        Self::send_msg_to_stream(receiver_stream_tx, ReceiverInteractionMessage::BreakPoint(break_point)).await?;
+                                    }
+                                    continue;
+                                }
+
+                                let parent = absolute_path.parent().unwrap_or(Path::new(""));
+                                if !parent.exists() && !parent.to_string_lossy().is_empty() {
+                                    fs::create_dir_all(parent).await?;
+                                }
+                                // readable as well, the writer may need to hash the file again
+                                let file_instance = fs::File::options().read(true).write(true).create(true).truncate(true).open(&part_path).await?;
+                                #[cfg(unix)]
+                                {
+                                    file_instance
+                                        .set_permissions(if metadata.file_mode > 0 {
+                                            std::fs::Permissions::from_mode(metadata.file_mode)
+                                        } else {
+                                            // Set as the default permissions of the file
+                                            std::fs::Permissions::from_mode(0o644)
+                                        })
+                                        .await?;
+                                }
+
+                                let mut recv_file = RecvFile::new(
+                                    file_cipher(&cipher, new_file_req.file_id)?,
+                                    absolute_path.clone(),
+                                    file_instance,
+                                    0,
+                                )
+                                .await?;
+                                if metadata.kind() == EntryKind::ZippedFolder {
+                                    zipped_folders.insert(new_file_req.file_id);
+                                }
+                                let archive = metadata.kind() == EntryKind::Archive;
+                                if archive && self.extract_archives {
+                                    recv_file.extractor = Some(ArchiveExtractor::new(self.output_dir.clone()));
+                                }
+                                recv_files.insert(new_file_req.file_id, recv_file);
+                                if let Some(journal) = journal.as_mut() {
+                                    // an archive is built anew on every transfer and stdin is read only once,
+                                    // neither is resumed across sessions
+                                    if !archive && metadata.kind() != EntryKind::Stream {
+                                        journal.track(new_file_req.file_id, &metadata.relative_path, metadata.total_size, 0);
                                     }
+                                }
+                                if let Some(attributes) = attributes {
+                                    file_attributes.insert(new_file_req.file_id, (attributes, metadata.file_mode));
+                                }
 
+                                if absolute_path.exists() && !(archive && self.extract_archives) {
+                                    // the original is only replaced once the new file is received and verified
                                     Self::send_msg_to_stream(
                                         receiver_stream_tx,
                                         ReceiverInteractionMessage::FileDuplication(FileDuplication {
                                             file_id: new_file_req.file_id,
-                                            filename: new_file_req.filename.clone(),
+                                            filename: metadata.filename.clone(),
                                             path: absolute_path.to_string_lossy().to_string(),
                                         }),
                                     )
                                     .await?;
                                 } else {
-                                    let parent = absolute_path.parent().unwrap_or(Path::new(""));
-                                    if !parent.exists() && !parent.to_string_lossy().is_empty() {
-                                        fs::create_dir_all(parent).await?;
+                                    send_msg_to_relay(&tx, accept_msg).await?;
+                                }
+                            }
+                            SenderMessage::FileBatch(file_batch) => {
+                                let data = session_cipher(&cipher)?
+                                    .decrypt_with_aad(
+                                        file_batch.sealed_files.as_ref(),
+                                        &batch_aad(file_batch.batch_id, file_batch.compressed),
+                                    )
+                                    .map_err(|e| anyhow!("decrypt file batch failed: {e}"))?;
+                                let data = if file_batch.compressed {
+                                    decompress_batch(&data).map_err(|e| anyhow!("decompress failed: {e}"))?
+                                } else {
+                                    data
+                                };
+                                let batch = BatchedFiles::decode(data.as_slice())?;
+                                // refuse the batch as a whole if any path would escape the output directory
+                                let mut files = Vec::with_capacity(batch.files.len());
+                                for file in batch.files {
+                                    let relative_path = file.metadata.as_ref().map(|metadata| metadata.relative_path.as_str()).unwrap_or_default();
+                                    files.push((safe_join_relative_path(&self.output_dir, relative_path)?, file));
+                                }
+                                let mut rejected = Vec::new();
+                                let mut conflicts = Vec::new();
+                                for (absolute_path, file) in files {
+                                    // cancelled before the batch went out
+                                    if cancelled.contains(&file.file_id) {
+                                        rejected.push(file.file_id);
+                                        continue;
                                     }
-                                    let file_instance = fs::File::create(&absolute_path).await?;
-                                    #[cfg(unix)]
-                                    {
-                                        file_instance
-                                            .set_permissions(if new_file_req.file_mode > 0 {
-                                                std::fs::Permissions::from_mode(new_file_req.file_mode)
-                                            } else {
-                                                // Set as the default permissions of the file
-                                                std::fs::Permissions::from_mode(0o644)
-                                            })
-                                            .await?;
+                                    Self::send_msg_to_stream(
+                                        receiver_stream_tx,
+                                        ReceiverInteractionMessage::RecvNewFile(RecvNewFile {
+                                            file_id: file.file_id,
+                                            filename: absolute_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                                            path: absolute_path.to_string_lossy().to_string(),
+                                            size: file.content.len() as u64,
+                                        }),
+                                    )
+                                    .await?;
+                                    if absolute_path.exists() {
+                                        conflicts.push((absolute_path, file));
+                                    } else {
+                                        Self::receive_batched_file(&absolute_path, file, self.preserve_attributes, &mut journal, receiver_stream_tx).await?;
                                     }
-
-                                    let recv_file = RecvFile::new(file_instance, 0).await?;
-                                    recv_files.insert(new_file_req.file_id, recv_file);
-
-                                    send_msg_to_relay(&tx, accept_msg).await?;
                                 }
+                                if let Some(journal) = journal.as_ref() {
+                                    Self::save_journal(journal, receiver_stream_tx).await;
+                                }
+                                if conflicts.is_empty() {
+                                    Self::confirm_batch(&tx, file_batch.batch_id, rejected).await?;
+                                    continue;
+                                }
+                                // a single answer covers every existing file of the batch
+                                let files = conflicts
+                                    .iter()
+                                    .map(|(absolute_path, file)| FileDuplication {
+                                        file_id: file.file_id,
+                                        filename: absolute_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                                        path: absolute_path.to_string_lossy().to_string(),
+                                    })
+                                    .collect();
+                                pending_batches.insert(file_batch.batch_id, (rejected, conflicts));
+                                Self::send_msg_to_stream(
+                                    receiver_stream_tx,
+                                    ReceiverInteractionMessage::BatchDuplication(BatchDuplication {
+                                        batch_id: file_batch.batch_id,
+                                        files,
+                                    }),
+                                )
+                                .await?;
                             }
                             SenderMessage::BreakPoint(break_point) => {
                                 if !recv_files.contains_key(&break_point.file_id) {
@@ -584,39 +1180,128 @@ impl FlashCatReceiver {
                                 let recv_file = recv_files.get_mut(&break_point.file_id).unwrap();
                                 recv_file.seek(break_point.position).await?;
                             }
-                            SenderMessage::FileData(file_data) => {
-                                if !recv_files.contains_key(&file_data.file_id) {
-                                    bail!("receive file failed");
+                            SenderMessage::PauseState(pause_state) => {
+                                Self::send_msg_to_stream(receiver_stream_tx, ReceiverInteractionMessage::PausedByPeer(pause_state.paused)).await?;
+                            }
+                            SenderMessage::CancelFile(cancel_file) => {
+                                let file_id = cancel_file.file_id;
+                                if cancelled.insert(file_id) {
+                                    deferred_done.remove(&file_id);
+                                    pending_break_points.remove(&file_id);
+                                    file_attributes.remove(&file_id);
+                                    zipped_folders.remove(&file_id);
+                                    Self::cancel_file(file_id, &mut recv_files, &mut journal, self.keep_partial, receiver_stream_tx).await?;
                                 }
-                                let recv_file = recv_files.get_mut(&file_data.file_id).unwrap();
-                                let encryptor = encryptor.clone();
-                                let data = match encryptor.decrypt(file_data.data.as_ref()) {
-                                    Ok(data) => data,
-                                    Err(e) => {
-                                        bail!(format!("decrypt failed: {e}"));
+                            }
+                            SenderMessage::OpenStreams(open_streams) => {
+                                for stream in 1..=open_streams.count.min(MAX_DATA_STREAMS) {
+                                    if let Err(e) = self.spawn_data_stream(&endpoint, slot, stream, data_tx.clone(), shutdown.clone()).await {
+                                        Self::send_msg_to_stream(
+                                            receiver_stream_tx,
+                                            ReceiverInteractionMessage::Message(format!("Open data stream {stream} failed: {e}")),
+                                        )
+                                        .await?;
                                     }
+                                }
+                            }
+                            SenderMessage::FileData(file_data) => {
+                                let file_id = file_data.file_id;
+                                let Some(recv_file) = recv_files.get_mut(&file_id) else {
+                                    bail!("receive file failed");
                                 };
-                                recv_file.write(data).await?;
+                                recv_file.write(file_data).await?;
                                 Self::send_msg_to_stream(
                                     receiver_stream_tx,
                                     ReceiverInteractionMessage::FileProgress(Progress {
-                                        file_id: file_data.file_id,
+                                        file_id,
                                         position: recv_file.get_progress(),
                                     }),
                                 )
                                 .await?;
+                                if deferred_done.get(&file_id).is_some_and(|file_done| file_done.length <= recv_file.get_progress()) {
+                                    let file_done = deferred_done.remove(&file_id).unwrap();
+                                    replay_tx.send(RelayMessage::Sender(SenderUpdate {
+                                        sender_message: Some(SenderMessage::FileDone(file_done)),
+                                    }))?;
+                                }
                             }
                             SenderMessage::FileDone(file_done) => {
-                                if !recv_files.contains_key(&file_done.file_id) {
+                                let Some(recv_file) = recv_files.get(&file_done.file_id) else {
                                     bail!("receive file failed");
+                                };
+                                if file_done.length > recv_file.get_progress() {
+                                    // chunks striped across the data streams are still on their way
+                                    deferred_done.insert(file_done.file_id, file_done);
+                                    continue;
                                 }
                                 let mut recv_file = recv_files.remove(&file_done.file_id).unwrap();
-                                recv_file.finish().await?; // notify and wait for writer Task
-                                Self::send_msg_to_stream(
-                                    receiver_stream_tx,
-                                    ReceiverInteractionMessage::FileProgressFinish(file_done.file_id),
-                                )
-                                .await?;
+                                let path = recv_file.path.clone();
+                                let digest = recv_file.finish(file_done.size).await?; // notify and wait for writer Task
+                                let expected = session_cipher(&cipher)?
+                                    .decrypt_with_aad(file_done.sealed_digest.as_ref(), &file_done.file_id.to_be_bytes())
+                                    .map_err(|e| anyhow!("decrypt file digest failed: {e}"))?;
+                                let extracted = match recv_file.extractor.take() {
+                                    Some(extractor) => Some(extractor.finish().await),
+                                    None => None,
+                                };
+                                if let Some(Err(e)) = &extracted {
+                                    Self::send_msg_to_stream(
+                                        receiver_stream_tx,
+                                        ReceiverInteractionMessage::Message(format!("Extract {} failed: {e}", path.to_string_lossy())),
+                                    )
+                                    .await?;
+                                }
+                                let verified = digest == expected[..] && extracted.as_ref().is_none_or(|result| result.is_ok());
+                                let attributes = file_attributes.remove(&file_done.file_id);
+                                let mut persisted = false;
+                                if verified && extracted.is_some() {
+                                    // the archive is not kept once extracted
+                                    recv_file.discard().await?;
+                                } else if verified {
+                                    persisted = recv_file.persist().await?;
+                                    if !persisted {
+                                        // showed up since, or left from before a restart
+                                        Self::send_msg_to_stream(
+                                            receiver_stream_tx,
+                                            ReceiverInteractionMessage::Message(format!(
+                                                "{} exists, the received file is kept as {}",
+                                                path.to_string_lossy(),
+                                                part_file_path(&path).to_string_lossy()
+                                            )),
+                                        )
+                                        .await?;
+                                    } else if let Some((attributes, mode)) = attributes {
+                                        Self::apply_attributes(&path, &attributes, Some(mode).filter(|mode| *mode > 0), receiver_stream_tx).await;
+                                    }
+                                } else {
+                                    recv_file.discard().await?;
+                                }
+                                if let Some(journal) = journal.as_mut() {
+                                    if verified {
+                                        journal.complete(file_done.file_id);
+                                    } else {
+                                        journal.untrack(file_done.file_id);
+                                    }
+                                    Self::save_journal(journal, receiver_stream_tx).await;
+                                }
+                                let msg = if verified {
+                                    ReceiverInteractionMessage::FileProgressFinish(file_done.file_id)
+                                } else {
+                                    ReceiverInteractionMessage::FileVerifyFailed(file_done.file_id)
+                                };
+                                Self::send_msg_to_stream(receiver_stream_tx, msg).await?;
+                                if zipped_folders.remove(&file_done.file_id) && persisted {
+                                    Self::send_msg_to_stream(
+                                        receiver_stream_tx,
+                                        ReceiverInteractionMessage::ExtractArchive(ExtractArchive {
+                                            file_id: file_done.file_id,
+                                            filename: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                                            path: path.to_string_lossy().to_string(),
+                                        }),
+                                    )
+                                    .await?;
+                                    pending_extractions.insert(file_done.file_id, path);
+                                }
                             }
                             SenderMessage::ResumeRequest(_) => {
                                 // Sender reconnected and asks for current file progress
@@ -628,12 +1313,22 @@ impl FlashCatReceiver {
                                         completed: false,
                                     });
                                 }
+                                if let Some(journal) = journal.as_ref() {
+                                    for (file_id, entry) in journal.entries().filter(|(_, entry)| entry.completed) {
+                                        files.push(FileResumeProgress {
+                                            file_id,
+                                            received_bytes: entry.size,
+                                            completed: true,
+                                        });
+                                    }
+                                }
                                 // Reply with ResumeState
                                 send_msg_to_relay(
                                     &tx,
                                     RelayMessage::Receiver(ReceiverUpdate {
                                         receiver_message: Some(ReceiverMessage::ResumeState(ResumeState {
                                             files,
+                                            sealed_selection: Bytes::new(),
                                         })),
                                     }),
                                 )
@@ -650,8 +1345,22 @@ impl FlashCatReceiver {
                     .await?;
                 }
                 RelayMessage::Done(_) => {
+                    if let Some(journal) = journal.take() {
+                        journal.remove().await?;
+                    }
+                    // children come after their parents in the manifest
+                    for (path, attributes) in dir_attributes.drain(..).rev() {
+                        if path.is_dir() {
+                            Self::apply_attributes(&path, &attributes, None, receiver_stream_tx).await;
+                        }
+                    }
                     send_msg_to_relay(&tx, RelayMessage::Done(Done {})).await?;
-                    Self::send_msg_to_stream(receiver_stream_tx, ReceiverInteractionMessage::ReceiveDone).await?;
+                    if pending_extractions.is_empty() {
+                        Self::send_msg_to_stream(receiver_stream_tx, ReceiverInteractionMessage::ReceiveDone).await?;
+                    } else {
+                        // done once every offered extraction is answered
+                        transfer_done = true;
+                    }
                 }
                 RelayMessage::Error(e) => {
                     receiver_stream_tx.send(ReceiverInteractionMessage::Error(e.to_string())).await?;
@@ -665,6 +1374,295 @@ impl FlashCatReceiver {
         }
     }
 
+    /// Stop receiving a cancelled file, its partial file is kept for a later transfer to
+    /// resume from if `keep_partial`, otherwise removed.
+    async fn cancel_file(
+        file_id: u64,
+        recv_files: &mut HashMap<u64, RecvFile>,
+        journal: &mut Option<ResumeJournal>,
+        keep_partial: bool,
+        receiver_stream_tx: &mpsc::Sender<ReceiverInteractionMessage>,
+    ) -> Result<()> {
+        let Some(recv_file) = recv_files.remove(&file_id) else {
+            return Ok(());
+        };
+        if keep_partial {
+            let synced = recv_file.keep().await?;
+            if let Some(journal) = journal.as_mut() {
+                journal.confirm(file_id, synced);
+            }
+        } else {
+            recv_file.discard().await?;
+            if let Some(journal) = journal.as_mut() {
+                journal.untrack(file_id);
+            }
+        }
+        if let Some(journal) = journal.as_ref() {
+            Self::save_journal(journal, receiver_stream_tx).await;
+        }
+        Self::send_msg_to_stream(receiver_stream_tx, ReceiverInteractionMessage::FileCancelled(file_id)).await
+    }
+
+    /// Reopen the files left partial by a previous session, returns the progress
+    /// offered to the sender. Files that changed on disk since are received again.
+    async fn resume_from_journal(
+        journal: &mut ResumeJournal,
+        cipher: &Option<SessionCipher>,
+        output_dir: &Path,
+        recv_files: &mut HashMap<u64, RecvFile>,
+        receiver_stream_tx: &mpsc::Sender<ReceiverInteractionMessage>,
+    ) -> Result<Vec<FileResumeProgress>> {
+        let entries = journal.entries().map(|(file_id, entry)| (file_id, entry.clone())).collect::<Vec<_>>();
+        let mut files = Vec::new();
+        for (file_id, entry) in entries {
+            let absolute_path = safe_join_relative_path(output_dir, entry.relative_path.as_str())?;
+            if entry.completed {
+                if fs::metadata(&absolute_path).await.is_ok_and(|metadata| metadata.len() == entry.size) {
+                    files.push(FileResumeProgress {
+                        file_id,
+                        received_bytes: entry.size,
+                        completed: true,
+                    });
+                } else {
+                    journal.untrack(file_id);
+                }
+                continue;
+            }
+            let part_path = part_file_path(&absolute_path);
+            let part_len = fs::metadata(&part_path).await.map(|metadata| metadata.len()).ok();
+            if entry.confirmed == 0 || part_len.is_none_or(|part_len| part_len < entry.confirmed) {
+                journal.untrack(file_id);
+                continue;
+            }
+
+            let recv_file = RecvFile::new(
+                file_cipher(cipher, file_id)?,
+                absolute_path.clone(),
+                fs::File::options().write(true).read(true).open(&part_path).await?,
+                entry.confirmed,
+            )
+            .await?;
+            recv_files.insert(file_id, recv_file);
+            Self::send_msg_to_stream(
+                receiver_stream_tx,
+                ReceiverInteractionMessage::RecvNewFile(RecvNewFile {
+                    file_id,
+                    filename: absolute_path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+                    path: absolute_path.to_string_lossy().to_string(),
+                    size: entry.size,
+                }),
+            )
+            .await?;
+            Self::send_msg_to_stream(
+                receiver_stream_tx,
+                ReceiverInteractionMessage::FileProgress(Progress {
+                    file_id,
+                    position: entry.confirmed,
+                }),
+            )
+            .await?;
+            files.push(FileResumeProgress {
+                file_id,
+                received_bytes: entry.confirmed,
+                completed: false,
+            });
+        }
+        Ok(files)
+    }
+
+    /// Recreate a symbolic or hard link, links pointing outside of the output directory are refused.
+    async fn create_link(
+        output_dir: &Path,
+        absolute_path: &Path,
+        metadata: &FileMetadata,
+    ) -> Result<()> {
+        if fs::symlink_metadata(absolute_path).await.is_ok() {
+            bail!("already exists");
+        }
+        let parent = absolute_path.parent().unwrap_or(Path::new(""));
+        if !parent.exists() && !parent.to_string_lossy().is_empty() {
+            fs::create_dir_all(parent).await?;
+        }
+        if metadata.kind() == EntryKind::Hardlink {
+            let original = safe_join_relative_path(output_dir, metadata.link_target.as_str())?;
+            fs::hard_link(original, absolute_path).await?;
+            return Ok(());
+        }
+        // the link keeps its original target, which is only resolved to check it
+        #[cfg_attr(unix, allow(unused_variables))]
+        let resolved = safe_link_target(output_dir, metadata.relative_path.as_str(), metadata.link_target.as_str())?;
+        let target = reset_path(metadata.link_target.as_str());
+        #[cfg(unix)]
+        fs::symlink(target, absolute_path).await?;
+        #[cfg(windows)]
+        if resolved.is_dir() {
+            fs::symlink_dir(target, absolute_path).await?;
+        } else {
+            fs::symlink_file(target, absolute_path).await?;
+        }
+        Ok(())
+    }
+
+    /// Join the parallel data stream `stream`, the chunks received on it are passed on to `data_tx`.
+    async fn spawn_data_stream(
+        &self,
+        endpoint: &Endpoint,
+        slot: u32,
+        stream: u32,
+        data_tx: mpsc::Sender<RelayMessage>,
+        shutdown: Shutdown,
+    ) -> Result<()> {
+        let (client, tx, messages) = open_data_stream(&self.encryptor, endpoint, Character::Receiver, slot, stream).await?;
+        let mut messages = rate_limited(messages, self.rate_limiter.clone());
+        tokio::spawn(async move {
+            // the stream stays open as long as both are held
+            let _client = client;
+            let _tx = tx;
+            loop {
+                let message = tokio::select! {
+                    _ = shutdown.wait() => return,
+                    message = messages.next() => match message {
+                        Some(Ok(update)) => update.relay_message,
+                        Some(Err(_)) | None => return,
+                    },
+                };
+                // only chunks are sent on a data stream
+                if let Some(message @ RelayMessage::Sender(_)) = message {
+                    if data_tx.send(message).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Write a file received in a batch through its partial file, the content is
+    /// authenticated with the batch so it needs no further verification.
+    async fn receive_batched_file(
+        absolute_path: &Path,
+        file: BatchedFile,
+        preserve_attributes: bool,
+        journal: &mut Option<ResumeJournal>,
+        receiver_stream_tx: &mpsc::Sender<ReceiverInteractionMessage>,
+    ) -> Result<()> {
+        let metadata = file.metadata.unwrap_or_default();
+        let parent = absolute_path.parent().unwrap_or(Path::new(""));
+        if !parent.exists() && !parent.to_string_lossy().is_empty() {
+            fs::create_dir_all(parent).await?;
+        }
+        let part_path = part_file_path(absolute_path);
+        // on disk before the rename, the journal marks the file complete right after
+        let mut part_file = fs::File::create(&part_path).await?;
+        part_file.write_all(&file.content).await?;
+        part_file.sync_all().await?;
+        drop(part_file);
+        #[cfg(unix)]
+        fs::set_permissions(
+            &part_path,
+            std::fs::Permissions::from_mode(if metadata.file_mode > 0 {
+                metadata.file_mode
+            } else {
+                0o644
+            }),
+        )
+        .await?;
+        fs::rename(&part_path, absolute_path).await?;
+        sync_parent(absolute_path).await?;
+        if let Some(attributes) = metadata.attributes.filter(|_| preserve_attributes) {
+            let mode = Some(metadata.file_mode).filter(|mode| *mode > 0);
+            Self::apply_attributes(absolute_path, &attributes.into(), mode, receiver_stream_tx).await;
+        }
+        if let Some(journal) = journal.as_mut() {
+            journal.track(file.file_id, &metadata.relative_path, metadata.total_size, 0);
+            journal.complete(file.file_id);
+        }
+        Self::send_msg_to_stream(receiver_stream_tx, ReceiverInteractionMessage::FileProgressFinish(file.file_id)).await
+    }
+
+    async fn confirm_batch(
+        tx: &mpsc::Sender<RelayUpdate>,
+        batch_id: u64,
+        rejected: Vec<u64>,
+    ) -> Result<()> {
+        send_msg_to_relay(
+            tx,
+            RelayMessage::Receiver(ReceiverUpdate {
+                receiver_message: Some(ReceiverMessage::FileConfirm(FileConfirm {
+                    confirm_message: Some(ConfirmMessage::BatchConfirm(BatchConfirm {
+                        batch_id,
+                        rejected,
+                    })),
+                })),
+            }),
+        )
+        .await
+    }
+
+    /// Save the journal, a failure is reported but does not stop the transfer.
+    async fn save_journal(
+        journal: &ResumeJournal,
+        receiver_stream_tx: &mpsc::Sender<ReceiverInteractionMessage>,
+    ) {
+        if let Err(e) = journal.save().await {
+            let _ = Self::send_msg_to_stream(
+                receiver_stream_tx,
+                ReceiverInteractionMessage::Message(format!("Save resume journal failed: {e}")),
+            )
+            .await;
+        }
+    }
+
+    /// Extract a received zipped folder into the output directory and remove the archive, a failure is
+    /// reported and the archive kept.
+    async fn extract_zipped_folder(
+        path: PathBuf,
+        output_dir: PathBuf,
+        receiver_stream_tx: &mpsc::Sender<ReceiverInteractionMessage>,
+    ) {
+        let archive = path.clone();
+        let result = match tokio::task::spawn_blocking(move || unzip(&archive, &output_dir)).await {
+            Ok(Ok(())) => fs::remove_file(&path).await.map_err(anyhow::Error::from),
+            Ok(Err(e)) => Err(e),
+            Err(e) => Err(e.into()),
+        };
+        let msg = match result {
+            Ok(()) => format!("Extracted {}", path.to_string_lossy()),
+            Err(e) => format!("Extract {} failed: {e}", path.to_string_lossy()),
+        };
+        let _ = Self::send_msg_to_stream(receiver_stream_tx, ReceiverInteractionMessage::Message(msg)).await;
+    }
+
+    /// Apply the attributes of a received file, a failure is reported but does not fail the transfer.
+    /// The `mode` set on creation is set again, changing the owner may have cleared a part of it.
+    async fn apply_attributes(
+        path: &Path,
+        attributes: &FileAttributes,
+        mode: Option<u32>,
+        receiver_stream_tx: &mpsc::Sender<ReceiverInteractionMessage>,
+    ) {
+        if let Err(e) = attributes.apply(path, mode) {
+            let _ = Self::send_msg_to_stream(
+                receiver_stream_tx,
+                ReceiverInteractionMessage::Message(format!("Apply attributes of {} failed: {e}", path.to_string_lossy())),
+            )
+            .await;
+        }
+    }
+
+    /// Pause the transfer, the sender is told so and the relay channel stays open.
+    pub fn pause(&self) {
+        self.pause.set(true);
+    }
+
+    pub fn resume(&self) {
+        self.pause.set(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause.is_paused()
+    }
+
     pub fn shutdown(&self) {
         self.shutdown.shutdown();
     }
@@ -687,17 +1685,124 @@ enum FileWriteCommand {
     Write(Vec<u8>, oneshot::Sender<Result<u64, String>>),
     Seek(u64, oneshot::Sender<Result<u64, String>>),
     Restart(oneshot::Sender<Result<u64, String>>),
-    Finish,
+    ChunkHashes(oneshot::Sender<Result<ChunkHashes, String>>),
+    Sync(oneshot::Sender<Result<u64, String>>),
+    /// Finish with the final size of the file.
+    Finish(u64),
+}
+
+/// Sync the directory of `path` to disk, so a file renamed into it stays after a crash.
+async fn sync_parent(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::File::open(parent).await?.sync_all().await?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Path of the partial file the data of `path` is written to until it is verified.
+fn part_file_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(PART_FILE_SUFFIX);
+    path.with_file_name(file_name)
+}
+
+fn new_file_confirm(
+    file_id: u64,
+    confirm: Confirm,
+) -> RelayMessage {
+    RelayMessage::Receiver(ReceiverUpdate {
+        receiver_message: Some(ReceiverMessage::FileConfirm(FileConfirm {
+            confirm_message: Some(ConfirmMessage::NewFileConfirm(NewFileConfirm {
+                file_id,
+                confirm: confirm.into(),
+            })),
+        })),
+    })
+}
+
+/// Hold the messages of the relay back to the rate limit by the size of the file data, so
+/// the loop reading them still handles everything else meanwhile.
+fn rate_limited(
+    mut messages: tonic::Streaming<RelayUpdate>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+) -> RelayMessages {
+    let Some(rate_limiter) = rate_limiter else {
+        return Box::pin(messages);
+    };
+    Box::pin(async_stream::stream! {
+        while let Some(update) = messages.next().await {
+            if let Some(size) = update.as_ref().ok().and_then(|update| update.relay_message.as_ref()).and_then(data_size) {
+                rate_limiter.acquire(size).await;
+            }
+            yield update;
+        }
+    })
+}
+
+/// Size of the file data sent in a message, rate limited.
+fn data_size(message: &RelayMessage) -> Option<usize> {
+    match message {
+        RelayMessage::Sender(SenderUpdate {
+            sender_message: Some(SenderMessage::FileData(file_data)),
+        }) => Some(file_data.data.len()),
+        RelayMessage::Sender(SenderUpdate {
+            sender_message: Some(SenderMessage::FileBatch(file_batch)),
+        }) => Some(file_batch.sealed_files.len()),
+        _ => None,
+    }
+}
+
+/// File the data of a sender message belongs to.
+fn data_file_id(message: &RelayMessage) -> Option<u64> {
+    match message {
+        RelayMessage::Sender(SenderUpdate {
+            sender_message: Some(sender_message),
+        }) => match sender_message {
+            SenderMessage::FileData(file_data) => Some(file_data.file_id),
+            SenderMessage::BreakPoint(break_point) => Some(break_point.file_id),
+            SenderMessage::FileDone(file_done) => Some(file_done.file_id),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn session_cipher(cipher: &Option<SessionCipher>) -> Result<&SessionCipher> {
+    cipher.as_ref().ok_or_else(|| anyhow!("missing session key"))
+}
+
+fn file_cipher(
+    cipher: &Option<SessionCipher>,
+    file_id: u64,
+) -> Result<FileCipher> {
+    session_cipher(cipher)?.file_cipher(file_id)
 }
 
 struct RecvFile {
+    cipher: FileCipher,
+    /// Destination of the file, the data is written to its partial file until finished.
+    path: PathBuf,
+    /// Written to stdout in order, there is no partial file.
+    stdout: bool,
+    /// Extracts the file while it is written, only for archives.
+    extractor: Option<ArchiveExtractor>,
     tx: tokio::sync::mpsc::Sender<FileWriteCommand>,
-    writer_handle: Option<tokio::task::JoinHandle<Result<()>>>,
+    writer_handle: Option<tokio::task::JoinHandle<Result<Option<blake3::Hash>>>>,
     progress: u64,
+    /// Chunks received ahead of the current position, by offset.
+    pending: BTreeMap<u64, FileData>,
+    pending_size: usize,
+    /// Set once replacing the file already at the destination is confirmed.
+    overwrite: bool,
 }
 
 impl RecvFile {
     async fn new(
+        cipher: FileCipher,
+        path: PathBuf,
         mut file: fs::File,
         position: u64,
     ) -> Result<Self> {
@@ -705,12 +1810,26 @@ impl RecvFile {
 
         let writer_handle = tokio::spawn(async move {
             let mut progress = position;
+            // written data is hashed as long as it is contiguous from the start of the file,
+            // otherwise the file is hashed again on finish
+            let mut hasher = blake3::Hasher::new();
+            let mut hashed = if position == 0 {
+                Some(0)
+            } else {
+                None
+            };
             file.seek(SeekFrom::Start(position)).await?;
             while let Some(cmd) = rx.recv().await {
                 match cmd {
                     FileWriteCommand::Write(data, ack) => {
                         let result: Result<u64> = async {
                             file.write_all(&data).await?;
+                            if hashed == Some(progress) {
+                                hasher.update(&data);
+                                hashed = Some(progress + data.len() as u64);
+                            } else {
+                                hashed = None;
+                            }
                             progress += data.len() as u64;
                             Ok(progress)
                         }
@@ -751,6 +1870,52 @@ impl RecvFile {
                             file.set_len(0).await?;
                             file.seek(SeekFrom::Start(0)).await?;
                             progress = 0;
+                            hasher.reset();
+                            hashed = Some(0);
+                            Ok(progress)
+                        }
+                        .await;
+
+                        match result {
+                            Ok(progress) => {
+                                let _ = ack.send(Ok(progress));
+                            }
+                            Err(e) => {
+                                let msg = e.to_string();
+                                let _ = ack.send(Err(msg.clone()));
+                                bail!(msg);
+                            }
+                        }
+                    }
+                    FileWriteCommand::ChunkHashes(ack) => {
+                        let result: Result<ChunkHashes> = async {
+                            file.flush().await?;
+                            let chunk_size = resume_chunk_size(file.metadata().await?.len());
+                            file.seek(SeekFrom::Start(0)).await?;
+                            let hashes = hash_chunks(&mut file, chunk_size).await?;
+                            file.seek(SeekFrom::Start(progress)).await?;
+                            Ok(ChunkHashes {
+                                chunk_size,
+                                hashes,
+                            })
+                        }
+                        .await;
+
+                        match result {
+                            Ok(chunk_hashes) => {
+                                let _ = ack.send(Ok(chunk_hashes));
+                            }
+                            Err(e) => {
+                                let msg = e.to_string();
+                                let _ = ack.send(Err(msg.clone()));
+                                bail!(msg);
+                            }
+                        }
+                    }
+                    FileWriteCommand::Sync(ack) => {
+                        let result: Result<u64> = async {
+                            file.flush().await?;
+                            file.sync_data().await?;
                             Ok(progress)
                         }
                         .await;
@@ -766,26 +1931,175 @@ impl RecvFile {
                             }
                         }
                     }
-                    FileWriteCommand::Finish => {
+                    FileWriteCommand::Finish(size) => {
                         file.flush().await?;
-                        break;
+                        file.set_len(size).await?;
+                        file.sync_all().await?;
+                        if hashed != Some(size) {
+                            hasher.reset();
+                            file.seek(SeekFrom::Start(0)).await?;
+                            let mut buffer = vec![0u8; SEND_BUFF_SIZE];
+                            loop {
+                                let n = file.read(&mut buffer).await?;
+                                if n == 0 {
+                                    break;
+                                }
+                                hasher.update(&buffer[..n]);
+                            }
+                        }
+                        return Ok(Some(hasher.finalize()));
                     }
                 }
             }
-            Ok(())
+            Ok(None)
         });
 
         Ok(Self {
+            cipher,
+            path,
+            stdout: false,
+            extractor: None,
             tx,
             writer_handle: Some(writer_handle),
             progress: position,
+            pending: BTreeMap::new(),
+            pending_size: 0,
+            overwrite: false,
         })
     }
 
+    /// Write the file to stdout instead, the chunks are only accepted in order.
+    fn stdout(cipher: FileCipher) -> Self {
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<FileWriteCommand>(1024);
+
+        let writer_handle = tokio::spawn(async move {
+            let mut stdout = tokio::io::stdout();
+            let mut progress = 0;
+            let mut hasher = blake3::Hasher::new();
+            while let Some(cmd) = rx.recv().await {
+                let (result, ack) = match cmd {
+                    FileWriteCommand::Write(data, ack) => {
+                        let result: Result<u64> = async {
+                            stdout.write_all(&data).await?;
+                            hasher.update(&data);
+                            progress += data.len() as u64;
+                            Ok(progress)
+                        }
+                        .await;
+                        (result, ack)
+                    }
+                    // only a resume from where the data stopped is possible
+                    FileWriteCommand::Seek(position, ack) if position == progress => (Ok(progress), ack),
+                    FileWriteCommand::Seek(_, ack) | FileWriteCommand::Restart(ack) => (Err(anyhow!("stdout can't be rewound")), ack),
+                    FileWriteCommand::ChunkHashes(ack) => {
+                        let _ = ack.send(Err("stdout can't be read back".to_string()));
+                        bail!("stdout can't be read back");
+                    }
+                    FileWriteCommand::Sync(ack) => (stdout.flush().await.map(|_| progress).map_err(Into::into), ack),
+                    FileWriteCommand::Finish(_) => {
+                        stdout.flush().await?;
+                        return Ok(Some(hasher.finalize()));
+                    }
+                };
+                match result {
+                    Ok(progress) => {
+                        let _ = ack.send(Ok(progress));
+                    }
+                    Err(e) => {
+                        let msg = e.to_string();
+                        let _ = ack.send(Err(msg.clone()));
+                        bail!(msg);
+                    }
+                }
+            }
+            Ok(None)
+        });
+
+        Self {
+            cipher,
+            path: PathBuf::from(STDIO_PATH),
+            stdout: true,
+            extractor: None,
+            tx,
+            writer_handle: Some(writer_handle),
+            progress: 0,
+            pending: BTreeMap::new(),
+            pending_size: 0,
+            overwrite: false,
+        }
+    }
+
+    /// Decrypt and write a chunk. Chunks striped across the data streams may arrive ahead
+    /// of the current position of this file, they are held back until it is reached.
     async fn write(
         &mut self,
-        data: Vec<u8>,
+        file_data: FileData,
     ) -> Result<()> {
+        if file_data.offset > self.progress {
+            if self.pending_size + file_data.data.len() > MAX_PENDING_SIZE {
+                bail!(
+                    "too many chunks of file {} received ahead of offset {}",
+                    file_data.file_id,
+                    self.progress
+                );
+            }
+            self.pending_size += file_data.data.len();
+            if let Some(replaced) = self.pending.insert(file_data.offset, file_data) {
+                self.pending_size -= replaced.data.len();
+            }
+            return Ok(());
+        }
+        self.write_chunk(&file_data).await?;
+        while let Some(file_data) = self.pending.remove(&self.progress) {
+            self.pending_size -= file_data.data.len();
+            self.write_chunk(&file_data).await?;
+        }
+        Ok(())
+    }
+
+    /// Decrypt and write the next chunk, only the chunk expected at the current
+    /// position of this file is accepted.
+    async fn write_chunk(
+        &mut self,
+        file_data: &FileData,
+    ) -> Result<()> {
+        if file_data.file_id != self.cipher.file_id() {
+            bail!("chunk of file {} written to file {}", file_data.file_id, self.cipher.file_id());
+        }
+        if file_data.offset != self.progress {
+            bail!(
+                "unexpected chunk offset {} of file {}, expected {}",
+                file_data.offset,
+                file_data.file_id,
+                self.progress
+            );
+        }
+        let data = match self.cipher.decrypt_chunk(
+            file_data.nonce.as_ref(),
+            self.progress,
+            file_data.compressed,
+            file_data.data.as_ref(),
+        ) {
+            Ok(data) => data,
+            Err(e) => {
+                bail!(format!("decrypt failed: {e}"));
+            }
+        };
+        let data = if file_data.compressed {
+            decompress_chunk(&data).map_err(|e| anyhow!("decompress failed: {e}"))?
+        } else {
+            data
+        };
+        if data.len() > MAX_CHUNK_SIZE {
+            bail!(
+                "chunk of {} bytes of file {} exceeds the max chunk size",
+                data.len(),
+                file_data.file_id
+            );
+        }
+        if let Some(extractor) = self.extractor.as_mut() {
+            extractor.feed(Bytes::copy_from_slice(&data)).await?;
+        }
         let (ack_tx, ack_rx) = oneshot::channel();
         self.tx.send(FileWriteCommand::Write(data, ack_tx)).await?;
         self.progress = ack_rx.await.map_err(|_| anyhow!("writer task stopped"))?.map_err(anyhow::Error::msg)?;
@@ -796,6 +2110,8 @@ impl RecvFile {
         &mut self,
         position: u64,
     ) -> Result<()> {
+        self.pending.clear();
+        self.pending_size = 0;
         let (ack_tx, ack_rx) = oneshot::channel();
         self.tx.send(FileWriteCommand::Seek(position, ack_tx)).await?;
         self.progress = ack_rx.await.map_err(|_| anyhow!("writer task stopped"))?.map_err(anyhow::Error::msg)?;
@@ -803,23 +2119,233 @@ impl RecvFile {
     }
 
     async fn restart(&mut self) -> Result<()> {
+        self.pending.clear();
+        self.pending_size = 0;
         let (ack_tx, ack_rx) = oneshot::channel();
         self.tx.send(FileWriteCommand::Restart(ack_tx)).await?;
         self.progress = ack_rx.await.map_err(|_| anyhow!("writer task stopped"))?.map_err(anyhow::Error::msg)?;
         Ok(())
     }
 
-    async fn finish(&mut self) -> Result<()> {
-        self.tx.send(FileWriteCommand::Finish).await?;
-        if let Some(handle) = self.writer_handle.take() {
-            if let Err(e) = handle.await {
-                bail!("writer task failed: {}", e);
-            }
+    /// Hashes of the chunks already in the file, offered to the sender for resuming.
+    async fn chunk_hashes(&mut self) -> Result<ChunkHashes> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.tx.send(FileWriteCommand::ChunkHashes(ack_tx)).await?;
+        ack_rx.await.map_err(|_| anyhow!("writer task stopped"))?.map_err(anyhow::Error::msg)
+    }
+
+    /// Sync the written data to disk, returns the position synced up to.
+    async fn sync(&mut self) -> Result<u64> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.tx.send(FileWriteCommand::Sync(ack_tx)).await?;
+        ack_rx.await.map_err(|_| anyhow!("writer task stopped"))?.map_err(anyhow::Error::msg)
+    }
+
+    /// Stop the writer task and remove the partial file, the destination is left untouched.
+    async fn discard(self) -> Result<()> {
+        let Self {
+            path,
+            stdout,
+            tx,
+            writer_handle,
+            ..
+        } = self;
+        drop(tx);
+        if let Some(handle) = writer_handle {
+            handle.await.map_err(|e| anyhow!("writer task failed: {}", e))??;
+        }
+        if !stdout {
+            fs::remove_file(part_file_path(&path)).await?;
         }
         Ok(())
     }
 
+    /// Stop the writer task and keep the partial file, returns the position synced up to.
+    async fn keep(mut self) -> Result<u64> {
+        let synced = self.sync().await?;
+        let Self {
+            tx,
+            writer_handle,
+            ..
+        } = self;
+        drop(tx);
+        if let Some(handle) = writer_handle {
+            handle.await.map_err(|e| anyhow!("writer task failed: {}", e))??;
+        }
+        Ok(synced)
+    }
+
+    /// Move the finished file into place, replacing the original only if confirmed. Returns
+    /// `false` when the partial file is kept as the destination exists.
+    async fn persist(&self) -> Result<bool> {
+        if self.stdout {
+            return Ok(true);
+        }
+        if !self.overwrite && fs::symlink_metadata(&self.path).await.is_ok() {
+            return Ok(false);
+        }
+        fs::rename(part_file_path(&self.path), &self.path).await?;
+        sync_parent(&self.path).await?;
+        Ok(true)
+    }
+
+    /// Finish writing the file of the given final size, returns the digest of the written file.
+    async fn finish(
+        &mut self,
+        size: u64,
+    ) -> Result<blake3::Hash> {
+        self.tx.send(FileWriteCommand::Finish(size)).await?;
+        match self.writer_handle.take() {
+            Some(handle) => match handle.await {
+                Ok(Ok(Some(digest))) => Ok(digest),
+                Ok(Ok(None)) => bail!("writer task stopped"),
+                Ok(Err(e)) => Err(e),
+                Err(e) => bail!("writer task failed: {}", e),
+            },
+            None => bail!("writer task already finished"),
+        }
+    }
+
     fn get_progress(&self) -> u64 {
         self.progress
     }
 }
+
+/// Extracts a tar archive into a folder on a blocking task while the archive is received.
+struct ArchiveExtractor {
+    tx: Option<mpsc::Sender<io::Result<Bytes>>>,
+    handle: tokio::task::JoinHandle<Result<()>>,
+}
+
+impl ArchiveExtractor {
+    fn new(output_dir: PathBuf) -> Self {
+        let (tx, rx) = mpsc::channel(16);
+        let handle = tokio::task::spawn_blocking(move || {
+            let output_dir = if output_dir.as_os_str().is_empty() {
+                // the current directory when no output directory is given
+                PathBuf::from(".")
+            } else {
+                output_dir
+            };
+            std::fs::create_dir_all(&output_dir)?;
+            extract_tar(SyncIoBridge::new(StreamReader::new(TokioReceiverStream::new(rx))), &output_dir)
+        });
+        Self {
+            tx: Some(tx),
+            handle,
+        }
+    }
+
+    /// Hand the next chunk of the archive to the extraction, fails with its error if it stopped.
+    async fn feed(
+        &mut self,
+        data: Bytes,
+    ) -> Result<()> {
+        let sent = match self.tx.as_ref() {
+            Some(tx) => tx.send(Ok(data)).await.is_ok(),
+            None => false,
+        };
+        if !sent {
+            self.tx = None;
+            (&mut self.handle).await??;
+            bail!("archive extraction stopped");
+        }
+        Ok(())
+    }
+
+    /// Wait for the extraction of the complete archive.
+    async fn finish(mut self) -> Result<()> {
+        drop(self.tx.take());
+        self.handle.await?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+    use bytes::Bytes;
+    use tokio::fs;
+
+    use flash_cat_common::{crypt::encryptor::SessionCipher, proto::FileData};
+
+    use super::{MAX_PENDING_SIZE, RecvFile, part_file_path};
+
+    fn chunk(
+        cipher: &SessionCipher,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<FileData> {
+        let nonce = cipher.next_chunk_nonce(1)?;
+        Ok(FileData {
+            file_id: 1,
+            data: Bytes::from(cipher.file_cipher(1)?.encrypt_chunk(&nonce, offset, false, data)?),
+            offset,
+            nonce: Bytes::copy_from_slice(&nonce),
+            compressed: false,
+        })
+    }
+
+    #[tokio::test]
+    async fn writes_chunks_received_out_of_order() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("file");
+        let cipher = SessionCipher::new(&[7; 32]);
+        let file = fs::File::create(part_file_path(&path)).await?;
+        let mut recv_file = RecvFile::new(cipher.file_cipher(1)?, path.clone(), file, 0).await?;
+
+        recv_file.write(chunk(&cipher, 10, b"delta")?).await?;
+        recv_file.write(chunk(&cipher, 5, b"bravo")?).await?;
+        assert_eq!(recv_file.get_progress(), 0);
+        recv_file.write(chunk(&cipher, 0, b"alpha")?).await?;
+        assert_eq!(recv_file.get_progress(), 15);
+        assert_eq!(recv_file.pending_size, 0);
+
+        recv_file.finish(15).await?;
+        assert!(recv_file.persist().await?);
+        assert_eq!(fs::read(&path).await?, b"alphabravodelta");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replaces_the_destination_only_once_confirmed() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("file");
+        fs::write(&path, b"original").await?;
+        let cipher = SessionCipher::new(&[7; 32]);
+        let file = fs::File::create(part_file_path(&path)).await?;
+        let mut recv_file = RecvFile::new(cipher.file_cipher(1)?, path.clone(), file, 0).await?;
+        recv_file.write(chunk(&cipher, 0, b"received")?).await?;
+        recv_file.finish(8).await?;
+
+        assert!(!recv_file.persist().await?);
+        assert_eq!(fs::read(&path).await?, b"original");
+        assert_eq!(fs::read(part_file_path(&path)).await?, b"received");
+        recv_file.overwrite = true;
+        assert!(recv_file.persist().await?);
+        assert_eq!(fs::read(&path).await?, b"received");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuses_too_many_bytes_ahead() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("file");
+        let cipher = SessionCipher::new(&[7; 32]);
+        let file = fs::File::create(part_file_path(&path)).await?;
+        let mut recv_file = RecvFile::new(cipher.file_cipher(1)?, path, file, 0).await?;
+
+        let ahead = |offset| FileData {
+            file_id: 1,
+            data: Bytes::from(vec![0; MAX_PENDING_SIZE / 2]),
+            offset,
+            nonce: Bytes::new(),
+            compressed: false,
+        };
+        recv_file.write(ahead(1)).await?;
+        // the same chunk again takes no more room
+        recv_file.write(ahead(1)).await?;
+        recv_file.write(ahead(2)).await?;
+        assert!(recv_file.write(ahead(3)).await.is_err());
+        Ok(())
+    }
+}
@@ -1,43 +1,292 @@
-use std::{collections::HashMap, net::SocketAddr, path::Path, pin::Pin, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::{Result, bail};
 use bytes::{Bytes, BytesMut};
+use prost::Message;
 use tokio::{
     fs::File,
-    io::{AsyncReadExt, AsyncSeekExt, SeekFrom},
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, SeekFrom},
     signal::ctrl_c,
-    sync::{Semaphore, mpsc, oneshot},
+    sync::{OwnedSemaphorePermit, Semaphore, broadcast, mpsc, oneshot},
+    task::JoinHandle,
 };
 use tokio_stream::{Stream, StreamExt, wrappers::ReceiverStream};
 use tonic::transport::Endpoint;
 
 use flash_cat_common::{
-    Shutdown, compare_versions,
-    consts::{DEFAULT_RELAY_PORT, PUBLIC_RELAY, SEND_BUFF_SIZE},
-    crypt::encryptor::Encryptor,
+    Pause, Shutdown, compare_versions,
+    consts::{DEFAULT_RELAY_PORT, MAX_DATA_STREAMS, MAX_RECEIVERS, MAX_TEXT_SIZE, PUBLIC_RELAY},
+    crypt::{
+        encryptor::{Encryptor, MailboxKdf, SessionCipher},
+        pake::Role,
+    },
+    format::HumanDuration,
     proto::{
-        BreakPoint, Character, ClientType, CloseRequest, Confirm, Done, FileConfirm, FileData, FileDone, Id, JoinRequest, NewFileRequest, RelayInfo,
-        RelayUpdate, SendRequest, SenderUpdate, file_confirm::ConfirmMessage, join_response, receiver_update::ReceiverMessage,
-        relay_service_client::RelayServiceClient, relay_update::RelayMessage, sender_update::SenderMessage,
+        BatchedFile, BatchedFiles, BreakPoint, CancelFile, Character, ChunkHashes, ClientType, CloseRequest, Confirm, DirectoryAttributes, Done, EntryKind,
+        Features, FileBatch, FileConfirm, FileData, FileDone, FileManifest, FileMetadata, Id, JoinRequest, KeyExchange, Manifest, ManifestEntry,
+        NewFileRequest, OpenStreams, PauseState, RelayInfo, RelayUpdate, SelectedFiles, SendRequest, SenderUpdate, TextPayload, TransferSummary,
+        file_confirm::ConfirmMessage, join_response, receiver_update::ReceiverMessage, relay_service_client::RelayServiceClient, relay_update::RelayMessage,
+        sender_update::SenderMessage,
     },
     utils::{
-        fs::{FileCollector, FileInfo, collect_files, is_idr, paths_exist, remove_files, zip_folder},
+        fs::{
+            CollectOptions, FileCollector, FileInfo, FileKind, STDIO_PATH, collect_files_with, collect_stdin, is_idr, paths_exist, remove_files,
+            stream_tar_archive, zip_folder,
+        },
         net::{find_available_port, get_local_ip, net_scout::NetScout},
+        rate_limit::RateLimiter,
     },
 };
 use flash_cat_relay::{built_info, relay::Relay};
 
-use crate::{PING_INTERVAL, Progress, RelayType, SenderInteractionMessage, get_endpoint, normalize_relay_endpoint, send_msg_to_relay};
+use crate::{
+    Download, PING_INTERVAL, Progress, RelayType, SenderInteractionMessage, batch_aad,
+    chunk::{ChunkSizer, PIPELINE_DEPTH},
+    compress_chunk,
+    fanout::Broadcast,
+    get_endpoint, hash_chunks, mismatched_ranges, normalize_relay_endpoint, open_data_stream, send_msg_to_relay,
+};
 
 /// Broadcast local relay addr timeout.
 pub const BROADCAST_TIMEOUT: Duration = Duration::from_secs(60);
 
-/// Maximum number of files to transfer concurrently.
-pub const MAX_CONCURRENT_FILES: usize = 3;
+/// Default number of files transferred concurrently.
+pub const DEFAULT_CONCURRENT_FILES: usize = 3;
 
 /// How long the sender waits for receiver-side file confirmation.
 pub const FILE_CONFIRM_TIMEOUT: Duration = Duration::from_secs(300);
 
+/// Files up to this size are sent in batches, without asking for each file first.
+pub const SMALL_FILE_SIZE: u64 = 64 * 1024;
+
+/// Maximum size of a batch of small files encoded, the metadata included.
+pub const MAX_BATCH_SIZE: usize = 1024 * 1024;
+
+/// Most bytes of a batch encoded once its files are read, they may have grown since
+/// they were batched up to the max batch size.
+pub const MAX_BATCH_DATA_SIZE: usize = 4 * MAX_BATCH_SIZE;
+
+/// Maximum number of files in a batch.
+pub const MAX_BATCH_FILES: usize = 1024;
+
+/// Name the data read from stdin is sent under.
+pub const STDIN_FILE_NAME: &str = "stdin";
+
+/// How long the other receivers of a broadcast may join once the first did, the share is
+/// done once the receivers joined by then are.
+pub const JOIN_WINDOW: Duration = Duration::from_secs(5 * 60);
+
+/// A chunk of a file read ahead of sending.
+struct FileChunk {
+    position: u64,
+    /// Whether the receiver asked for the chunk, otherwise it is only hashed.
+    in_range: bool,
+    data: Bytes,
+}
+
+/// Where the chunks of a file are read from.
+enum ChunkSource {
+    Reader(Pin<Box<dyn AsyncRead + Send>>),
+    /// A file sent to the receivers of a broadcast session, the chunks read for one of
+    /// them are held for the others.
+    Shared {
+        file: File,
+        /// Position of the file, behind the chunks taken from the others.
+        offset: u64,
+        file_id: u64,
+        broadcast: Arc<Broadcast>,
+    },
+}
+
+impl ChunkSource {
+    /// Chunk at `position` of at most `limit` bytes, empty at the end of the file.
+    async fn read(
+        &mut self,
+        position: u64,
+        limit: usize,
+    ) -> Result<Bytes> {
+        match self {
+            ChunkSource::Reader(reader) => read_chunk(reader, limit).await,
+            ChunkSource::Shared {
+                file,
+                offset,
+                file_id,
+                broadcast,
+            } => {
+                if let Some(data) = broadcast.take_chunk(*file_id, position, limit) {
+                    return Ok(data);
+                }
+                if *offset != position {
+                    file.seek(SeekFrom::Start(position)).await?;
+                }
+                let data = read_chunk(file, limit).await?;
+                *offset = position + data.len() as u64;
+                broadcast.hold_chunk(*file_id, position, data.clone());
+                Ok(data)
+            }
+        }
+    }
+}
+
+/// Read up to `limit` bytes, a single read may return less than that.
+async fn read_chunk<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    limit: usize,
+) -> Result<Bytes> {
+    let mut data = BytesMut::with_capacity(limit);
+    while data.len() < limit {
+        if (&mut *reader).take((limit - data.len()) as u64).read_buf(&mut data).await? == 0 {
+            break;
+        }
+    }
+    Ok(data.freeze())
+}
+
+/// Cancellation of the files sent one by one, kept across reconnects. A file cancelled
+/// before it is reached is skipped.
+#[derive(Clone, Default)]
+struct FileCancels(Arc<Mutex<HashMap<u64, Shutdown>>>);
+
+impl FileCancels {
+    /// Token of a file, shut down once the file is cancelled.
+    fn token(
+        &self,
+        file_id: u64,
+    ) -> Shutdown {
+        self.0.lock().unwrap().entry(file_id).or_default().clone()
+    }
+
+    fn cancel(
+        &self,
+        file_id: u64,
+    ) {
+        self.token(file_id).shutdown();
+    }
+}
+
+/// Files sent to the receiver with their sizes, kept across reconnects. The download of a
+/// kept alive share is summed up from them.
+#[derive(Clone, Default)]
+struct SentFiles(Arc<Mutex<HashMap<u64, u64>>>);
+
+impl SentFiles {
+    fn add(
+        &self,
+        file_id: u64,
+        size: u64,
+    ) {
+        self.0.lock().unwrap().insert(file_id, size);
+    }
+
+    /// Number and total size of the files sent.
+    fn total(&self) -> (u64, u64) {
+        let sent = self.0.lock().unwrap();
+        (sent.len() as u64, sent.values().sum())
+    }
+}
+
+/// State of the transfer to a receiver, shared by the files sent in it.
+#[derive(Clone)]
+struct TransferContext {
+    cipher: Arc<SessionCipher>,
+    /// Control stream to the relay.
+    tx: mpsc::Sender<RelayUpdate>,
+    sender_stream_tx: mpsc::Sender<SenderInteractionMessage>,
+    /// Shut down once the receiver is gone.
+    cancel: Shutdown,
+    /// Compress the chunks, the receiver supports it.
+    compress: bool,
+    chunk_sizer: Arc<Mutex<ChunkSizer>>,
+    /// Streams the chunks of a whole file are striped across besides the control stream.
+    data_streams: Arc<Vec<mpsc::Sender<RelayUpdate>>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Set while either end is paused.
+    pause: Pause,
+    cancels: FileCancels,
+    sent: SentFiles,
+    broadcast: Option<Arc<Broadcast>>,
+    /// Confirmations awaited, by file or batch.
+    confirm_waiters: Arc<Mutex<HashMap<u64, oneshot::Sender<FileConfirm>>>>,
+}
+
+/// Serve receivers one after another on the same share code, instead of only the first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeepAlive {
+    /// Downloads done before the share closes, unlimited if `None`.
+    pub max_downloads: Option<u32>,
+    /// How long the share stays open, until closed if `None`.
+    pub expires_in: Option<Duration>,
+}
+
+/// How a share is sent, set by the user.
+#[derive(Debug, Clone)]
+pub struct SendOptions {
+    /// Relay to connect to instead of the local and the public one.
+    pub specify_relay: Option<String>,
+    pub client_type: ClientType,
+    /// Announce the local relay on the LAN.
+    pub lan_broadcast: bool,
+    /// Compress the chunks if the receiver supports it.
+    pub compress: bool,
+    /// Number of files transferred concurrently.
+    pub concurrency: usize,
+    /// Number of streams to the relay, chunks of large files are striped across them.
+    pub streams: usize,
+    /// Limit of the bytes sent per second, shared by all files.
+    pub limit_rate: Option<u64>,
+    /// Receivers accepted at once, each joins a slot of its own.
+    pub max_receivers: u32,
+    /// Set when the receivers are served one after another.
+    pub keep_alive: Option<KeepAlive>,
+}
+
+impl Default for SendOptions {
+    fn default() -> Self {
+        Self {
+            specify_relay: None,
+            client_type: ClientType::Cli,
+            lan_broadcast: false,
+            compress: false,
+            concurrency: 1,
+            streams: 1,
+            limit_rate: None,
+            max_receivers: 1,
+            keep_alive: None,
+        }
+    }
+}
+
+/// Receivers of a kept alive share, counted across the relays.
+#[derive(Debug)]
+struct Downloads {
+    max_downloads: Option<u32>,
+    joined: AtomicU32,
+    done: AtomicU32,
+}
+
+impl Downloads {
+    /// Number of a receiver joining, from 1.
+    fn join(&self) -> u32 {
+        self.joined.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Count a download done, `true` once the most allowed are.
+    fn done(&self) -> bool {
+        let done = self.done.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_downloads.is_some_and(|max_downloads| done >= max_downloads)
+    }
+}
+
 /// Sender stream
 pub type SenderStream = Pin<Box<dyn Stream<Item = SenderInteractionMessage> + Send>>;
 
@@ -51,65 +300,195 @@ pub struct FlashCatSender {
     public_relay_shutdown: Shutdown,
     client_type: ClientType,
     lan_broadcast: bool,
+    compress: bool,
+    /// Number of files transferred concurrently.
+    concurrency: usize,
+    /// Number of streams to the relay, chunks of large files are striped across them.
+    streams: usize,
+    /// Limit of the bytes sent per second, shared by all files.
+    rate_limiter: Option<Arc<RateLimiter>>,
+    /// Paused by this end, the relay channel stays open meanwhile.
+    pause: Pause,
+    /// Files cancelled by this end, the relay channel tells the receiver.
+    file_cancel: broadcast::Sender<u64>,
+    /// Text sent instead of files.
+    text: Option<String>,
+    /// Set when more than one receiver is accepted, each joins a slot of its own.
+    broadcast: Option<Arc<Broadcast>>,
+    /// Set when the receivers are served one after another.
+    keep_alive: Option<KeepAlive>,
+    downloads: Option<Arc<Downloads>>,
     shutdown: Shutdown,
 }
 
 impl FlashCatSender {
     pub async fn new(
         share_code: String,
-        specify_relay: Option<String>,
         mut files: Vec<String>,
         zip_floder: bool,
-        client_type: ClientType,
-        lan_broadcast: bool,
+        collect_options: CollectOptions,
+        options: SendOptions,
     ) -> Result<Self> {
-        paths_exist(files.as_slice())?;
+        let downloads = Self::downloads(
+            options.max_receivers,
+            options.keep_alive,
+            files.iter().any(|file| file == STDIO_PATH),
+        )?;
         let shutdown = Shutdown::new();
         let mut zip_files = vec![];
-        if zip_floder {
-            let (treated_files, zip) = Self::zip_folder(files, shutdown.clone()).await?;
-            files = treated_files;
-            zip_files = zip;
-        }
-        let file_collector = collect_files(files.as_slice());
+        let file_collector = if files.iter().any(|file| file == STDIO_PATH) {
+            if files.len() > 1 {
+                bail!("stdin can't be sent along with other files");
+            }
+            if options.max_receivers > 1 {
+                bail!("the data read from stdin can't be sent to several receivers");
+            }
+            collect_stdin(STDIN_FILE_NAME)
+        } else {
+            paths_exist(files.as_slice())?;
+            if zip_floder {
+                let (treated_files, zip) = Self::zip_folder(files, shutdown.clone()).await?;
+                files = treated_files;
+                zip_files = zip;
+            }
+            let mut file_collector = collect_files_with(files.as_slice(), &collect_options)?;
+            // marked so the receiver can offer to extract them
+            for file in file_collector.files.iter_mut().filter(|file| zip_files.contains(&file.access_path)) {
+                file.kind = FileKind::ZippedFolder;
+            }
+            file_collector
+        };
         let encryptor = Arc::new(Encryptor::new(share_code)?);
         Ok(Self {
             zip_files,
             encryptor,
-            specify_relay,
+            specify_relay: options.specify_relay,
             file_collector: Arc::new(file_collector),
             local_relay_shutdown: Shutdown::new(),
             public_relay_shutdown: Shutdown::new(),
-            client_type,
-            lan_broadcast,
+            client_type: options.client_type,
+            lan_broadcast: options.lan_broadcast,
+            compress: options.compress,
+            concurrency: options.concurrency.max(1),
+            streams: options.streams.max(1),
+            rate_limiter: options.limit_rate.map(|rate| Arc::new(RateLimiter::new(rate))),
+            pause: Pause::new(),
+            file_cancel: broadcast::channel(64).0,
+            text: None,
+            broadcast: Self::broadcast(options.max_receivers)?,
+            keep_alive: options.keep_alive,
+            downloads,
             shutdown,
         })
     }
 
     pub fn new_with_file_collector(
         share_code: String,
-        specify_relay: Option<String>,
         file_collector: FileCollector,
-        client_type: ClientType,
-        lan_broadcast: bool,
+        options: SendOptions,
     ) -> Result<Self> {
+        let from_stdin = file_collector.files.iter().any(|file| file.kind == FileKind::Stream);
+        let downloads = Self::downloads(options.max_receivers, options.keep_alive, from_stdin)?;
+        if options.max_receivers > 1 && from_stdin {
+            bail!("the data read from stdin can't be sent to several receivers");
+        }
         let shutdown = Shutdown::new();
         let encryptor = Arc::new(Encryptor::new(share_code)?);
         Ok(Self {
             zip_files: vec![],
             encryptor,
-            specify_relay,
+            specify_relay: options.specify_relay,
             file_collector: Arc::new(file_collector),
             local_relay_shutdown: Shutdown::new(),
             public_relay_shutdown: Shutdown::new(),
-            client_type,
-            lan_broadcast,
+            client_type: options.client_type,
+            lan_broadcast: options.lan_broadcast,
+            compress: options.compress,
+            concurrency: options.concurrency.max(1),
+            streams: options.streams.max(1),
+            rate_limiter: options.limit_rate.map(|rate| Arc::new(RateLimiter::new(rate))),
+            pause: Pause::new(),
+            file_cancel: broadcast::channel(64).0,
+            text: None,
+            broadcast: Self::broadcast(options.max_receivers)?,
+            keep_alive: options.keep_alive,
+            downloads,
             shutdown,
         })
     }
 
+    /// Send a short text instead of files, delivered to the receiver as a message of its own.
+    /// The options of the files sent, like compressing them, don't apply to it.
+    pub fn new_with_text(
+        share_code: String,
+        text: String,
+        options: SendOptions,
+    ) -> Result<Self> {
+        let downloads = Self::downloads(options.max_receivers, options.keep_alive, false)?;
+        if text.is_empty() {
+            bail!("the text to send is empty");
+        }
+        if text.len() > MAX_TEXT_SIZE {
+            bail!(
+                "the text to send is {} bytes, at most {MAX_TEXT_SIZE} bytes can be sent",
+                text.len()
+            );
+        }
+        let encryptor = Arc::new(Encryptor::new(share_code)?);
+        Ok(Self {
+            zip_files: vec![],
+            encryptor,
+            specify_relay: options.specify_relay,
+            file_collector: Arc::new(FileCollector::default()),
+            local_relay_shutdown: Shutdown::new(),
+            public_relay_shutdown: Shutdown::new(),
+            client_type: options.client_type,
+            lan_broadcast: options.lan_broadcast,
+            compress: false,
+            concurrency: 1,
+            streams: 1,
+            rate_limiter: None,
+            pause: Pause::new(),
+            file_cancel: broadcast::channel(64).0,
+            text: Some(text),
+            broadcast: Self::broadcast(options.max_receivers)?,
+            keep_alive: options.keep_alive,
+            downloads,
+            shutdown: Shutdown::new(),
+        })
+    }
+
+    fn broadcast(max_receivers: u32) -> Result<Option<Arc<Broadcast>>> {
+        if max_receivers > MAX_RECEIVERS {
+            bail!("a share accepts at most {MAX_RECEIVERS} receivers");
+        }
+        Ok((max_receivers > 1).then(|| Arc::new(Broadcast::new(max_receivers))))
+    }
+
+    fn downloads(
+        max_receivers: u32,
+        keep_alive: Option<KeepAlive>,
+        from_stdin: bool,
+    ) -> Result<Option<Arc<Downloads>>> {
+        let Some(keep_alive) = keep_alive else {
+            return Ok(None);
+        };
+        if max_receivers > 1 {
+            bail!("a share sent to several receivers at once can't be kept alive");
+        }
+        // stdin is read through by the first receiver
+        if from_stdin {
+            bail!("a share of the data read from stdin can't be kept alive");
+        }
+        Ok(Some(Arc::new(Downloads {
+            max_downloads: keep_alive.max_downloads,
+            joined: AtomicU32::new(0),
+            done: AtomicU32::new(0),
+        })))
+    }
+
     pub async fn start(self: Arc<Self>) -> Result<SenderStream> {
-        let (sender_stream_tx, mut sender_stream_rx) = mpsc::channel(128);
+        let (sender_stream_tx, sender_stream_rx) = mpsc::channel(128);
 
         if self.specify_relay.is_some() {
             let specify_relay = self.specify_relay.clone().unwrap();
@@ -165,9 +544,48 @@ impl FlashCatSender {
                 self.broadcast_relay_addr(local_relay_port, sender_stream_tx.clone()).await;
             }
         }
+        if let Some(expires_in) = self.keep_alive.and_then(|keep_alive| keep_alive.expires_in) {
+            let sender_stream_tx = sender_stream_tx.clone();
+            let shutdown = self.shutdown.clone();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = shutdown.wait() => (),
+                    _ = tokio::time::sleep(expires_in) => {
+                        let _ = sender_stream_tx.send(SenderInteractionMessage::Expired).await;
+                    }
+                }
+            });
+        }
+        Ok(self.interaction_stream(sender_stream_rx))
+    }
+
+    /// Store the transfer on the relay for the receiver to collect later, the sender is
+    /// done once it is stored. Only the relay specified or the public one may store it.
+    pub async fn deposit(self: Arc<Self>) -> Result<SenderStream> {
+        if self.broadcast.is_some() || self.keep_alive.is_some() {
+            bail!("a share sent to several receivers or kept alive can't be stored on the relay");
+        }
+        let endpoint = match self.specify_relay.clone() {
+            Some(specify_relay) => get_endpoint(normalize_relay_endpoint(specify_relay))?,
+            None => get_endpoint(format!("https://{PUBLIC_RELAY}"))?,
+        };
+        let (sender_stream_tx, sender_stream_rx) = mpsc::channel(128);
+        let sender = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = sender.deposit_channel(endpoint, &sender_stream_tx).await {
+                let _ = Self::send_msg_to_stream(&sender_stream_tx, SenderInteractionMessage::Error(e.to_string())).await;
+            }
+        });
+        Ok(self.interaction_stream(sender_stream_rx))
+    }
+
+    fn interaction_stream(
+        self: Arc<Self>,
+        mut sender_stream_rx: mpsc::Receiver<SenderInteractionMessage>,
+    ) -> SenderStream {
         // resolve shutdown when sender_stream_rx is no message will cause panic
         let mut interval = tokio::time::interval(std::time::Duration::from_millis(100));
-        Ok(Box::pin(async_stream::stream! {
+        Box::pin(async_stream::stream! {
             while !self.shutdown.is_terminated() {
                 tokio::select! {
                     Some(sender_stream) = sender_stream_rx.recv() => {
@@ -176,7 +594,240 @@ impl FlashCatSender {
                     _ = interval.tick() =>(),
                 }
             }
-        }))
+        })
+    }
+
+    /// Send the transfer to the relay storing it. The relay stands in for the receiver and
+    /// accepts every file, there is no key exchange: the key is derived from the share code
+    /// by Argon2id with a random salt, stored along.
+    async fn deposit_channel(
+        &self,
+        mut endpoint: Endpoint,
+        sender_stream_tx: &mpsc::Sender<SenderInteractionMessage>,
+    ) -> Result<()> {
+        let mut client = RelayServiceClient::connect(endpoint.clone()).await?;
+        let kdf = MailboxKdf::generate();
+        let resp = client
+            .join(JoinRequest {
+                id: Some(Id {
+                    encrypted_share_code: self.encryptor.encrypt_share_code_bytes(),
+                    character: Character::Sender.into(),
+                    stream: 0,
+                    slot: 0,
+                }),
+                client_type: self.client_type.into(),
+                sender_local_relay: None,
+                max_receivers: 1,
+                keep_alive: false,
+                mailbox_salt: kdf.encode(),
+            })
+            .await?;
+        let (relay, ttl) = match resp.into_inner().join_response_message {
+            Some(join_response::JoinResponseMessage::Success(join_success)) => (join_success.relay, Duration::from_secs(join_success.mailbox_ttl)),
+            Some(join_response::JoinResponseMessage::Failed(join_failed)) => bail!(join_failed.error_msg),
+            None => bail!("can't get relay info"),
+        };
+        if let Some(relay_info) = relay {
+            endpoint = get_endpoint(format!("http://{}:{}", relay_info.relay_ip, relay_info.relay_port))?;
+        }
+
+        let (_client, tx, mut messages, confirm_tx, confirm_rx) = Self::establish_channel(&self.encryptor, &endpoint, 0).await?;
+        let encryptor = self.encryptor.clone();
+        let cipher = Arc::new(tokio::task::spawn_blocking(move || encryptor.mailbox_cipher(&kdf)).await??);
+        send_msg_to_relay(
+            &tx,
+            RelayMessage::Sender(SenderUpdate {
+                sender_message: Some(SenderMessage::SendRequest(Self::send_request(
+                    &cipher,
+                    &self.file_collector,
+                    self.text.as_deref(),
+                )?)),
+            }),
+        )
+        .await?;
+        let send_files_shutdown = Shutdown::new();
+        let cancels = FileCancels::default();
+        if self.text.is_some() {
+            send_msg_to_relay(&tx, RelayMessage::Done(Done {})).await?;
+        } else {
+            send_msg_to_relay(
+                &tx,
+                RelayMessage::Sender(SenderUpdate {
+                    sender_message: Some(SenderMessage::Manifest(Manifest {
+                        sealed_manifest: cipher.seal(&Self::manifest(&self.file_collector), b"manifest")?,
+                    })),
+                }),
+            )
+            .await?;
+            // every file is stored, the receiver selects from them once it collects the transfer
+            let transfer = TransferContext {
+                cipher: cipher.clone(),
+                tx: tx.clone(),
+                sender_stream_tx: sender_stream_tx.clone(),
+                cancel: send_files_shutdown.clone(),
+                compress: self.compress,
+                chunk_sizer: Arc::new(Mutex::new(ChunkSizer::new())),
+                data_streams: Arc::default(),
+                rate_limiter: self.rate_limiter.clone(),
+                pause: self.pause.clone(),
+                cancels: cancels.clone(),
+                sent: SentFiles::default(),
+                broadcast: None,
+                confirm_waiters: Arc::default(),
+            };
+            Self::spawn_send_files(
+                transfer,
+                self.file_collector.clone(),
+                confirm_rx,
+                None,
+                None,
+                false,
+                self.concurrency,
+            );
+        }
+
+        let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+        let mut pause_rx = self.pause.subscribe();
+        let mut file_cancel_rx = self.file_cancel.subscribe();
+        loop {
+            let message = tokio::select! {
+                // hanging up drops the transfer stored so far
+                _ = self.shutdown.wait() => {
+                    send_files_shutdown.shutdown();
+                    return Ok(());
+                }
+                _ = ping_interval.tick() => {
+                    let _ = send_msg_to_relay(&tx, RelayMessage::Ping(0)).await;
+                    continue;
+                }
+                Ok(()) = pause_rx.changed() => {
+                    let paused = *pause_rx.borrow_and_update();
+                    Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::Paused(paused)).await?;
+                    continue;
+                }
+                Ok(file_id) = file_cancel_rx.recv() => {
+                    cancels.cancel(file_id);
+                    send_msg_to_relay(
+                        &tx,
+                        RelayMessage::Sender(SenderUpdate {
+                            sender_message: Some(SenderMessage::CancelFile(CancelFile {
+                                file_id,
+                            })),
+                        }),
+                    )
+                    .await?;
+                    continue;
+                }
+                item = messages.next() => match item {
+                    Some(Ok(update)) => match update.relay_message {
+                        Some(message) => message,
+                        None => continue,
+                    },
+                    Some(Err(status)) => {
+                        send_files_shutdown.shutdown();
+                        bail!("the relay stopped storing the transfer: {}", status.message());
+                    }
+                    None => {
+                        send_files_shutdown.shutdown();
+                        bail!("the relay stopped storing the transfer");
+                    }
+                }
+            };
+            match message {
+                RelayMessage::Receiver(receiver) => {
+                    if let Some(ReceiverMessage::FileConfirm(file_confirm)) = receiver.receiver_message {
+                        confirm_tx.send(file_confirm).await?;
+                    }
+                }
+                RelayMessage::Done(_) => {
+                    Self::send_msg_to_stream(
+                        sender_stream_tx,
+                        SenderInteractionMessage::Message(format!(
+                            "Stored on the relay, the receiver may collect it within {}",
+                            HumanDuration(ttl)
+                        )),
+                    )
+                    .await?;
+                    Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::Completed).await?;
+                    return Ok(());
+                }
+                RelayMessage::Error(e) => {
+                    send_files_shutdown.shutdown();
+                    bail!("relay error {e}");
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Message for a receiver rejecting the transfer, the receiver of a kept alive share
+    /// leaves it to the next one.
+    fn receiver_rejected(
+        receiver: &mut Option<u32>,
+        kept_alive: bool,
+    ) -> SenderInteractionMessage {
+        match *receiver {
+            Some(joined) if kept_alive => {
+                *receiver = None;
+                SenderInteractionMessage::ReceiverLeft(joined)
+            }
+            _ => SenderInteractionMessage::ReceiverReject,
+        }
+    }
+
+    /// Request announcing the transfer, a text is sent along with it.
+    fn send_request(
+        cipher: &SessionCipher,
+        file_collector: &FileCollector,
+        text: Option<&str>,
+    ) -> Result<SendRequest> {
+        let summary = TransferSummary {
+            total_size: file_collector.total_size,
+            num_files: file_collector.num_files,
+            num_folders: file_collector.num_folders,
+            max_file_name_length: file_collector.max_file_name_length as u64,
+            // a text is not resumed, the receiver keeps no journal for it
+            manifest_fingerprint: match text {
+                Some(_) => Bytes::new(),
+                None => Bytes::from(file_collector.fingerprint()),
+            },
+        };
+        let sealed_text = match text {
+            Some(text) => cipher.seal(
+                &TextPayload {
+                    text: text.to_string(),
+                },
+                b"text",
+            )?,
+            None => Bytes::new(),
+        };
+        Ok(SendRequest {
+            sealed_summary: cipher.seal(&summary, b"summary")?,
+            sealed_text,
+        })
+    }
+
+    fn manifest(file_collector: &FileCollector) -> FileManifest {
+        FileManifest {
+            entries: file_collector
+                .files
+                .iter()
+                .map(|file| ManifestEntry {
+                    file_id: file.file_id,
+                    relative_path: file.relative_path.clone(),
+                    size: file.size,
+                    is_empty_dir: file.empty_dir,
+                })
+                .collect(),
+            directories: file_collector
+                .dir_attributes
+                .iter()
+                .map(|(relative_path, attributes)| DirectoryAttributes {
+                    relative_path: relative_path.clone(),
+                    attributes: Some(attributes.clone().into()),
+                })
+                .collect(),
+        }
     }
 
     async fn start_local_relay(
@@ -186,7 +837,7 @@ impl FlashCatSender {
         local_relay_shutdown: Shutdown,
     ) {
         tokio::spawn(async move {
-            let relay = match Relay::new(None, true) {
+            let relay = match Relay::new(None, true, None, None, None) {
                 Ok(relay) => relay,
                 Err(e) => {
                     let _ = &sender_stream_tx
@@ -231,7 +882,7 @@ impl FlashCatSender {
     }
 
     async fn connect_relay(
-        &self,
+        self: &Arc<Self>,
         relay_type: RelayType,
         local_relay_port: Option<u16>,
         mut endpoint: Endpoint,
@@ -258,9 +909,14 @@ impl FlashCatSender {
                 id: Some(Id {
                     encrypted_share_code: self.encryptor.encrypt_share_code_bytes(),
                     character: Character::Sender.into(),
+                    stream: 0,
+                    slot: 0,
                 }),
                 client_type: self.client_type.into(),
                 sender_local_relay,
+                max_receivers: self.max_receivers(),
+                keep_alive: self.keep_alive.is_some(),
+                mailbox_salt: Bytes::new(),
             })
             .await
         {
@@ -317,34 +973,134 @@ impl FlashCatSender {
             None => (),
         }
 
-        let encryptor = self.encryptor.clone();
-        let file_collector = self.file_collector.clone();
-        tokio::spawn(async move {
-            if let Err(e) = Self::relay_channel(
-                relay_type.clone(),
-                encryptor,
-                file_collector.clone(),
-                endpoint,
-                &sender_stream_tx,
-                public_or_specify_shutdown,
-                local_relay_shutdown,
-            )
-            .await
-            {
-                let _ = Self::send_msg_to_stream(
-                    &sender_stream_tx,
-                    SenderInteractionMessage::RelayFailed((relay_type, e.to_string())),
-                )
-                .await;
-            }
-        });
+        // a relay channel for every slot of a broadcast session, their messages are told
+        // apart by the receiver that joined the slot
+        for slot in 0..self.max_receivers() {
+            let relay_type = relay_type.clone();
+            let endpoint = endpoint.clone();
+            let public_or_specify_shutdown = public_or_specify_shutdown.clone();
+            let local_relay_shutdown = local_relay_shutdown.clone();
+            let sender = self.clone();
+            let slot_stream_tx = match self.broadcast.clone() {
+                Some(broadcast) => {
+                    let (slot_stream_tx, slot_stream_rx) = mpsc::channel(128);
+                    tokio::spawn(Self::forward_slot_messages(
+                        slot_stream_rx,
+                        sender_stream_tx.clone(),
+                        slot,
+                        broadcast,
+                    ));
+                    slot_stream_tx
+                }
+                None => sender_stream_tx.clone(),
+            };
+            tokio::spawn(async move {
+                if let Err(e) = sender
+                    .relay_channel(
+                        relay_type.clone(),
+                        endpoint,
+                        &slot_stream_tx,
+                        public_or_specify_shutdown,
+                        local_relay_shutdown,
+                        slot,
+                    )
+                    .await
+                {
+                    let _ = Self::send_msg_to_stream(
+                        &slot_stream_tx,
+                        SenderInteractionMessage::RelayFailed((relay_type, e.to_string())),
+                    )
+                    .await;
+                }
+            });
+        }
         Ok(())
     }
 
+    /// Forward the messages of the relay channel of a broadcast slot. The ones about the
+    /// transfer carry the number of the receiver that joined the slot, the others are
+    /// the same for every slot and only forwarded from the first one.
+    async fn forward_slot_messages(
+        mut slot_stream_rx: mpsc::Receiver<SenderInteractionMessage>,
+        sender_stream_tx: mpsc::Sender<SenderInteractionMessage>,
+        slot: u32,
+        broadcast: Arc<Broadcast>,
+    ) {
+        let mut receiver = None;
+        let mut finished = false;
+        while let Some(message) = slot_stream_rx.recv().await {
+            // the slot of a receiver gone is taken by the next one to join
+            if let SenderInteractionMessage::ReceiverJoined(joined) = message {
+                receiver = Some(joined);
+                finished = false;
+                let _ = sender_stream_tx.send(message).await;
+                // the share is done once the receivers joined meanwhile are, however many
+                if joined == 1 {
+                    let broadcast = broadcast.clone();
+                    let sender_stream_tx = sender_stream_tx.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(JOIN_WINDOW).await;
+                        if broadcast.close_joins() {
+                            let _ = sender_stream_tx.send(SenderInteractionMessage::Completed).await;
+                        }
+                    });
+                }
+                continue;
+            }
+            let Some(joined) = receiver else {
+                if slot == 0 {
+                    let _ = sender_stream_tx.send(message).await;
+                }
+                continue;
+            };
+            let message = match message {
+                SenderInteractionMessage::FileProgress(progress) => SenderInteractionMessage::ReceiverProgress((joined, progress)),
+                SenderInteractionMessage::FileProgressFinish(file_id) => SenderInteractionMessage::ReceiverFileFinished((joined, file_id)),
+                SenderInteractionMessage::ContinueFile(file_id) | SenderInteractionMessage::FileCancelled(file_id) => {
+                    SenderInteractionMessage::ReceiverFileSkipped((joined, file_id))
+                }
+                SenderInteractionMessage::Message(msg) | SenderInteractionMessage::Error(msg) => {
+                    SenderInteractionMessage::Message(format!("Receiver {joined}: {msg}"))
+                }
+                SenderInteractionMessage::PausedByPeer(paused) => SenderInteractionMessage::Message(format!(
+                    "Receiver {joined} {}",
+                    if paused {
+                        "paused"
+                    } else {
+                        "resumed"
+                    }
+                )),
+                SenderInteractionMessage::ReceiverReject | SenderInteractionMessage::OtherClose | SenderInteractionMessage::Completed => {
+                    // a receiver done closes its end afterwards
+                    if finished {
+                        continue;
+                    }
+                    finished = true;
+                    let completed = matches!(message, SenderInteractionMessage::Completed);
+                    let message = if completed {
+                        SenderInteractionMessage::ReceiverCompleted(joined)
+                    } else {
+                        SenderInteractionMessage::ReceiverLeft(joined)
+                    };
+                    let _ = sender_stream_tx.send(message).await;
+                    if broadcast.finish(completed) {
+                        let _ = sender_stream_tx.send(SenderInteractionMessage::Completed).await;
+                    }
+                    continue;
+                }
+                SenderInteractionMessage::SendDone => continue,
+                message if slot == 0 => message,
+                _ => continue,
+            };
+            let _ = sender_stream_tx.send(message).await;
+        }
+    }
+
     /// Establish a gRPC channel stream connection. Returns the client, tx, messages stream, and confirm channels.
     async fn establish_channel(
         encryptor: &Encryptor,
         endpoint: &Endpoint,
+        slot: u32,
     ) -> Result<(
         RelayServiceClient<tonic::transport::Channel>,
         mpsc::Sender<RelayUpdate>,
@@ -359,6 +1115,8 @@ impl FlashCatSender {
         let join = RelayMessage::Join(Id {
             encrypted_share_code: encryptor.encrypt_share_code_bytes(),
             character: Character::Sender.into(),
+            stream: 0,
+            slot,
         });
         tx.send(RelayUpdate {
             relay_message: Some(join),
@@ -374,15 +1132,16 @@ impl FlashCatSender {
     }
 
     async fn relay_channel(
+        &self,
         relay_type: RelayType,
-        encryptor: Arc<Encryptor>,
-        file_collector: Arc<FileCollector>,
         endpoint: Endpoint,
         sender_stream_tx: &mpsc::Sender<SenderInteractionMessage>,
         public_or_specify_shutdown: Shutdown,
         local_relay_shutdown: Shutdown,
+        slot: u32,
     ) -> Result<()> {
-        let (mut client, mut tx, mut messages, mut confirm_tx, mut confirm_rx) = Self::establish_channel(&encryptor, &endpoint).await?;
+        let mut file_cancel_rx = self.file_cancel.subscribe();
+        let (mut client, mut tx, mut messages, mut confirm_tx, mut confirm_rx) = Self::establish_channel(&self.encryptor, &endpoint, slot).await?;
 
         let shutdown = match relay_type {
             RelayType::Local => local_relay_shutdown.clone(),
@@ -393,33 +1152,99 @@ impl FlashCatSender {
         let mut reconnect_attempt = 0u32;
         let mut is_first_connect = true;
         let mut send_files_shutdown = Shutdown::new();
+        let mut key_exchange = None;
+        let mut cipher = None;
+        // files selected by the receiver from the manifest, kept for resuming after a reconnect
+        let mut selection: Option<HashSet<u64>> = None;
+        // set while the receiver is asked for its progress after a reconnect, a resume state
+        // sent otherwise comes from a restarted receiver
+        let mut resume_requested = false;
+        // chunks are only compressed and small files only batched for receivers announcing support
+        let mut zstd_chunks = false;
+        let mut file_batches = false;
+        // chunks are only striped when both the relay and the receiver support data streams
+        let mut relay_streams = false;
+        let mut receiver_streams = false;
+        let mut data_streams: Option<Arc<Vec<mpsc::Sender<RelayUpdate>>>> = None;
+        // shared by every file sent, they all go over the same link
+        let chunk_sizer = match self.broadcast.as_ref() {
+            Some(broadcast) => broadcast.chunk_sizer(),
+            None => Arc::new(Mutex::new(ChunkSizer::new())),
+        };
+        // number of the receiver of a broadcast session or of a kept alive share, kept
+        // across reconnects
+        let mut receiver = None;
+        let mut last_ping = None;
+        // the files are held back while either end is paused
+        let mut pause_rx = self.pause.subscribe();
+        let transfer_pause = Pause::new();
+        let mut peer_paused = false;
+        let mut cancels = FileCancels::default();
+        let mut sent = SentFiles::default();
         loop {
             let message = tokio::select! {
                 _ = shutdown.wait() => {
                     send_files_shutdown.shutdown();
                     let _ = client.close(CloseRequest {
-                        encrypted_share_code: encryptor.encrypt_share_code_bytes(),
+                        encrypted_share_code: self.encryptor.encrypt_share_code_bytes(),
+                        character: Character::Sender.into(),
+                        slot,
                     })
                     .await;
                     return Ok(());
                 }
                 _ = ping_interval.tick() => {
-                    let _ = send_msg_to_relay(&tx, RelayMessage::Ping(0)).await;
+                    if send_msg_to_relay(&tx, RelayMessage::Ping(0)).await.is_ok() {
+                        last_ping = Some(Instant::now());
+                    }
                     continue;
                 }
-                item = messages.next() => {
-                    match item {
-                        Some(Ok(update)) => {
-                            match update.relay_message {
-                                Some(msg) => {
-                                    reconnect_attempt = 0;
-                                    msg
-                                }
-                                None => continue,
-                            }
-                        }
-                        Some(Err(_)) | None => {
-                            if shutdown.is_terminated() {
+                Ok(()) = pause_rx.changed() => {
+                    let paused = *pause_rx.borrow_and_update();
+                    transfer_pause.set(paused || peer_paused);
+                    // the receiver accepts nothing before the key exchange
+                    if cipher.is_some() {
+                        let _ = send_msg_to_relay(
+                            &tx,
+                            RelayMessage::Sender(SenderUpdate {
+                                sender_message: Some(SenderMessage::PauseState(PauseState {
+                                    paused,
+                                })),
+                            }),
+                        )
+                        .await;
+                    }
+                    Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::Paused(paused)).await?;
+                    continue;
+                }
+                Ok(file_id) = file_cancel_rx.recv() => {
+                    cancels.cancel(file_id);
+                    if cipher.is_some() {
+                        let _ = send_msg_to_relay(
+                            &tx,
+                            RelayMessage::Sender(SenderUpdate {
+                                sender_message: Some(SenderMessage::CancelFile(CancelFile {
+                                    file_id,
+                                })),
+                            }),
+                        )
+                        .await;
+                    }
+                    continue;
+                }
+                item = messages.next() => {
+                    match item {
+                        Some(Ok(update)) => {
+                            match update.relay_message {
+                                Some(msg) => {
+                                    reconnect_attempt = 0;
+                                    msg
+                                }
+                                None => continue,
+                            }
+                        }
+                        Some(Err(_)) | None => {
+                            if shutdown.is_terminated() {
                                 return Ok(());
                             }
                             send_files_shutdown.shutdown();
@@ -446,7 +1271,7 @@ impl FlashCatSender {
                                     return Ok(());
                                 }
 
-                                match Self::establish_channel(&encryptor, &endpoint).await {
+                                match Self::establish_channel(&self.encryptor, &endpoint, slot).await {
                                     Ok(result) => break result,
                                     Err(e) => {
                                         let _ = Self::send_msg_to_stream(
@@ -467,6 +1292,7 @@ impl FlashCatSender {
                             confirm_tx = new_confirm_tx;
                             confirm_rx = new_confirm_rx;
                             send_files_shutdown = Shutdown::new();
+                            data_streams = None;
                             is_first_connect = false;
                             reconnect_attempt = 0;
                             ping_interval = tokio::time::interval(PING_INTERVAL);
@@ -492,6 +1318,7 @@ impl FlashCatSender {
                 RelayMessage::Joined(_) => {
                     // After reconnection, send ResumeRequest instead of waiting for Ready
                     if !is_first_connect {
+                        resume_requested = true;
                         send_msg_to_relay(
                             &tx,
                             RelayMessage::Sender(SenderUpdate {
@@ -502,23 +1329,72 @@ impl FlashCatSender {
                     }
                 }
                 RelayMessage::Ready(ready) => {
-                    if ready.local_relay {
-                        public_or_specify_shutdown.shutdown();
-                    } else {
-                        local_relay_shutdown.shutdown();
+                    match self.broadcast.as_ref() {
+                        // the other receivers may still join through the other relay
+                        Some(broadcast) => {
+                            if receiver.is_none() {
+                                let Some(joined) = broadcast.join() else {
+                                    send_msg_to_relay(&tx, RelayMessage::Error("the share accepts no more receivers".to_string())).await?;
+                                    continue;
+                                };
+                                receiver = Some(joined);
+                                // the slot may have served a receiver gone
+                                send_files_shutdown.shutdown();
+                                send_files_shutdown = Shutdown::new();
+                                (confirm_tx, confirm_rx) = async_channel::bounded(10);
+                                selection = None;
+                                cancels = FileCancels::default();
+                                sent = SentFiles::default();
+                                Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::ReceiverJoined(joined)).await?;
+                            }
+                        }
+                        None => match self.downloads.as_ref() {
+                            // the next receivers may join through either relay
+                            Some(downloads) => {
+                                if receiver.is_none() {
+                                    let joined = downloads.join();
+                                    receiver = Some(joined);
+                                    // nothing of the receiver before is kept
+                                    send_files_shutdown.shutdown();
+                                    send_files_shutdown = Shutdown::new();
+                                    (confirm_tx, confirm_rx) = async_channel::bounded(10);
+                                    selection = None;
+                                    cancels = FileCancels::default();
+                                    sent = SentFiles::default();
+                                    Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::ReceiverJoined(joined)).await?;
+                                }
+                            }
+                            None => {
+                                if ready.local_relay {
+                                    public_or_specify_shutdown.shutdown();
+                                } else {
+                                    local_relay_shutdown.shutdown();
+                                }
+                            }
+                        },
                     }
+                    // the send request is only sent once the key exchange is confirmed
+                    let exchange = self.encryptor.key_exchange(Role::Sender);
                     send_msg_to_relay(
                         &tx,
                         RelayMessage::Sender(SenderUpdate {
-                            sender_message: Some(SenderMessage::SendRequest(SendRequest {
-                                total_size: file_collector.total_size,
-                                num_files: file_collector.num_files,
-                                num_folders: file_collector.num_folders,
-                                max_file_name_length: file_collector.max_file_name_length as u64,
+                            sender_message: Some(SenderMessage::KeyExchange(KeyExchange {
+                                public_share: exchange.public_share(),
+                                confirm: Bytes::new(),
                             })),
                         }),
                     )
                     .await?;
+                    key_exchange = Some(exchange);
+                    cipher = None;
+                    zstd_chunks = false;
+                    file_batches = false;
+                    relay_streams = ready.parallel_streams;
+                    receiver_streams = false;
+                    data_streams = None;
+                    peer_paused = false;
+                    resume_requested = false;
+                    transfer_pause.set(self.pause.is_paused());
                 }
                 RelayMessage::Sender(_) => {
                     Self::send_msg_to_stream(
@@ -527,34 +1403,95 @@ impl FlashCatSender {
                     )
                     .await?;
                 }
-                RelayMessage::Receiver(receiver) => {
-                    if let Some(receiver_message) = receiver.receiver_message {
+                RelayMessage::Receiver(update) => {
+                    if let Some(receiver_message) = update.receiver_message {
                         match receiver_message {
+                            ReceiverMessage::KeyExchange(peer) => {
+                                let session_key = match key_exchange.take() {
+                                    Some(exchange) => exchange.finish(&peer.public_share).and_then(|key| key.verify_peer(&peer.confirm).map(|_| key)),
+                                    None => Err(anyhow::anyhow!("unexpected key exchange message")),
+                                };
+                                let session_key = match session_key {
+                                    Ok(session_key) => session_key,
+                                    Err(e) => {
+                                        // fail closed, nothing is sent without a confirmed session key
+                                        send_msg_to_relay(&tx, RelayMessage::Done(Done {})).await?;
+                                        Self::send_msg_to_stream(
+                                            sender_stream_tx,
+                                            SenderInteractionMessage::Error(format!("key exchange failed: {e}")),
+                                        )
+                                        .await?;
+                                        continue;
+                                    }
+                                };
+                                send_msg_to_relay(
+                                    &tx,
+                                    RelayMessage::Sender(SenderUpdate {
+                                        sender_message: Some(SenderMessage::KeyExchange(KeyExchange {
+                                            public_share: Bytes::new(),
+                                            confirm: session_key.confirmation(),
+                                        })),
+                                    }),
+                                )
+                                .await?;
+                                let session_cipher = Arc::new(session_key.cipher());
+                                send_msg_to_relay(
+                                    &tx,
+                                    RelayMessage::Sender(SenderUpdate {
+                                        sender_message: Some(SenderMessage::SendRequest(Self::send_request(
+                                            &session_cipher,
+                                            &self.file_collector,
+                                            self.text.as_deref(),
+                                        )?)),
+                                    }),
+                                )
+                                .await?;
+                                cipher = Some(session_cipher);
+                                // paused before the key exchange, the receiver is only told now
+                                if self.pause.is_paused() {
+                                    send_msg_to_relay(
+                                        &tx,
+                                        RelayMessage::Sender(SenderUpdate {
+                                            sender_message: Some(SenderMessage::PauseState(PauseState {
+                                                paused: true,
+                                            })),
+                                        }),
+                                    )
+                                    .await?;
+                                }
+                                if self.text.is_some() {
+                                    // nothing else to send, the receiver answers once the text is shown
+                                    send_msg_to_relay(&tx, RelayMessage::Done(Done {})).await?;
+                                    Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::SendDone).await?;
+                                }
+                            }
                             ReceiverMessage::ShareConfirm(share_confirm) => {
                                 if let Ok(confirm) = Confirm::try_from(share_confirm) {
                                     match confirm {
                                         Confirm::Accept => {
-                                            let encryptor = encryptor.clone();
-                                            let file_collector = file_collector.clone();
-                                            let tx = tx.clone();
-                                            let sender_stream_tx = sender_stream_tx.clone();
-                                            let notify_rx = confirm_rx.clone();
-                                            let cancel = send_files_shutdown.clone();
-                                            tokio::spawn(async move {
-                                                if let Err(err) =
-                                                    Self::send_files(encryptor, tx, file_collector, notify_rx, &sender_stream_tx, cancel, None).await
-                                                {
-                                                    let _ = Self::send_msg_to_stream(
-                                                        &sender_stream_tx,
-                                                        SenderInteractionMessage::Error(format!("send files error {}", err)),
-                                                    )
-                                                    .await;
-                                                }
-                                            });
+                                            let Some(cipher) = cipher.clone() else {
+                                                Self::send_msg_to_stream(
+                                                    sender_stream_tx,
+                                                    SenderInteractionMessage::Error("share confirmed before key exchange".to_string()),
+                                                )
+                                                .await?;
+                                                continue;
+                                            };
+                                            // the files are sent once the receiver selected from the manifest
+                                            send_msg_to_relay(
+                                                &tx,
+                                                RelayMessage::Sender(SenderUpdate {
+                                                    sender_message: Some(SenderMessage::Manifest(Manifest {
+                                                        sealed_manifest: cipher.seal(&Self::manifest(&self.file_collector), b"manifest")?,
+                                                    })),
+                                                }),
+                                            )
+                                            .await?;
                                         }
                                         Confirm::Reject => {
                                             send_msg_to_relay(&tx, RelayMessage::Done(Done {})).await?;
-                                            Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::ReceiverReject).await?;
+                                            let message = Self::receiver_rejected(&mut receiver, self.downloads.is_some());
+                                            Self::send_msg_to_stream(sender_stream_tx, message).await?;
                                         }
                                     }
                                 } else {
@@ -568,43 +1505,179 @@ impl FlashCatSender {
                             ReceiverMessage::FileConfirm(file_confirm) => {
                                 confirm_tx.send(file_confirm).await?;
                             }
+                            ReceiverMessage::PauseState(pause_state) => {
+                                peer_paused = pause_state.paused;
+                                transfer_pause.set(self.pause.is_paused() || peer_paused);
+                                Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::PausedByPeer(peer_paused)).await?;
+                            }
+                            ReceiverMessage::CancelFile(cancel_file) => {
+                                cancels.cancel(cancel_file.file_id);
+                            }
+                            ReceiverMessage::Capabilities(capabilities) => {
+                                let Some(cipher) = cipher.as_ref() else {
+                                    continue;
+                                };
+                                let features: Features = cipher.open(capabilities.sealed_features.as_ref(), b"features")?;
+                                zstd_chunks = features.zstd_chunks;
+                                file_batches = features.file_batches;
+                                receiver_streams = features.parallel_streams;
+                            }
+                            ReceiverMessage::FileSelection(file_selection) => {
+                                let Some(cipher) = cipher.clone() else {
+                                    Self::send_msg_to_stream(
+                                        sender_stream_tx,
+                                        SenderInteractionMessage::Error("files selected before key exchange".to_string()),
+                                    )
+                                    .await?;
+                                    continue;
+                                };
+                                let selected: SelectedFiles = cipher.open(file_selection.sealed_selection.as_ref(), b"selection")?;
+                                selection = Some(selected.file_ids.into_iter().collect());
+                                if data_streams.is_none() {
+                                    let count = if relay_streams && receiver_streams {
+                                        self.streams - 1
+                                    } else {
+                                        0
+                                    };
+                                    let opened = Self::open_data_streams(
+                                        &self.encryptor,
+                                        &endpoint,
+                                        &tx,
+                                        sender_stream_tx,
+                                        slot,
+                                        count,
+                                        send_files_shutdown.clone(),
+                                    )
+                                    .await?;
+                                    data_streams = Some(Arc::new(opened));
+                                }
+                                let transfer = TransferContext {
+                                    cipher,
+                                    tx: tx.clone(),
+                                    sender_stream_tx: sender_stream_tx.clone(),
+                                    cancel: send_files_shutdown.clone(),
+                                    compress: self.compress && zstd_chunks,
+                                    chunk_sizer: chunk_sizer.clone(),
+                                    data_streams: data_streams.clone().unwrap_or_default(),
+                                    rate_limiter: self.rate_limiter.clone(),
+                                    pause: transfer_pause.clone(),
+                                    cancels: cancels.clone(),
+                                    sent: sent.clone(),
+                                    broadcast: self.broadcast.clone(),
+                                    confirm_waiters: Arc::default(),
+                                };
+                                Self::spawn_send_files(
+                                    transfer,
+                                    self.file_collector.clone(),
+                                    confirm_rx.clone(),
+                                    None,
+                                    selection.clone(),
+                                    file_batches,
+                                    self.concurrency,
+                                );
+                            }
                             ReceiverMessage::ResumeState(resume_state) => {
                                 let mut resume_progress = HashMap::new();
                                 for fp in resume_state.files {
                                     resume_progress.insert(fp.file_id, (fp.received_bytes, fp.completed));
                                 }
-                                let encryptor = encryptor.clone();
-                                let file_collector = file_collector.clone();
-                                let tx = tx.clone();
-                                let sender_stream_tx = sender_stream_tx.clone();
-                                let notify_rx = confirm_rx.clone();
-                                let cancel = send_files_shutdown.clone();
-                                tokio::spawn(async move {
-                                    if let Err(err) = Self::send_files(
-                                        encryptor,
-                                        tx,
-                                        file_collector,
-                                        notify_rx,
-                                        &sender_stream_tx,
-                                        cancel,
-                                        Some(resume_progress),
+                                let Some(cipher) = cipher.clone() else {
+                                    Self::send_msg_to_stream(
+                                        sender_stream_tx,
+                                        SenderInteractionMessage::Error("resume requested before key exchange".to_string()),
                                     )
-                                    .await
-                                    {
-                                        let _ = Self::send_msg_to_stream(
-                                            &sender_stream_tx,
-                                            SenderInteractionMessage::Error(format!("send files error {}", err)),
-                                        )
-                                        .await;
+                                    .await?;
+                                    continue;
+                                };
+                                if !std::mem::take(&mut resume_requested) {
+                                    // a restarted receiver skips the selection, the one made before comes along
+                                    if !resume_state.sealed_selection.is_empty() {
+                                        let selected: SelectedFiles = cipher.open(resume_state.sealed_selection.as_ref(), b"selection")?;
+                                        selection = Some(selected.file_ids.into_iter().collect());
                                     }
-                                });
+                                    // and has the attributes of the directories from the manifest
+                                    send_msg_to_relay(
+                                        &tx,
+                                        RelayMessage::Sender(SenderUpdate {
+                                            sender_message: Some(SenderMessage::Manifest(Manifest {
+                                                sealed_manifest: cipher.seal(&Self::manifest(&self.file_collector), b"manifest")?,
+                                            })),
+                                        }),
+                                    )
+                                    .await?;
+                                }
+                                if data_streams.is_none() {
+                                    let count = if relay_streams && receiver_streams {
+                                        self.streams - 1
+                                    } else {
+                                        0
+                                    };
+                                    let opened = Self::open_data_streams(
+                                        &self.encryptor,
+                                        &endpoint,
+                                        &tx,
+                                        sender_stream_tx,
+                                        slot,
+                                        count,
+                                        send_files_shutdown.clone(),
+                                    )
+                                    .await?;
+                                    data_streams = Some(Arc::new(opened));
+                                }
+                                let transfer = TransferContext {
+                                    cipher,
+                                    tx: tx.clone(),
+                                    sender_stream_tx: sender_stream_tx.clone(),
+                                    cancel: send_files_shutdown.clone(),
+                                    compress: self.compress && zstd_chunks,
+                                    chunk_sizer: chunk_sizer.clone(),
+                                    data_streams: data_streams.clone().unwrap_or_default(),
+                                    rate_limiter: self.rate_limiter.clone(),
+                                    pause: transfer_pause.clone(),
+                                    cancels: cancels.clone(),
+                                    sent: sent.clone(),
+                                    broadcast: self.broadcast.clone(),
+                                    confirm_waiters: Arc::default(),
+                                };
+                                Self::spawn_send_files(
+                                    transfer,
+                                    self.file_collector.clone(),
+                                    confirm_rx.clone(),
+                                    Some(resume_progress),
+                                    selection.clone(),
+                                    file_batches,
+                                    self.concurrency,
+                                );
                             }
                         }
                     }
                 }
-                RelayMessage::Done(_) => {
-                    Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::Completed).await?;
-                }
+                RelayMessage::Done(_) => match self.downloads.as_ref() {
+                    // the share stays open for the next receiver
+                    Some(downloads) => {
+                        let Some(joined) = receiver.take() else {
+                            continue;
+                        };
+                        let (num_files, size) = match self.text.as_ref() {
+                            Some(text) => (0, text.len() as u64),
+                            None => sent.total(),
+                        };
+                        Self::send_msg_to_stream(
+                            sender_stream_tx,
+                            SenderInteractionMessage::Downloaded(Download {
+                                receiver: joined,
+                                text: self.text.is_some(),
+                                num_files,
+                                size,
+                            }),
+                        )
+                        .await?;
+                        if downloads.done() {
+                            Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::Completed).await?;
+                        }
+                    }
+                    None => Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::Completed).await?,
+                },
                 RelayMessage::Error(e) => {
                     Self::send_msg_to_stream(
                         sender_stream_tx,
@@ -612,36 +1685,143 @@ impl FlashCatSender {
                     )
                     .await?;
                 }
-                RelayMessage::Terminated(_) => {
-                    Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::OtherClose).await?;
-                }
+                RelayMessage::Terminated(_) => match (self.broadcast.as_ref(), self.downloads.as_ref()) {
+                    // a receiver done or gone closes its end, the share stays open
+                    (None, Some(_)) => {
+                        if let Some(joined) = receiver.take() {
+                            send_files_shutdown.shutdown();
+                            Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::ReceiverLeft(joined)).await?;
+                        }
+                    }
+                    // and frees its slot for the next receiver
+                    (Some(_), _) => {
+                        if receiver.take().is_some() {
+                            send_files_shutdown.shutdown();
+                            Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::OtherClose).await?;
+                        }
+                    }
+                    (None, None) => Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::OtherClose).await?,
+                },
                 RelayMessage::Ping(_) => (),
-                RelayMessage::Pong(_) => (),
+                RelayMessage::Pong(_) => {
+                    if let Some(sent) = last_ping.take() {
+                        chunk_sizer.lock().unwrap().record_rtt(sent.elapsed());
+                    }
+                }
             }
         }
     }
 
+    /// Ask the receiver to open `count` data streams and open them on this side, a stream
+    /// failing to open is reported and the chunks are striped across the others.
+    async fn open_data_streams(
+        encryptor: &Encryptor,
+        endpoint: &Endpoint,
+        tx: &mpsc::Sender<RelayUpdate>,
+        sender_stream_tx: &mpsc::Sender<SenderInteractionMessage>,
+        slot: u32,
+        count: usize,
+        shutdown: Shutdown,
+    ) -> Result<Vec<mpsc::Sender<RelayUpdate>>> {
+        let count = count.min(MAX_DATA_STREAMS as usize);
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        send_msg_to_relay(
+            tx,
+            RelayMessage::Sender(SenderUpdate {
+                sender_message: Some(SenderMessage::OpenStreams(OpenStreams {
+                    count: count as u32,
+                })),
+            }),
+        )
+        .await?;
+        let mut data_streams = Vec::with_capacity(count);
+        for stream in 1..=count as u32 {
+            let (client, data_tx, mut messages) = match open_data_stream(encryptor, endpoint, Character::Sender, slot, stream).await {
+                Ok(opened) => opened,
+                Err(e) => {
+                    Self::send_msg_to_stream(
+                        sender_stream_tx,
+                        SenderInteractionMessage::Message(format!("Open data stream {stream} failed: {e}")),
+                    )
+                    .await?;
+                    continue;
+                }
+            };
+            let shutdown = shutdown.clone();
+            tokio::spawn(async move {
+                // nothing but the relay's own replies comes back on a data stream
+                let _client = client;
+                loop {
+                    tokio::select! {
+                        _ = shutdown.wait() => return,
+                        item = messages.next() => {
+                            if !matches!(item, Some(Ok(_))) {
+                                return;
+                            }
+                        }
+                    }
+                }
+            });
+            data_streams.push(data_tx);
+        }
+        Ok(data_streams)
+    }
+
     fn extract_confirm_file_id(confirm: &FileConfirm) -> Option<u64> {
         confirm.confirm_message.as_ref().map(|msg| match msg {
             ConfirmMessage::NewFileConfirm(c) => c.file_id,
             ConfirmMessage::BreakPointConfirm(c) => c.file_id,
+            ConfirmMessage::BatchConfirm(c) => c.batch_id,
         })
     }
 
+    /// Send the files in the background, an error is reported to the sender stream.
+    fn spawn_send_files(
+        transfer: TransferContext,
+        file_collector: Arc<FileCollector>,
+        notify: async_channel::Receiver<FileConfirm>,
+        resume_progress: Option<HashMap<u64, (u64, bool)>>,
+        selection: Option<HashSet<u64>>,
+        file_batches: bool,
+        concurrency: usize,
+    ) {
+        tokio::spawn(async move {
+            let sender_stream_tx = transfer.sender_stream_tx.clone();
+            if let Err(err) = Self::send_files(
+                transfer,
+                file_collector,
+                notify,
+                resume_progress,
+                selection,
+                file_batches,
+                concurrency,
+            )
+            .await
+            {
+                let _ = Self::send_msg_to_stream(
+                    &sender_stream_tx,
+                    SenderInteractionMessage::Error(format!("send files error {}", err)),
+                )
+                .await;
+            }
+        });
+    }
+
     async fn send_files(
-        encryptor: Arc<Encryptor>,
-        tx: mpsc::Sender<RelayUpdate>,
+        transfer: TransferContext,
         file_collector: Arc<FileCollector>,
         notify: async_channel::Receiver<FileConfirm>,
-        sender_stream_tx: &mpsc::Sender<SenderInteractionMessage>,
-        cancel: Shutdown,
         resume_progress: Option<HashMap<u64, (u64, bool)>>,
+        selection: Option<HashSet<u64>>,
+        file_batches: bool,
+        concurrency: usize,
     ) -> Result<()> {
-        let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_FILES));
-        let confirm_waiters: Arc<std::sync::Mutex<HashMap<u64, oneshot::Sender<FileConfirm>>>> = Arc::new(std::sync::Mutex::new(HashMap::new()));
+        let semaphore = Arc::new(Semaphore::new(concurrency));
 
-        let confirm_waiters_ref = confirm_waiters.clone();
-        let cancel_ref = cancel.clone();
+        let confirm_waiters_ref = transfer.confirm_waiters.clone();
+        let cancel_ref = transfer.cancel.clone();
         let dispatcher = tokio::spawn(async move {
             loop {
                 if cancel_ref.is_terminated() {
@@ -661,64 +1841,120 @@ impl FlashCatSender {
             }
         });
 
-        let mut tasks = Vec::new();
+        let mut first_error = None;
+        // links are sent last, a hard link needs the file it shares the content of
+        for links in [false, true] {
+            let mut tasks = Vec::new();
+            let mut batch: Vec<FileInfo> = Vec::new();
+            let mut batch_size = 0;
+
+            for send_file in file_collector.files.iter().filter(|send_file| send_file.kind.is_link() == links) {
+                if transfer.pause.is_paused() {
+                    tokio::select! {
+                        _ = transfer.pause.resumed() => (),
+                        _ = transfer.cancel.wait() => (),
+                    }
+                }
+                if transfer.cancel.is_terminated() {
+                    break;
+                }
 
-        for send_file in file_collector.files.iter() {
-            if cancel.is_terminated() {
-                break;
-            }
+                if selection.as_ref().is_some_and(|selection| !selection.contains(&send_file.file_id)) {
+                    Self::send_msg_to_stream(
+                        &transfer.sender_stream_tx,
+                        SenderInteractionMessage::ContinueFile(send_file.file_id),
+                    )
+                    .await?;
+                    continue;
+                }
 
-            if let Some(ref progress) = resume_progress {
-                if let Some(&(_, completed)) = progress.get(&send_file.file_id) {
-                    if completed {
-                        continue;
+                if let Some(ref progress) = resume_progress {
+                    if let Some(&(_, completed)) = progress.get(&send_file.file_id) {
+                        if completed {
+                            continue;
+                        }
                     }
                 }
-            }
 
-            let file_resume = resume_progress.as_ref().and_then(|p| p.get(&send_file.file_id).copied());
+                let file_cancel = transfer.cancels.token(send_file.file_id);
+                if file_cancel.is_terminated() {
+                    Self::send_msg_to_stream(
+                        &transfer.sender_stream_tx,
+                        SenderInteractionMessage::FileCancelled(send_file.file_id),
+                    )
+                    .await?;
+                    continue;
+                }
 
-            let permit = semaphore.clone().acquire_owned().await.map_err(|e| anyhow::anyhow!("semaphore closed: {}", e))?;
+                let file_resume = resume_progress.as_ref().and_then(|p| p.get(&send_file.file_id).copied());
+
+                let batched_size = Self::batched_size(send_file);
+                if file_batches
+                    && send_file.kind == FileKind::Regular
+                    && !send_file.empty_dir
+                    && send_file.size <= SMALL_FILE_SIZE
+                    && batched_size <= MAX_BATCH_SIZE
+                    && file_resume.is_none_or(|(received_bytes, _)| received_bytes == 0)
+                {
+                    if batch.len() == MAX_BATCH_FILES || batch_size + batched_size > MAX_BATCH_SIZE {
+                        let permit = semaphore.clone().acquire_owned().await.map_err(|e| anyhow::anyhow!("semaphore closed: {}", e))?;
+                        tasks.push(Self::spawn_batch(transfer.clone(), std::mem::take(&mut batch), permit));
+                        batch_size = 0;
+                    }
+                    batch_size += batched_size;
+                    batch.push(send_file.clone());
+                    continue;
+                }
 
-            let send_file = send_file.clone();
-            let encryptor = encryptor.clone();
-            let tx = tx.clone();
-            let sender_stream_tx = sender_stream_tx.clone();
-            let cancel = cancel.clone();
-            let confirm_waiters = confirm_waiters.clone();
-
-            let task = tokio::spawn(async move {
-                let result = Self::send_single_file(
-                    &send_file,
-                    &encryptor,
-                    &tx,
-                    &sender_stream_tx,
-                    &cancel,
-                    &confirm_waiters,
-                    file_resume,
-                )
-                .await;
-                drop(permit);
-                result
-            });
-            tasks.push(task);
-        }
+                let permit = semaphore.clone().acquire_owned().await.map_err(|e| anyhow::anyhow!("semaphore closed: {}", e))?;
 
-        let mut first_error = None;
-        for task in tasks {
-            match task.await {
-                Ok(Ok(())) => {}
-                Ok(Err(e)) => {
-                    if first_error.is_none() {
-                        first_error = Some(e);
+                let send_file = send_file.clone();
+                let transfer = transfer.clone();
+
+                let task = tokio::spawn(async move {
+                    // a cancelled file stops where it is and makes room for the next one
+                    let (result, cancelled) = tokio::select! {
+                        result = Self::send_single_file(&transfer, &send_file, file_resume) => (result, false),
+                        _ = file_cancel.wait() => (Ok(()), true),
+                    };
+                    drop(permit);
+                    if cancelled {
+                        transfer.confirm_waiters.lock().unwrap().remove(&send_file.file_id);
+                        Self::send_msg_to_stream(
+                            &transfer.sender_stream_tx,
+                            SenderInteractionMessage::FileCancelled(send_file.file_id),
+                        )
+                        .await?;
                     }
-                }
-                Err(e) => {
-                    if first_error.is_none() {
-                        first_error = Some(anyhow::anyhow!("task panicked: {}", e));
+                    result
+                });
+                tasks.push(task);
+            }
+
+            if !batch.is_empty() && !transfer.cancel.is_terminated() {
+                let permit = semaphore.clone().acquire_owned().await.map_err(|e| anyhow::anyhow!("semaphore closed: {}", e))?;
+                tasks.push(Self::spawn_batch(transfer.clone(), batch, permit));
+            }
+
+            for task in tasks {
+                match task.await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        if first_error.is_none() {
+                            first_error = Some(e);
+                        }
+                    }
+                    Err(e) => {
+                        if first_error.is_none() {
+                            first_error = Some(anyhow::anyhow!("task panicked: {}", e));
+                        }
                     }
                 }
             }
+
+            if first_error.is_some() {
+                break;
+            }
         }
 
         dispatcher.abort();
@@ -726,42 +1962,42 @@ impl FlashCatSender {
         if let Some(e) = first_error {
             return Err(e);
         }
+        // the receiver is gone, a Done would reach the next one taking its place
+        if transfer.cancel.is_terminated() {
+            return Ok(());
+        }
 
-        send_msg_to_relay(&tx, RelayMessage::Done(Done {})).await?;
-        Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::SendDone).await?;
+        send_msg_to_relay(&transfer.tx, RelayMessage::Done(Done {})).await?;
+        Self::send_msg_to_stream(&transfer.sender_stream_tx, SenderInteractionMessage::SendDone).await?;
         Ok(())
     }
 
     async fn send_single_file(
+        transfer: &TransferContext,
         send_file: &FileInfo,
-        encryptor: &Encryptor,
-        tx: &mpsc::Sender<RelayUpdate>,
-        sender_stream_tx: &mpsc::Sender<SenderInteractionMessage>,
-        cancel: &Shutdown,
-        confirm_waiters: &std::sync::Mutex<HashMap<u64, oneshot::Sender<FileConfirm>>>,
         file_resume: Option<(u64, bool)>,
     ) -> Result<()> {
+        let TransferContext {
+            cipher,
+            tx,
+            sender_stream_tx,
+            confirm_waiters,
+            sent,
+            ..
+        } = transfer;
         // Resume: partial file — send BreakPoint and stream remaining data
         if let Some((received_bytes, _)) = file_resume {
             if received_bytes > 0 && !send_file.empty_dir {
+                if send_file.kind == FileKind::Stream {
+                    bail!("the data read from stdin can't be sent again after a reconnect");
+                }
                 let _ = Self::send_msg_to_stream(
                     sender_stream_tx,
                     SenderInteractionMessage::Message(format!("Resuming file {} from {}", send_file.name, received_bytes)),
                 )
                 .await;
 
-                send_msg_to_relay(
-                    tx,
-                    RelayMessage::Sender(SenderUpdate {
-                        sender_message: Some(SenderMessage::BreakPoint(BreakPoint {
-                            file_id: send_file.file_id,
-                            position: received_bytes,
-                        })),
-                    }),
-                )
-                .await?;
-
-                Self::stream_file_data(send_file, encryptor, tx, sender_stream_tx, cancel, received_bytes).await?;
+                Self::stream_file_data(transfer, send_file, &[(received_bytes, u64::MAX)]).await?;
                 return Ok(());
             }
         }
@@ -770,41 +2006,21 @@ impl FlashCatSender {
         let (confirm_tx, confirm_rx) = oneshot::channel();
         confirm_waiters.lock().unwrap().insert(send_file.file_id, confirm_tx);
 
+        let metadata = Self::file_metadata(send_file);
         send_msg_to_relay(
             tx,
             RelayMessage::Sender(SenderUpdate {
                 sender_message: Some(SenderMessage::NewFileRequest(NewFileRequest {
                     file_id: send_file.file_id,
-                    filename: send_file.name.clone(),
-                    #[cfg(unix)]
-                    file_mode: send_file.mode,
-                    #[cfg(windows)]
-                    file_mode: 0,
-                    relative_path: send_file.relative_path.clone(),
-                    total_size: send_file.size,
-                    is_empty_dir: send_file.empty_dir,
+                    sealed_metadata: cipher.seal(&metadata, &send_file.file_id.to_be_bytes())?,
                 })),
             }),
         )
         .await?;
 
-        let file_confirm = match tokio::time::timeout(FILE_CONFIRM_TIMEOUT, confirm_rx).await {
-            Ok(Ok(file_confirm)) => file_confirm,
-            Ok(Err(_)) => {
-                confirm_waiters.lock().unwrap().remove(&send_file.file_id);
-                bail!("confirm channel closed for file {}", send_file.file_id);
-            }
-            Err(_) => {
-                confirm_waiters.lock().unwrap().remove(&send_file.file_id);
-                bail!(
-                    "timed out waiting for receiver confirmation for file {} after {}s",
-                    send_file.file_id,
-                    FILE_CONFIRM_TIMEOUT.as_secs()
-                );
-            }
-        };
+        let file_confirm = Self::wait_confirm(send_file.file_id, confirm_rx, confirm_waiters).await?;
 
-        let mut position = 0;
+        let mut ranges = vec![(0, u64::MAX)];
 
         if let Some(confirm_message) = file_confirm.confirm_message {
             match confirm_message {
@@ -817,7 +2033,7 @@ impl FlashCatSender {
                         .await?;
                         return Ok(());
                     }
-                    if new_file_confirm.confirm == Confirm::Reject.into() {
+                    if new_file_confirm.confirm == i32::from(Confirm::Reject) {
                         Self::send_msg_to_stream(
                             sender_stream_tx,
                             SenderInteractionMessage::ContinueFile(new_file_confirm.file_id),
@@ -835,90 +2051,460 @@ impl FlashCatSender {
                         .await?;
                         return Ok(());
                     }
-                    if break_point_confirm.confirm == Confirm::Accept.into() {
-                        position = break_point_confirm.position;
-                        send_msg_to_relay(
-                            tx,
-                            RelayMessage::Sender(SenderUpdate {
-                                sender_message: Some(SenderMessage::BreakPoint(BreakPoint {
-                                    file_id: send_file.file_id,
-                                    position,
-                                })),
-                            }),
-                        )
-                        .await?;
+                    if break_point_confirm.confirm == i32::from(Confirm::Accept) {
+                        let chunk_hashes: ChunkHashes = cipher.open(
+                            break_point_confirm.sealed_chunk_hashes.as_ref(),
+                            &send_file.file_id.to_be_bytes(),
+                        )?;
+                        if chunk_hashes.chunk_size == 0 {
+                            bail!("invalid resume chunk size for file {}", send_file.file_id);
+                        }
+                        let read_file = File::open(send_file.access_path.as_str()).await?;
+                        let local_hashes = hash_chunks(read_file, chunk_hashes.chunk_size).await?;
+                        ranges = mismatched_ranges(&local_hashes, &chunk_hashes.hashes, chunk_hashes.chunk_size);
                     }
                 }
+                ConfirmMessage::BatchConfirm(_) => {
+                    bail!("unexpected batch confirm for file {}", send_file.file_id);
+                }
             }
         }
 
-        if send_file.empty_dir {
+        if send_file.empty_dir || send_file.kind.is_link() {
+            sent.add(send_file.file_id, 0);
+            return Ok(());
+        }
+
+        Self::stream_file_data(transfer, send_file, &ranges).await
+    }
+
+    fn spawn_batch(
+        transfer: TransferContext,
+        files: Vec<FileInfo>,
+        permit: OwnedSemaphorePermit,
+    ) -> JoinHandle<Result<()>> {
+        tokio::spawn(async move {
+            let result = Self::send_batch(&transfer, &files).await;
+            drop(permit);
+            result
+        })
+    }
+
+    /// Send small files together with their content in a single message, the receiver
+    /// confirms the whole batch once every file is written or rejected. The files cancelled
+    /// until the batch is sent are left out of it.
+    async fn send_batch(
+        transfer: &TransferContext,
+        files: &[FileInfo],
+    ) -> Result<()> {
+        let TransferContext {
+            cipher,
+            tx,
+            sender_stream_tx,
+            confirm_waiters,
+            cancels,
+            sent,
+            ..
+        } = transfer;
+        let mut batched = Vec::with_capacity(files.len());
+        for send_file in files {
+            if cancels.token(send_file.file_id).is_terminated() {
+                Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::FileCancelled(send_file.file_id)).await?;
+            } else {
+                batched.push(send_file);
+            }
+        }
+        let Some(first) = batched.first() else {
             return Ok(());
+        };
+        let batch_id = first.file_id;
+        let mut batch = BatchedFiles::default();
+        for send_file in batched.iter() {
+            let mut content = Vec::with_capacity(send_file.size as usize);
+            File::open(send_file.access_path.as_str()).await?.take(SMALL_FILE_SIZE + 1).read_to_end(&mut content).await?;
+            if content.len() as u64 > SMALL_FILE_SIZE {
+                bail!("file {} grew while sending", send_file.name);
+            }
+            let mut metadata = Self::file_metadata(send_file);
+            metadata.total_size = content.len() as u64;
+            batch.files.push(BatchedFile {
+                file_id: send_file.file_id,
+                metadata: Some(metadata),
+                content: Bytes::from(content),
+            });
+        }
+        let encoded = batch.encode_to_vec();
+        if encoded.len() > MAX_BATCH_DATA_SIZE {
+            bail!("the files of batch {batch_id} grew while sending");
+        }
+        let compressed = if transfer.compress {
+            compress_chunk(&encoded)
+        } else {
+            None
+        };
+        let sealed_files = cipher.encrypt_with_aad(
+            compressed.as_deref().unwrap_or(&encoded),
+            &batch_aad(batch_id, compressed.is_some()),
+        )?;
+        if let Some(rate_limiter) = transfer.rate_limiter.as_ref() {
+            rate_limiter.acquire(sealed_files.len()).await;
+        }
+
+        let (confirm_tx, confirm_rx) = oneshot::channel();
+        confirm_waiters.lock().unwrap().insert(batch_id, confirm_tx);
+        send_msg_to_relay(
+            tx,
+            RelayMessage::Sender(SenderUpdate {
+                sender_message: Some(SenderMessage::FileBatch(FileBatch {
+                    batch_id,
+                    sealed_files: Bytes::from(sealed_files),
+                    compressed: compressed.is_some(),
+                })),
+            }),
+        )
+        .await?;
+
+        let file_confirm = Self::wait_confirm(batch_id, confirm_rx, confirm_waiters).await?;
+        let Some(ConfirmMessage::BatchConfirm(batch_confirm)) = file_confirm.confirm_message else {
+            bail!("unexpected confirm for batch {batch_id}");
+        };
+        let rejected = batch_confirm.rejected.into_iter().collect::<HashSet<_>>();
+        for send_file in batched {
+            let msg = if rejected.contains(&send_file.file_id) && cancels.token(send_file.file_id).is_terminated() {
+                SenderInteractionMessage::FileCancelled(send_file.file_id)
+            } else if rejected.contains(&send_file.file_id) {
+                SenderInteractionMessage::ContinueFile(send_file.file_id)
+            } else {
+                sent.add(send_file.file_id, send_file.size);
+                SenderInteractionMessage::FileProgressFinish(send_file.file_id)
+            };
+            Self::send_msg_to_stream(sender_stream_tx, msg).await?;
         }
+        Ok(())
+    }
 
-        Self::stream_file_data(send_file, encryptor, tx, sender_stream_tx, cancel, position).await
+    /// Size of a file encoded in a batch, its metadata and framing included.
+    fn batched_size(send_file: &FileInfo) -> usize {
+        let file = BatchedFile {
+            file_id: send_file.file_id,
+            metadata: Some(Self::file_metadata(send_file)),
+            content: Bytes::new(),
+        };
+        // the tags and lengths of the content and of the file in the batch
+        file.encoded_len() + send_file.size as usize + 8
+    }
+
+    fn file_metadata(send_file: &FileInfo) -> FileMetadata {
+        FileMetadata {
+            filename: send_file.name.clone(),
+            #[cfg(unix)]
+            file_mode: send_file.mode,
+            #[cfg(windows)]
+            file_mode: 0,
+            relative_path: send_file.relative_path.clone(),
+            total_size: send_file.size,
+            is_empty_dir: send_file.empty_dir,
+            kind: match send_file.kind {
+                FileKind::Regular => EntryKind::Regular,
+                FileKind::Dir => EntryKind::Dir,
+                FileKind::Symlink(_) => EntryKind::Symlink,
+                FileKind::Hardlink(_) => EntryKind::Hardlink,
+                FileKind::Archive => EntryKind::Archive,
+                FileKind::ZippedFolder => EntryKind::ZippedFolder,
+                FileKind::Stream => EntryKind::Stream,
+            }
+            .into(),
+            link_target: match &send_file.kind {
+                FileKind::Symlink(target) | FileKind::Hardlink(target) => target.clone(),
+                _ => String::new(),
+            },
+            attributes: send_file.attributes.clone().map(Into::into),
+        }
     }
 
+    /// Wait for the receiver to confirm a file or batch registered under `id`.
+    async fn wait_confirm(
+        id: u64,
+        confirm_rx: oneshot::Receiver<FileConfirm>,
+        confirm_waiters: &Mutex<HashMap<u64, oneshot::Sender<FileConfirm>>>,
+    ) -> Result<FileConfirm> {
+        match tokio::time::timeout(FILE_CONFIRM_TIMEOUT, confirm_rx).await {
+            Ok(Ok(file_confirm)) => Ok(file_confirm),
+            Ok(Err(_)) => {
+                confirm_waiters.lock().unwrap().remove(&id);
+                bail!("confirm channel closed for file {}", id);
+            }
+            Err(_) => {
+                confirm_waiters.lock().unwrap().remove(&id);
+                bail!(
+                    "timed out waiting for receiver confirmation for file {} after {}s",
+                    id,
+                    FILE_CONFIRM_TIMEOUT.as_secs()
+                );
+            }
+        }
+    }
+
+    /// Stream the given byte ranges of a file (`u64::MAX` as end means up to EOF). The
+    /// whole file is read so the digest in `FileDone` covers it, parts outside of the
+    /// ranges are only hashed. Chunks are compressed first if the transfer does and it pays
+    /// off. A whole file is striped across the control stream and the data streams, the
+    /// chunks are held back by the rate limiter if set and while paused. The chunks of a
+    /// file sent to the receivers of a broadcast session are read once for all.
     async fn stream_file_data(
+        transfer: &TransferContext,
         send_file: &FileInfo,
-        encryptor: &Encryptor,
-        tx: &mpsc::Sender<RelayUpdate>,
-        sender_stream_tx: &mpsc::Sender<SenderInteractionMessage>,
-        cancel: &Shutdown,
-        start_position: u64,
+        ranges: &[(u64, u64)],
     ) -> Result<()> {
-        let mut read_file = File::open(send_file.access_path.as_str()).await?;
-        read_file.seek(SeekFrom::Start(start_position)).await?;
-        let mut position = start_position;
+        let TransferContext {
+            cipher,
+            tx,
+            sender_stream_tx,
+            cancel,
+            chunk_sizer,
+            data_streams,
+            pause,
+            sent,
+            ..
+        } = transfer;
+        let file_cipher = cipher.file_cipher(send_file.file_id)?;
+        let (source, mut archive_builder) = match (&send_file.kind, transfer.broadcast.as_ref()) {
+            // the archive is built once into a file the receivers all read from
+            (FileKind::Archive, Some(broadcast)) => {
+                let archive = broadcast.archive(send_file.file_id, &send_file.access_path).await?;
+                let file = File::open(archive).await?;
+                (Self::shared_source(file, send_file.file_id, broadcast), None)
+            }
+            (FileKind::Archive, None) => {
+                let (archive, builder) = stream_tar_archive(PathBuf::from(&send_file.access_path));
+                (ChunkSource::Reader(Box::pin(archive)), Some(builder))
+            }
+            (FileKind::Stream, _) => (ChunkSource::Reader(Box::pin(tokio::io::stdin())), None),
+            (_, Some(broadcast)) => {
+                let file = File::open(send_file.access_path.as_str()).await?;
+                (Self::shared_source(file, send_file.file_id, broadcast), None)
+            }
+            (_, None) => {
+                let file = File::open(send_file.access_path.as_str()).await?;
+                (ChunkSource::Reader(Box::pin(file)), None)
+            }
+        };
+        let (chunk_tx, mut chunk_rx) = mpsc::channel(PIPELINE_DEPTH);
+        let reader = Self::read_chunks(source, ranges.to_vec(), chunk_sizer.clone(), chunk_tx);
+        let mut hasher = blake3::Hasher::new();
+        let mut receiver_position = 0;
+        let mut size = 0;
+        // a resume seeks the receiver to the ranges, which only works in order
+        let striped = !data_streams.is_empty() && ranges == [(0, u64::MAX)];
+        let mut chunks_sent = 0;
         loop {
+            if pause.is_paused() {
+                // the chunks read ahead wait in the channel meanwhile
+                tokio::select! {
+                    _ = pause.resumed() => (),
+                    _ = cancel.wait() => (),
+                }
+            }
             if cancel.is_terminated() {
+                reader.abort();
                 return Ok(());
             }
-            let mut buffer = BytesMut::with_capacity(SEND_BUFF_SIZE);
-            let read_length = read_file.read_buf(&mut buffer).await?;
-            if read_length == 0 {
+            let Some(FileChunk {
+                position,
+                in_range,
+                data,
+            }) = chunk_rx.recv().await
+            else {
+                reader.await??;
+                if let Some(builder) = archive_builder.take() {
+                    // an archive cut short by an error must not be reported as done
+                    builder.await??;
+                }
                 send_msg_to_relay(
                     tx,
                     RelayMessage::Sender(SenderUpdate {
                         sender_message: Some(SenderMessage::FileDone(FileDone {
                             file_id: send_file.file_id,
+                            sealed_digest: Bytes::from(cipher.encrypt_with_aad(hasher.finalize().as_bytes(), &send_file.file_id.to_be_bytes())?),
+                            length: if striped {
+                                receiver_position
+                            } else {
+                                0
+                            },
+                            size,
                         })),
                     }),
                 )
                 .await?;
+                sent.add(send_file.file_id, size);
                 Self::send_msg_to_stream(
                     sender_stream_tx,
                     SenderInteractionMessage::FileProgressFinish(send_file.file_id),
                 )
                 .await?;
                 return Ok(());
-            }
-            send_msg_to_relay(
-                tx,
-                RelayMessage::Sender(SenderUpdate {
+            };
+            hasher.update(&data);
+            size = position + data.len() as u64;
+            if in_range {
+                if receiver_position != position {
+                    send_msg_to_relay(
+                        tx,
+                        RelayMessage::Sender(SenderUpdate {
+                            sender_message: Some(SenderMessage::BreakPoint(BreakPoint {
+                                file_id: send_file.file_id,
+                                position,
+                            })),
+                        }),
+                    )
+                    .await?;
+                }
+                let compressed = if transfer.compress {
+                    compress_chunk(data.as_ref())
+                } else {
+                    None
+                };
+                let nonce = cipher.next_chunk_nonce(send_file.file_id)?;
+                let encrypted = file_cipher.encrypt_chunk(
+                    &nonce,
+                    position,
+                    compressed.is_some(),
+                    compressed.as_deref().unwrap_or(data.as_ref()),
+                )?;
+                // a data stream gone is left out, its chunks would never arrive
+                let open_streams: Vec<_> = data_streams.iter().filter(|stream_tx| striped && !stream_tx.is_closed()).collect();
+                let stream_tx = match chunks_sent % (open_streams.len() + 1) {
+                    0 => tx,
+                    stream => open_streams[stream - 1],
+                };
+                chunks_sent += 1;
+                if let Some(rate_limiter) = transfer.rate_limiter.as_ref() {
+                    rate_limiter.acquire(encrypted.len()).await;
+                }
+                let file_data = RelayMessage::Sender(SenderUpdate {
                     sender_message: Some(SenderMessage::FileData(FileData {
                         file_id: send_file.file_id,
-                        data: Bytes::from(encryptor.encrypt(buffer.as_ref())?),
+                        data: Bytes::from(encrypted),
+                        offset: position,
+                        nonce: Bytes::copy_from_slice(&nonce),
+                        compressed: compressed.is_some(),
                     })),
-                }),
-            )
-            .await?;
-            position += read_length as u64;
+                });
+                if let Err(e) = send_msg_to_relay(stream_tx, file_data.clone()).await {
+                    // the chunk goes over the control stream instead
+                    if std::ptr::eq(stream_tx, tx) {
+                        return Err(e);
+                    }
+                    send_msg_to_relay(tx, file_data).await?;
+                }
+                receiver_position = position + data.len() as u64;
+                let stats = chunk_sizer.lock().unwrap().record_sent(data.len());
+                if let Some(stats) = stats {
+                    Self::send_msg_to_stream(sender_stream_tx, SenderInteractionMessage::Stats(stats)).await?;
+                }
+            }
             Self::send_msg_to_stream(
                 sender_stream_tx,
                 SenderInteractionMessage::FileProgress(Progress {
                     file_id: send_file.file_id,
-                    position,
+                    position: position + data.len() as u64,
                 }),
             )
             .await?;
         }
     }
 
+    fn shared_source(
+        file: File,
+        file_id: u64,
+        broadcast: &Arc<Broadcast>,
+    ) -> ChunkSource {
+        ChunkSource::Shared {
+            file,
+            offset: 0,
+            file_id,
+            broadcast: broadcast.clone(),
+        }
+    }
+
+    /// Read chunks sized by `chunk_sizer` ahead of sending them, a chunk never crosses
+    /// the start or end of a range.
+    fn read_chunks(
+        mut source: ChunkSource,
+        ranges: Vec<(u64, u64)>,
+        chunk_sizer: Arc<Mutex<ChunkSizer>>,
+        chunk_tx: mpsc::Sender<FileChunk>,
+    ) -> JoinHandle<Result<()>> {
+        tokio::spawn(async move {
+            let mut ranges = ranges.into_iter().peekable();
+            let mut position = 0;
+            loop {
+                while ranges.next_if(|&(_, end)| end <= position).is_some() {}
+                let (in_range, limit) = match ranges.peek() {
+                    Some(&(start, end)) if start <= position => (true, end - position),
+                    Some(&(start, _)) => (false, start - position),
+                    None => (false, u64::MAX),
+                };
+                let limit = limit.min(chunk_sizer.lock().unwrap().chunk_size() as u64) as usize;
+                let data = source.read(position, limit).await?;
+                if data.is_empty() {
+                    return Ok(());
+                }
+                let length = data.len() as u64;
+                let chunk = FileChunk {
+                    position,
+                    in_range,
+                    data,
+                };
+                if chunk_tx.send(chunk).await.is_err() {
+                    // the file is no longer streamed
+                    return Ok(());
+                }
+                position += length;
+            }
+        })
+    }
+
     pub fn get_file_collector(&self) -> Arc<FileCollector> {
         self.file_collector.clone()
     }
 
+    /// Text sent instead of files, if any.
+    pub fn get_text(&self) -> Option<&str> {
+        self.text.as_deref()
+    }
+
+    /// Set when the receivers are served one after another.
+    pub fn keep_alive(&self) -> Option<KeepAlive> {
+        self.keep_alive
+    }
+
+    /// Number of receivers accepted, more than one for a broadcast session.
+    pub fn max_receivers(&self) -> u32 {
+        self.broadcast.as_ref().map_or(1, |broadcast| broadcast.max_receivers())
+    }
+
+    /// Pause the transfer, the receiver is told so and the relay channel stays open.
+    pub fn pause(&self) {
+        self.pause.set(true);
+    }
+
+    pub fn resume(&self) {
+        self.pause.set(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause.is_paused()
+    }
+
+    /// Cancel a single file, the receiver is told so and the other files go on.
+    pub fn cancel_file(
+        &self,
+        file_id: u64,
+    ) {
+        let _ = self.file_cancel.send(file_id);
+    }
+
     pub fn shutdown(&self) {
         let _ = self.clean_zip_files();
         self.local_relay_shutdown.shutdown();
@@ -977,3 +2563,169 @@ impl FlashCatSender {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use anyhow::Result;
+    use prost::Message;
+    use tokio::sync::{mpsc, oneshot};
+
+    use flash_cat_common::{
+        Pause, Shutdown,
+        consts::MAX_TEXT_SIZE,
+        crypt::encryptor::SessionCipher,
+        proto::{
+            BatchConfirm, BatchedFiles, FileConfirm, RelayUpdate, TextPayload, TransferSummary, file_confirm::ConfirmMessage, relay_update::RelayMessage,
+            sender_update::SenderMessage,
+        },
+        utils::{fs::FileInfo, gen_share_code},
+    };
+
+    use super::{FileCancels, FlashCatSender, KeepAlive, SendOptions, SentFiles, TransferContext};
+    use crate::{SenderInteractionMessage, batch_aad, chunk::ChunkSizer};
+
+    fn new_with_text(text: &str) -> Result<FlashCatSender> {
+        FlashCatSender::new_with_text(gen_share_code(), text.to_string(), SendOptions::default())
+    }
+
+    #[test]
+    fn text_size_is_checked() {
+        assert!(new_with_text("").is_err());
+        assert!(new_with_text(&"a".repeat(MAX_TEXT_SIZE + 1)).is_err());
+        assert!(new_with_text(&"a".repeat(MAX_TEXT_SIZE)).is_ok());
+    }
+
+    #[test]
+    fn text_is_sealed_apart_from_the_summary() -> Result<()> {
+        let sender = new_with_text("hello")?;
+        let cipher = SessionCipher::new(&[7; 32]);
+        let request = FlashCatSender::send_request(&cipher, &sender.file_collector, sender.text.as_deref())?;
+
+        let text: TextPayload = cipher.open(request.sealed_text.as_ref(), b"text")?;
+        assert_eq!(text.text, "hello");
+        let summary: TransferSummary = cipher.open(request.sealed_summary.as_ref(), b"summary")?;
+        assert_eq!(summary.num_files, 0);
+        // swapped by the relay
+        assert!(cipher.open::<TransferSummary>(request.sealed_text.as_ref(), b"summary").is_err());
+        assert!(cipher.open::<TextPayload>(request.sealed_summary.as_ref(), b"text").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cancelled_files_are_left_out_of_the_batch() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut files = Vec::new();
+        for (file_id, name) in ["alpha", "bravo"].into_iter().enumerate() {
+            let path = dir.path().join(name);
+            std::fs::write(&path, name)?;
+            files.push(FileInfo {
+                file_id: file_id as u64,
+                name: name.to_string(),
+                access_path: path.to_string_lossy().to_string(),
+                relative_path: name.to_string(),
+                size: name.len() as u64,
+                ..Default::default()
+            });
+        }
+        let cipher = Arc::new(SessionCipher::new(&[7; 32]));
+        let (tx, mut rx) = mpsc::channel::<RelayUpdate>(4);
+        let (sender_stream_tx, mut sender_stream_rx) = mpsc::channel(4);
+        let confirm_waiters = Arc::new(Mutex::new(HashMap::<u64, oneshot::Sender<FileConfirm>>::new()));
+        let cancels = FileCancels::default();
+        cancels.cancel(0);
+        let sent = SentFiles::default();
+        let transfer = TransferContext {
+            cipher: cipher.clone(),
+            tx,
+            sender_stream_tx,
+            cancel: Shutdown::new(),
+            compress: false,
+            chunk_sizer: Arc::new(Mutex::new(ChunkSizer::new())),
+            data_streams: Arc::default(),
+            rate_limiter: None,
+            pause: Pause::new(),
+            cancels: cancels.clone(),
+            sent: sent.clone(),
+            broadcast: None,
+            confirm_waiters: confirm_waiters.clone(),
+        };
+
+        let receive = async {
+            let Some(RelayMessage::Sender(update)) = rx.recv().await.and_then(|update| update.relay_message) else {
+                panic!("no batch sent");
+            };
+            let Some(SenderMessage::FileBatch(file_batch)) = update.sender_message else {
+                panic!("no batch sent");
+            };
+            let data = cipher.decrypt_with_aad(file_batch.sealed_files.as_ref(), &batch_aad(file_batch.batch_id, false)).unwrap();
+            let batch = BatchedFiles::decode(data.as_slice()).unwrap();
+            let waiter = confirm_waiters.lock().unwrap().remove(&file_batch.batch_id).unwrap();
+            let _ = waiter.send(FileConfirm {
+                confirm_message: Some(ConfirmMessage::BatchConfirm(BatchConfirm {
+                    batch_id: file_batch.batch_id,
+                    rejected: Vec::new(),
+                })),
+            });
+            (
+                file_batch.batch_id,
+                batch.files.iter().map(|file| file.file_id).collect::<Vec<_>>(),
+            )
+        };
+        let (result, (batch_id, batched)) = tokio::join!(FlashCatSender::send_batch(&transfer, &files), receive);
+        result?;
+        assert_eq!(batch_id, 1);
+        assert_eq!(batched, vec![1]);
+        assert!(matches!(
+            sender_stream_rx.recv().await,
+            Some(SenderInteractionMessage::FileCancelled(0))
+        ));
+        assert!(matches!(
+            sender_stream_rx.recv().await,
+            Some(SenderInteractionMessage::FileProgressFinish(1))
+        ));
+        assert_eq!(sent.total(), (1, 5));
+
+        // nothing is sent once every file of the batch is cancelled
+        cancels.cancel(1);
+        FlashCatSender::send_batch(&transfer, &files).await?;
+        assert!(rx.try_recv().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn batched_size_counts_the_metadata() {
+        let send_file = FileInfo {
+            file_id: 1,
+            name: "a.txt".to_string(),
+            relative_path: format!("{}/a.txt", "nested/".repeat(100)),
+            size: 10,
+            ..Default::default()
+        };
+        assert!(FlashCatSender::batched_size(&send_file) > send_file.size as usize + send_file.relative_path.len());
+    }
+
+    #[test]
+    fn rejecting_receiver_leaves_a_kept_alive_share() -> Result<()> {
+        let downloads = FlashCatSender::downloads(1, Some(KeepAlive::default()), false)?.unwrap();
+        let mut receiver = Some(downloads.join());
+        assert!(matches!(
+            FlashCatSender::receiver_rejected(&mut receiver, true),
+            SenderInteractionMessage::ReceiverLeft(1)
+        ));
+        // the next receiver is let in
+        assert_eq!(receiver, None);
+        assert_eq!(downloads.join(), 2);
+
+        let mut receiver = Some(1);
+        assert!(matches!(
+            FlashCatSender::receiver_rejected(&mut receiver, false),
+            SenderInteractionMessage::ReceiverReject
+        ));
+        Ok(())
+    }
+}
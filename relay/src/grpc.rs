@@ -1,22 +1,28 @@
-use std::{sync::Arc, time::Duration};
+use std::{collections::HashSet, sync::Arc, time::Duration};
 
+use bytes::Bytes;
 use log::{debug, error, info};
+use prost::Message;
 use tokio::sync::mpsc;
 use tokio_stream::{StreamExt, wrappers::ReceiverStream};
 use tonic::{Request, Response, Status, Streaming};
 
 use flash_cat_common::{
+    consts::{MAX_DATA_STREAMS, MAX_RECEIVERS},
     proto::{
-        Character, CloseRequest, CloseResponse, JoinFailed, JoinRequest, JoinResponse, JoinSuccess, Joined, Ready, RelayInfo, RelayUpdate, Terminated,
-        join_response::JoinResponseMessage, relay_service_server::RelayService, relay_update::RelayMessage,
+        BreakPoint, Character, CloseRequest, CloseResponse, Confirm, Done, FileConfirm, FileData, FileDone, JoinFailed, JoinRequest, JoinResponse, JoinSuccess,
+        Joined, NewFileConfirm, Ready, ReceiverUpdate, RelayInfo, RelayUpdate, SenderUpdate, Terminated, file_confirm::ConfirmMessage,
+        join_response::JoinResponseMessage, receiver_update::ReceiverMessage, relay_service_server::RelayService, relay_update::RelayMessage,
+        sender_update::SenderMessage,
     },
-    utils::net::get_local_ip,
+    utils::{net::get_local_ip, rate_limit::RateLimiter},
 };
 
 use crate::{
     built_info,
+    mailbox::Mailbox,
     relay::RelayState,
-    session::{Metadata, Session},
+    session::{Metadata, Session, SessionUserPair},
 };
 
 #[derive(Clone)]
@@ -52,15 +58,36 @@ impl RelayService for GrpcServer {
                     Err(_) => return Err(Status::invalid_argument("unknown character")),
                 };
                 let mut sender_local_relay = None;
+                let mut slot = 0;
+                let mut mailbox_salt = Bytes::new();
+                let mut mailbox_ttl = 0;
 
                 match character {
+                    Character::Sender if !request.mailbox_salt.is_empty() => {
+                        debug!("new sender({session_code}) storing a transfer");
+                        let Some(mailbox) = self.0.mailbox() else {
+                            return Ok(join_failed("the relay does not store transfers for later"));
+                        };
+                        if self.0.lookup(&session_code).is_some() {
+                            return Ok(join_failed("share code already has an active sender session"));
+                        }
+                        if let Err(e) = mailbox.begin(&id.encrypted_share_code, request.mailbox_salt) {
+                            return Ok(join_failed(&e.to_string()));
+                        }
+                        mailbox_ttl = mailbox.ttl().as_secs();
+                    }
                     Character::Sender => {
                         debug!("new sender({session_code}) incoming");
+                        if request.max_receivers > MAX_RECEIVERS {
+                            return Ok(join_failed(&format!("a share accepts at most {MAX_RECEIVERS} receivers")));
+                        }
                         let metadata = Metadata {
                             encrypted_share_code: id.encrypted_share_code,
                             sender_local_relay: request.sender_local_relay,
+                            max_receivers: request.max_receivers,
+                            keep_alive: request.keep_alive,
                         };
-                        let session = Arc::new(Session::new(metadata));
+                        let session = Arc::new(Session::new(metadata, self.0.session_limit_rate()));
                         if !self.0.insert_if_absent(&session_code, session.clone()) {
                             return Ok(Response::new(JoinResponse {
                                 join_response_message: Some(JoinResponseMessage::Failed(JoinFailed {
@@ -70,11 +97,32 @@ impl RelayService for GrpcServer {
                         }
                     }
                     Character::Receiver => match self.0.lookup(&session_code) {
-                        None => {
-                            return Err(Status::not_found("Not found, Please check share code."));
-                        }
+                        // no sender online, the transfer may be stored for later
+                        None => match self.0.mailbox().and_then(|mailbox| mailbox.salt(&id.encrypted_share_code)) {
+                            Some(salt) => {
+                                debug!("new receiver({session_code}) collecting a stored transfer");
+                                mailbox_salt = salt;
+                            }
+                            None => {
+                                return Err(Status::not_found("Not found, Please check share code."));
+                            }
+                        },
                         Some(session) => {
                             debug!("new receiver({session_code}) incoming");
+                            slot = match session.claim_slot() {
+                                Some(slot) => slot,
+                                // the next receiver of a kept alive share joins once the one before is done
+                                None if !session.is_broadcast() => {
+                                    return Ok(join_failed("the share is busy with another receiver, try again later"));
+                                }
+                                None => {
+                                    return Ok(Response::new(JoinResponse {
+                                        join_response_message: Some(JoinResponseMessage::Failed(JoinFailed {
+                                            error_msg: "share code already has the maximum number of receivers".to_string(),
+                                        })),
+                                    }));
+                                }
+                            };
                             sender_local_relay = session.metadata().sender_local_relay.clone();
                         }
                     },
@@ -101,6 +149,9 @@ impl RelayService for GrpcServer {
                         relay,
                         sender_local_relay,
                         client_latest_version,
+                        slot,
+                        mailbox_salt,
+                        mailbox_ttl,
                     })),
                 }))
             }
@@ -129,40 +180,81 @@ impl RelayService for GrpcServer {
 
         let (tx, rx) = mpsc::channel(256);
 
-        let (session, character) = match first_update.relay_message {
+        let (session, character, slot, stream_index) = match first_update.relay_message {
             Some(RelayMessage::Join(join)) => {
                 let session_code = String::from_utf8_lossy(join.encrypted_share_code.as_ref()).to_string();
                 let character = match Character::try_from(join.character) {
                     Ok(character) => character,
                     Err(_) => return Err(Status::invalid_argument("unknown character")),
                 };
+                if join.stream > MAX_DATA_STREAMS {
+                    return Err(Status::invalid_argument("too many data streams"));
+                }
                 let session = match self.0.lookup(&session_code) {
-                    None => return Err(Status::not_found("Not found, Please check share code.")),
                     Some(session) => session,
+                    None => {
+                        // no sender online, the transfer is stored or collected instead
+                        let Some(mailbox) = self.0.mailbox() else {
+                            return Err(Status::not_found("Not found, Please check share code."));
+                        };
+                        let share_code = join.encrypted_share_code;
+                        let limiter = self.0.limiter();
+                        match character {
+                            Character::Sender if join.stream == 0 && mailbox.is_pending(&share_code) => {
+                                send_msg(&tx, RelayMessage::Joined(Joined {})).await;
+                                info!("sender(addr: {remote_addr}) storing a transfer");
+                                tokio::spawn(store_transfer(tx, mailbox, share_code, stream, limiter));
+                            }
+                            Character::Receiver if join.stream == 0 && mailbox.salt(&share_code).is_some() => {
+                                send_msg(&tx, RelayMessage::Joined(Joined {})).await;
+                                info!("receiver(addr: {remote_addr}) collecting a stored transfer");
+                                tokio::spawn(collect_transfer(tx, mailbox, share_code, stream, limiter));
+                            }
+                            _ => return Err(Status::not_found("Not found, Please check share code.")),
+                        }
+                        return Ok(Response::new(ReceiverStream::new(rx)));
+                    }
                 };
+                if join.slot >= session.slots() {
+                    return Err(Status::invalid_argument("unknown slot"));
+                }
                 send_msg(&tx, RelayMessage::Joined(Joined {})).await;
-                (session, character)
+                (session, character, join.slot, join.stream)
             }
             _ => return Err(Status::invalid_argument("invalid first message")),
         };
 
-        if let Character::Receiver = character {
-            // readly to interaction
-            if let Err(e) = session
+        let user_pair = session.stream(slot, stream_index);
+        if stream_index > 0 {
+            debug!(
+                "data stream {stream_index}(addr: {remote_addr}, session_id: {}, slot: {slot}) started channel",
+                session.id()
+            );
+        } else if let Character::Receiver = character {
+            // readly to interaction, only the sender of the receiver's slot is told
+            if let Err(e) = user_pair
                 .broadcast(RelayMessage::Ready(Ready {
                     local_relay: self.0.is_local_relay(),
+                    parallel_streams: true,
                 }))
                 .await
             {
                 error!("broadcast failed: {e}");
             }
-            info!("receiver(addr: {remote_addr}, session_id: {}) started channel", session.id());
+            info!(
+                "receiver(addr: {remote_addr}, session_id: {}, slot: {slot}) started channel",
+                session.id()
+            );
         } else {
-            info!("sender(addr: {remote_addr}, session_id: {}) started channel", session.id());
+            info!(
+                "sender(addr: {remote_addr}, session_id: {}, slot: {slot}) started channel",
+                session.id()
+            );
         }
 
+        let limiter = self.0.limiter();
         tokio::spawn(async move {
-            if let Err(err) = handle_streaming(&tx, &session, stream, character).await {
+            if let Err(err) = handle_streaming(&tx, &session, &user_pair, stream, character, limiter.as_deref()).await {
                 error!(
                     "connection(addr: {remote_addr}, session_id: {}) exiting early due to an error {err}",
                     session.id()
@@ -180,6 +272,19 @@ impl RelayService for GrpcServer {
         let request = request.into_inner();
         let session_code = String::from_utf8_lossy(request.encrypted_share_code.as_ref()).to_string();
         if let Some(session) = self.0.lookup(&session_code) {
+            if session.outlives_receivers() && request.character == i32::from(Character::Receiver) {
+                // a receiver leaving a broadcast or kept alive session only ends its own slot,
+                // the next receiver to join takes it
+                if request.slot < session.slots() {
+                    let user_pair = session.stream(request.slot, 0);
+                    let (update_tx, _) = user_pair.updates(Character::Receiver);
+                    if let Err(e) = update_tx.send(RelayMessage::Terminated(Terminated {})).await {
+                        error!("send terminated failed: {e}");
+                    }
+                    session.release_slot(request.slot);
+                }
+                return Ok(Response::new(CloseResponse {}));
+            }
             if let Err(e) = session.broadcast(RelayMessage::Terminated(Terminated {})).await {
                 error!("broadcast failed: {e}");
             }
@@ -194,46 +299,315 @@ impl RelayService for GrpcServer {
 
 type RelayTx = mpsc::Sender<Result<RelayUpdate, Status>>;
 
+fn join_failed(error_msg: &str) -> Response<JoinResponse> {
+    Response::new(JoinResponse {
+        join_response_message: Some(JoinResponseMessage::Failed(JoinFailed {
+            error_msg: error_msg.to_string(),
+        })),
+    })
+}
+
+/// Store the transfer a sender sends for a receiver to collect later. The relay stands
+/// in for the receiver and accepts every file, the transfer is only kept once the sender
+/// is done.
+async fn store_transfer(
+    tx: RelayTx,
+    mailbox: Arc<Mailbox>,
+    share_code: Bytes,
+    mut stream: Streaming<RelayUpdate>,
+    limiter: Option<Arc<RateLimiter>>,
+) {
+    let mut writer = match mailbox.writer(&share_code).await {
+        Ok(writer) => writer,
+        Err(e) => {
+            send_err(&tx, e.to_string()).await;
+            mailbox.remove(&share_code);
+            return;
+        }
+    };
+    while let Some(Ok(update)) = stream.next().await {
+        match &update.relay_message {
+            Some(RelayMessage::Ping(_)) => {
+                send_msg(&tx, RelayMessage::Pong(0)).await;
+                continue;
+            }
+            Some(RelayMessage::Sender(_) | RelayMessage::Done(_)) => (),
+            _ => continue,
+        }
+        if let Some(limiter) = limiter.as_deref() {
+            limiter.acquire(update.encoded_len()).await;
+        }
+        if let Err(e) = writer.write(&update).await {
+            send_err(&tx, e.to_string()).await;
+            break;
+        }
+        match update.relay_message {
+            Some(RelayMessage::Sender(SenderUpdate {
+                sender_message: Some(SenderMessage::NewFileRequest(request)),
+            })) => {
+                send_msg(
+                    &tx,
+                    RelayMessage::Receiver(ReceiverUpdate {
+                        receiver_message: Some(ReceiverMessage::FileConfirm(FileConfirm {
+                            confirm_message: Some(ConfirmMessage::NewFileConfirm(NewFileConfirm {
+                                file_id: request.file_id,
+                                confirm: Confirm::Accept.into(),
+                            })),
+                        })),
+                    }),
+                )
+                .await;
+            }
+            Some(RelayMessage::Done(_)) => {
+                match writer.finish().await {
+                    Ok(()) => {
+                        info!("stored a transfer for {}s", mailbox.ttl().as_secs());
+                        send_msg(&tx, RelayMessage::Done(Done {})).await;
+                    }
+                    Err(e) => {
+                        send_err(&tx, e.to_string()).await;
+                        mailbox.remove(&share_code);
+                    }
+                }
+                return;
+            }
+            _ => (),
+        }
+    }
+    // the sender is gone before the transfer was stored whole
+    drop(writer);
+    mailbox.remove(&share_code);
+}
+
+/// Replay a stored transfer to a receiver, the relay stands in for the sender: the
+/// manifest waits for the receiver to accept the share, the files for the receiver to
+/// select them and the data of a file for the receiver to accept it. The transfer is
+/// removed once the receiver is done.
+async fn collect_transfer(
+    tx: RelayTx,
+    mailbox: Arc<Mailbox>,
+    share_code: Bytes,
+    mut stream: Streaming<RelayUpdate>,
+    limiter: Option<Arc<RateLimiter>>,
+) {
+    let mut reader = match mailbox.reader(&share_code).await {
+        Ok(reader) => reader,
+        Err(e) => {
+            send_err(&tx, e.to_string()).await;
+            return;
+        }
+    };
+    // the files are selected from the manifest, a text comes without one
+    let mut offered = false;
+    let mut selected = false;
+    // files rejected or cancelled by the receiver, their data is not replayed
+    let mut skipped = HashSet::new();
+    loop {
+        let update = match reader.next().await {
+            Ok(Some(update)) => update,
+            Ok(None) => return,
+            Err(e) => {
+                send_err(&tx, e.to_string()).await;
+                return;
+            }
+        };
+        let mut awaited_file = None;
+        match &update.relay_message {
+            Some(RelayMessage::Sender(SenderUpdate {
+                sender_message: Some(message),
+            })) => match message {
+                SenderMessage::Manifest(_) => {
+                    let accepted = loop {
+                        match next_receiver_message(&tx, &mut stream).await {
+                            Some(ReceiverMessage::ShareConfirm(confirm)) => break confirm == i32::from(Confirm::Accept),
+                            Some(ReceiverMessage::CancelFile(cancel)) => {
+                                skipped.insert(cancel.file_id);
+                            }
+                            Some(_) => (),
+                            None => break false,
+                        }
+                    };
+                    if !accepted {
+                        return;
+                    }
+                    offered = true;
+                }
+                SenderMessage::NewFileRequest(request) => {
+                    if !selected {
+                        if !wait_for_selection(&tx, &mut stream, &mut skipped).await {
+                            return;
+                        }
+                        selected = true;
+                    }
+                    awaited_file = Some(request.file_id);
+                }
+                SenderMessage::FileData(FileData {
+                    file_id,
+                    ..
+                })
+                | SenderMessage::FileDone(FileDone {
+                    file_id,
+                    ..
+                })
+                | SenderMessage::BreakPoint(BreakPoint {
+                    file_id,
+                    ..
+                }) if skipped.contains(file_id) => continue,
+                _ => (),
+            },
+            Some(RelayMessage::Done(_)) if offered && !selected && !wait_for_selection(&tx, &mut stream, &mut skipped).await => return,
+            _ => (),
+        }
+        if let Some(limiter) = limiter.as_deref() {
+            limiter.acquire(update.encoded_len()).await;
+        }
+        let done = matches!(update.relay_message, Some(RelayMessage::Done(_)));
+        if let Some(message) = update.relay_message {
+            if !send_msg(&tx, message).await {
+                return;
+            }
+        }
+        if let Some(file_id) = awaited_file {
+            loop {
+                match next_receiver_message(&tx, &mut stream).await {
+                    Some(ReceiverMessage::FileConfirm(FileConfirm {
+                        confirm_message: Some(ConfirmMessage::NewFileConfirm(confirm)),
+                    })) if confirm.file_id == file_id => {
+                        if confirm.confirm == i32::from(Confirm::Reject) {
+                            skipped.insert(file_id);
+                        }
+                        break;
+                    }
+                    // a partial file is written over from the start
+                    Some(ReceiverMessage::FileConfirm(FileConfirm {
+                        confirm_message: Some(ConfirmMessage::BreakPointConfirm(confirm)),
+                    })) if confirm.file_id == file_id => break,
+                    Some(ReceiverMessage::CancelFile(cancel)) => {
+                        skipped.insert(cancel.file_id);
+                    }
+                    Some(_) => (),
+                    None => return,
+                }
+            }
+        }
+        if done {
+            break;
+        }
+    }
+    // the transfer is kept until the receiver got all of it
+    while let Some(Ok(update)) = stream.next().await {
+        match update.relay_message {
+            Some(RelayMessage::Ping(_)) => {
+                send_msg(&tx, RelayMessage::Pong(0)).await;
+            }
+            Some(RelayMessage::Done(_)) => {
+                mailbox.remove(&share_code);
+                info!("a stored transfer was collected");
+                return;
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Wait for the receiver to select the files of the manifest, `false` once it hung up.
+async fn wait_for_selection(
+    tx: &RelayTx,
+    stream: &mut Streaming<RelayUpdate>,
+    skipped: &mut HashSet<u64>,
+) -> bool {
+    loop {
+        match next_receiver_message(tx, stream).await {
+            Some(ReceiverMessage::FileSelection(_)) => return true,
+            Some(ReceiverMessage::CancelFile(cancel)) => {
+                skipped.insert(cancel.file_id);
+            }
+            Some(_) => (),
+            None => return false,
+        }
+    }
+}
+
+/// Next message of a receiver collecting a stored transfer, its pings are answered
+/// meanwhile. `None` once it hung up.
+async fn next_receiver_message(
+    tx: &RelayTx,
+    stream: &mut Streaming<RelayUpdate>,
+) -> Option<ReceiverMessage> {
+    loop {
+        match stream.next().await? {
+            Ok(RelayUpdate {
+                relay_message: Some(RelayMessage::Receiver(ReceiverUpdate {
+                    receiver_message: Some(message),
+                })),
+            }) => return Some(message),
+            Ok(RelayUpdate {
+                relay_message: Some(RelayMessage::Ping(_)),
+            }) => {
+                send_msg(tx, RelayMessage::Pong(0)).await;
+            }
+            Ok(_) => (),
+            Err(_) => return None,
+        }
+    }
+}
+
 /// Handle bidirectional streaming messages RPC messages.
+///
+/// The sender and receiver payloads are held back by the session limit, then by the
+/// `limiter` shared by all sessions. Each direction is forwarded on its own, so the one
+/// held back doesn't stall the other.
 async fn handle_streaming(
     tx: &RelayTx,
     session: &Session,
+    user_pair: &SessionUserPair,
     mut stream: Streaming<RelayUpdate>,
     character: Character,
+    limiter: Option<&RateLimiter>,
 ) -> Result<(), &'static str> {
-    let (update_tx, update_rx) = match character {
-        Character::Sender => (session.recipient_update_tx(), session.sharer_update_rx()),
-        Character::Receiver => (session.sharer_update_tx(), session.recipient_update_rx()),
+    let (update_tx, update_rx) = user_pair.updates(character);
+    // Send buffered server updates to the client.
+    let forward_updates = async {
+        while let Ok(msg) = update_rx.recv().await {
+            if !send_msg(tx, msg).await {
+                return Err("failed to send update message");
+            }
+        }
+        std::future::pending().await
     };
-    loop {
-        tokio::select! {
-            // Send buffered server updates to the client.
-            Ok(msg) = update_rx.recv() => {
-                if !send_msg(tx, msg).await {
-                    return Err("failed to send update message");
+    // Handle incoming client messages.
+    let handle_updates = async {
+        while let Some(Ok(update)) = stream.next().await {
+            if matches!(update.relay_message, Some(RelayMessage::Sender(_) | RelayMessage::Receiver(_))) {
+                let bytes = update.encoded_len();
+                if let Some(session_limiter) = session.limiter() {
+                    session_limiter.acquire(bytes).await;
                 }
-            }
-            // Handle incoming client messages.
-            maybe_update = stream.next() => {
-                if let Some(Ok(update)) = maybe_update {
-                    if !handle_update(tx, session, update, update_tx).await {
-                        return Err("error responding to client update");
-                    }
-                } else {
-                    // The client has hung up on their end.
-                    return Ok(());
+                if let Some(limiter) = limiter {
+                    limiter.acquire(bytes).await;
                 }
             }
-            // Exit on a session shutdown signal.
-            _ = session.terminated() => {
-                send_err(tx, "disconnecting because session terminated".into()).await;
-                return Ok(());
+            if !handle_update(tx, session, update, update_tx).await {
+                return Err("error responding to client update");
             }
         }
+        // The client has hung up on their end.
+        Ok(())
+    };
+    tokio::select! {
+        result = forward_updates => result,
+        result = handle_updates => result,
+        // Exit on a session shutdown signal.
+        _ = session.terminated() => {
+            send_err(tx, "disconnecting because session terminated".into()).await;
+            Ok(())
+        }
     }
 }
 
 /// Handles a singe update from the client. Returns `true` on success.
+///
+/// Sender and receiver payloads are sealed with the session key, they are forwarded as opaque bytes.
 async fn handle_update(
     tx: &RelayTx,
     session: &Session,
@@ -1,5 +1,6 @@
 pub mod grpc;
 pub mod listen;
+pub mod mailbox;
 pub mod relay;
 pub mod session;
 
@@ -0,0 +1,435 @@
+//! Transfers stored on the relay for a receiver to collect later, so the sender and the
+//! receiver need not be online at the same time. The relay only ever sees the sealed
+//! messages, they are written as the sender sends them and replayed as they were.
+
+use std::{
+    fs,
+    io::{ErrorKind, Read},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Result, bail};
+use bytes::{Bytes, BytesMut};
+use dashmap::{DashMap, mapref::entry::Entry};
+use log::{debug, error};
+use prost::Message;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter};
+
+use flash_cat_common::proto::RelayUpdate;
+
+/// Extension of a stored transfer, and of one still being stored.
+const STORED_EXTENSION: &str = "box";
+const PARTIAL_EXTENSION: &str = "part";
+
+/// Where and for how long the transfers are stored.
+#[derive(Debug, Clone)]
+pub struct MailboxConfig {
+    pub dir: PathBuf,
+    /// Most bytes a single transfer may take.
+    pub max_size: u64,
+    /// Most bytes the transfers may take together, stored or being stored.
+    pub max_total_size: u64,
+    /// Most transfers kept at once, stored or being stored.
+    pub max_transfers: usize,
+    /// How long a transfer is kept for once stored.
+    pub ttl: Duration,
+}
+
+/// A stored transfer is the parameters its key is derived with followed by the messages of
+/// the sender, each prefixed with its length.
+#[derive(Debug)]
+pub struct Mailbox {
+    config: MailboxConfig,
+    /// Transfers by share code, including the ones the senders are still storing.
+    boxes: DashMap<String, StoredTransfer>,
+    /// Bytes taken by the transfers.
+    used: AtomicU64,
+}
+
+#[derive(Debug, Clone)]
+struct StoredTransfer {
+    salt: Bytes,
+    /// When the transfer was stored, or reserved while it is being stored.
+    since: SystemTime,
+    stored: bool,
+    /// Bytes written so far.
+    size: u64,
+}
+
+impl Mailbox {
+    /// Open the directory of the transfers, the ones stored before are kept until they expire.
+    pub fn open(config: MailboxConfig) -> Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let mailbox = Self {
+            config,
+            boxes: DashMap::new(),
+            used: AtomicU64::new(0),
+        };
+        for entry in fs::read_dir(&mailbox.config.dir)? {
+            let path = entry?.path();
+            let (Some(name), Some(extension)) = (path.file_stem(), path.extension()) else {
+                continue;
+            };
+            let name = name.to_string_lossy().to_string();
+            if extension == PARTIAL_EXTENSION {
+                // the sender went away with the relay
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            if extension != STORED_EXTENSION {
+                continue;
+            }
+            let metadata = fs::metadata(&path)?;
+            match read_salt(&path) {
+                Ok(salt) => {
+                    mailbox.used.fetch_add(metadata.len(), Ordering::SeqCst);
+                    mailbox.boxes.insert(
+                        name,
+                        StoredTransfer {
+                            salt,
+                            since: metadata.modified()?,
+                            stored: true,
+                            size: metadata.len(),
+                        },
+                    );
+                }
+                Err(e) => error!("skip stored transfer {}: {e}", path.display()),
+            }
+        }
+        Ok(mailbox)
+    }
+
+    /// How long a transfer is kept for once stored.
+    pub fn ttl(&self) -> Duration {
+        self.config.ttl
+    }
+
+    /// Reserve the share code for a transfer to store, fails if it has one already or the
+    /// relay keeps as many transfers as it stores.
+    pub fn begin(
+        &self,
+        share_code: &[u8],
+        salt: Bytes,
+    ) -> Result<()> {
+        if self.boxes.len() >= self.config.max_transfers {
+            bail!("the relay stores no more transfers for now");
+        }
+        match self.boxes.entry(box_name(share_code)) {
+            Entry::Occupied(_) => bail!("share code already has an active sender session"),
+            Entry::Vacant(entry) => {
+                entry.insert(StoredTransfer {
+                    salt,
+                    since: SystemTime::now(),
+                    stored: false,
+                    size: 0,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether a transfer is reserved for the share code and not stored yet.
+    pub fn is_pending(
+        &self,
+        share_code: &[u8],
+    ) -> bool {
+        self.boxes.get(&box_name(share_code)).is_some_and(|transfer| !transfer.stored)
+    }
+
+    /// Salt of the key of the transfer stored for the share code.
+    pub fn salt(
+        &self,
+        share_code: &[u8],
+    ) -> Option<Bytes> {
+        self.boxes.get(&box_name(share_code)).filter(|transfer| transfer.stored).map(|transfer| transfer.salt.clone())
+    }
+
+    /// Start storing the transfer reserved for the share code.
+    pub async fn writer(
+        self: &Arc<Self>,
+        share_code: &[u8],
+    ) -> Result<MailboxWriter> {
+        let name = box_name(share_code);
+        let Some(salt) = self.boxes.get(&name).map(|transfer| transfer.salt.clone()) else {
+            bail!("no transfer to store for the share code");
+        };
+        let path = self.path(&name, PARTIAL_EXTENSION);
+        let mut writer = MailboxWriter {
+            mailbox: self.clone(),
+            name,
+            file: BufWriter::new(tokio::fs::File::create(&path).await?),
+            size: 0,
+        };
+        writer.write_frame(&salt).await?;
+        Ok(writer)
+    }
+
+    /// Replay the transfer stored for the share code.
+    pub async fn reader(
+        &self,
+        share_code: &[u8],
+    ) -> Result<MailboxReader> {
+        let path = self.path(&box_name(share_code), STORED_EXTENSION);
+        let mut file = BufReader::new(tokio::fs::File::open(&path).await?);
+        // the salt was handed out on join
+        read_frame(&mut file).await?;
+        Ok(MailboxReader {
+            file,
+        })
+    }
+
+    /// Remove the transfer of the share code, stored or not.
+    pub fn remove(
+        &self,
+        share_code: &[u8],
+    ) {
+        self.remove_box(&box_name(share_code));
+    }
+
+    /// Remove the transfers stored for longer than the ttl, and the ones a sender took as
+    /// long to store.
+    pub fn remove_expired(&self) {
+        let expired: Vec<String> =
+            self.boxes.iter().filter(|entry| entry.since.elapsed().unwrap_or_default() > self.config.ttl).map(|entry| entry.key().clone()).collect();
+        for name in expired {
+            self.remove_box(&name);
+            debug!("removed expired transfer {name}");
+        }
+    }
+
+    fn remove_box(
+        &self,
+        name: &str,
+    ) {
+        if let Some((_, transfer)) = self.boxes.remove(name) {
+            self.used.fetch_sub(transfer.size, Ordering::SeqCst);
+        }
+        for extension in [PARTIAL_EXTENSION, STORED_EXTENSION] {
+            let path = self.path(name, extension);
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => error!("remove {} failed: {e}", path.display()),
+                _ => (),
+            }
+        }
+    }
+
+    fn path(
+        &self,
+        name: &str,
+        extension: &str,
+    ) -> PathBuf {
+        self.config.dir.join(format!("{name}.{extension}"))
+    }
+}
+
+/// Writes the messages of a transfer as they are sent, the transfer is only collected once
+/// finished.
+pub struct MailboxWriter {
+    mailbox: Arc<Mailbox>,
+    name: String,
+    file: BufWriter<tokio::fs::File>,
+    size: u64,
+}
+
+impl MailboxWriter {
+    pub async fn write(
+        &mut self,
+        update: &RelayUpdate,
+    ) -> Result<()> {
+        self.write_frame(&update.encode_to_vec()).await
+    }
+
+    /// Store the transfer for the receiver to collect.
+    pub async fn finish(mut self) -> Result<()> {
+        self.file.flush().await?;
+        self.file.get_ref().sync_all().await?;
+        tokio::fs::rename(
+            self.mailbox.path(&self.name, PARTIAL_EXTENSION),
+            self.mailbox.path(&self.name, STORED_EXTENSION),
+        )
+        .await?;
+        let Some(mut transfer) = self.mailbox.boxes.get_mut(&self.name) else {
+            bail!("the transfer expired before it was stored");
+        };
+        transfer.since = SystemTime::now();
+        transfer.stored = true;
+        Ok(())
+    }
+
+    async fn write_frame(
+        &mut self,
+        frame: &[u8],
+    ) -> Result<()> {
+        let len = 4 + frame.len() as u64;
+        let config = &self.mailbox.config;
+        if self.size + len > config.max_size {
+            bail!("the transfer is larger than the {} bytes the relay stores", config.max_size);
+        }
+        if self.mailbox.used.fetch_add(len, Ordering::SeqCst) + len > config.max_total_size {
+            self.mailbox.used.fetch_sub(len, Ordering::SeqCst);
+            bail!("the relay stores no more transfers for now");
+        }
+        // counted with the transfer, freed once it is removed
+        let Some(mut transfer) = self.mailbox.boxes.get_mut(&self.name) else {
+            self.mailbox.used.fetch_sub(len, Ordering::SeqCst);
+            bail!("the transfer expired before it was stored");
+        };
+        transfer.size += len;
+        drop(transfer);
+        self.size += len;
+        self.file.write_u32(frame.len() as u32).await?;
+        self.file.write_all(frame).await?;
+        Ok(())
+    }
+}
+
+pub struct MailboxReader {
+    file: BufReader<tokio::fs::File>,
+}
+
+impl MailboxReader {
+    /// Next message of the transfer, `None` once all are read.
+    pub async fn next(&mut self) -> Result<Option<RelayUpdate>> {
+        match read_frame(&mut self.file).await? {
+            Some(frame) => Ok(Some(RelayUpdate::decode(frame)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+async fn read_frame(file: &mut BufReader<tokio::fs::File>) -> Result<Option<Bytes>> {
+    let len = match file.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let mut frame = BytesMut::zeroed(len);
+    file.read_exact(&mut frame).await?;
+    Ok(Some(frame.freeze()))
+}
+
+fn read_salt(path: &Path) -> Result<Bytes> {
+    let mut file = fs::File::open(path)?;
+    let mut len = [0u8; 4];
+    file.read_exact(&mut len)?;
+    let mut salt = vec![0u8; u32::from_be_bytes(len) as usize];
+    file.read_exact(&mut salt)?;
+    Ok(Bytes::from(salt))
+}
+
+/// File name of the transfer of a share code, the code is already hashed.
+fn box_name(share_code: &[u8]) -> String {
+    share_code.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use anyhow::Result;
+    use bytes::Bytes;
+
+    use flash_cat_common::proto::{Done, RelayUpdate, relay_update::RelayMessage};
+
+    use super::{Mailbox, MailboxConfig};
+
+    fn config(dir: &std::path::Path) -> MailboxConfig {
+        MailboxConfig {
+            dir: dir.to_path_buf(),
+            max_size: 1024,
+            max_total_size: 4096,
+            max_transfers: 8,
+            ttl: Duration::from_secs(60),
+        }
+    }
+
+    fn update(message: RelayMessage) -> RelayUpdate {
+        RelayUpdate {
+            relay_message: Some(message),
+        }
+    }
+
+    #[tokio::test]
+    async fn stores_and_replays_transfer() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mailbox = Arc::new(Mailbox::open(config(dir.path()))?);
+        let salt = Bytes::from_static(b"salt");
+        mailbox.begin(b"code", salt.clone())?;
+        assert!(mailbox.is_pending(b"code"));
+        assert_eq!(mailbox.salt(b"code"), None);
+
+        let mut writer = mailbox.writer(b"code").await?;
+        writer.write(&update(RelayMessage::Ping(7))).await?;
+        writer.write(&update(RelayMessage::Done(Done {}))).await?;
+        writer.finish().await?;
+        assert!(!mailbox.is_pending(b"code"));
+        assert_eq!(mailbox.salt(b"code"), Some(salt.clone()));
+
+        let mut reader = mailbox.reader(b"code").await?;
+        assert_eq!(reader.next().await?, Some(update(RelayMessage::Ping(7))));
+        assert_eq!(reader.next().await?, Some(update(RelayMessage::Done(Done {}))));
+        assert_eq!(reader.next().await?, None);
+
+        // kept across a restart of the relay
+        let reopened = Mailbox::open(config(dir.path()))?;
+        assert_eq!(reopened.salt(b"code"), Some(salt));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn removes_expired_transfers() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mailbox = Arc::new(Mailbox::open(MailboxConfig {
+            ttl: Duration::ZERO,
+            ..config(dir.path())
+        })?);
+        mailbox.begin(b"stored", Bytes::from_static(b"salt"))?;
+        mailbox.writer(b"stored").await?.finish().await?;
+        mailbox.begin(b"pending", Bytes::from_static(b"salt"))?;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        mailbox.remove_expired();
+        assert_eq!(mailbox.salt(b"stored"), None);
+        assert!(!mailbox.is_pending(b"pending"));
+        assert_eq!(std::fs::read_dir(dir.path())?.count(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refuses_transfers_beyond_limits() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mailbox = Arc::new(Mailbox::open(MailboxConfig {
+            max_total_size: 1500,
+            max_transfers: 2,
+            ..config(dir.path())
+        })?);
+        let large = update(RelayMessage::Error("x".repeat(1024)));
+        let medium = update(RelayMessage::Error("x".repeat(900)));
+
+        mailbox.begin(b"first", Bytes::from_static(b"salt"))?;
+        assert!(mailbox.begin(b"first", Bytes::from_static(b"salt")).is_err());
+        let mut writer = mailbox.writer(b"first").await?;
+        assert!(writer.write(&large).await.is_err());
+        writer.write(&medium).await?;
+        writer.finish().await?;
+
+        mailbox.begin(b"second", Bytes::from_static(b"salt"))?;
+        assert!(mailbox.begin(b"third", Bytes::from_static(b"salt")).is_err());
+        // the relay stores less than both together
+        let mut writer = mailbox.writer(b"second").await?;
+        assert!(writer.write(&medium).await.is_err());
+        drop(writer);
+
+        // freed once removed
+        mailbox.remove(b"first");
+        mailbox.remove(b"second");
+        mailbox.begin(b"third", Bytes::from_static(b"salt"))?;
+        mailbox.writer(b"third").await?.write(&medium).await?;
+        Ok(())
+    }
+}
@@ -6,11 +6,15 @@ use std::{
 
 use anyhow::Result;
 use dashmap::{DashMap, mapref::entry::Entry};
-use flash_cat_common::Shutdown;
+use flash_cat_common::{Shutdown, utils::rate_limit::RateLimiter};
 use log::debug;
 use tokio::time;
 
-use crate::{listen, session::Session};
+use crate::{
+    listen,
+    mailbox::{Mailbox, MailboxConfig},
+    session::Session,
+};
 
 /// Session timeout.
 const DISCONNECTED_SESSION_EXPIRY: Duration = Duration::from_secs(300);
@@ -20,6 +24,12 @@ pub struct RelayState {
     external_ip: Option<IpAddr>,
     store: DashMap<String, Arc<Session>>,
     local_relay: bool,
+    /// Bytes per second each session may relay.
+    session_limit_rate: Option<u64>,
+    /// Limit of the bytes relayed by all sessions together.
+    limiter: Option<Arc<RateLimiter>>,
+    /// Transfers stored for the receivers to collect later.
+    mailbox: Option<Arc<Mailbox>>,
 }
 
 impl RelayState {
@@ -27,11 +37,17 @@ impl RelayState {
     pub fn new(
         external_ip: Option<IpAddr>,
         local_relay: bool,
+        session_limit_rate: Option<u64>,
+        limit_rate: Option<u64>,
+        mailbox: Option<MailboxConfig>,
     ) -> Result<Self> {
         Ok(Self {
             store: DashMap::new(),
             external_ip,
             local_relay,
+            session_limit_rate,
+            limiter: limit_rate.map(|rate| Arc::new(RateLimiter::new(rate))),
+            mailbox: mailbox.map(Mailbox::open).transpose()?.map(Arc::new),
         })
     }
 
@@ -64,6 +80,9 @@ impl RelayState {
                 self.close_session(&name);
                 debug!("closeed old session {name}");
             }
+            if let Some(mailbox) = &self.mailbox {
+                mailbox.remove_expired();
+            }
         }
     }
 
@@ -108,6 +127,21 @@ impl RelayState {
         self.local_relay
     }
 
+    /// Bytes per second each session may relay.
+    pub fn session_limit_rate(&self) -> Option<u64> {
+        self.session_limit_rate
+    }
+
+    /// Limit of the bytes relayed by all sessions together.
+    pub fn limiter(&self) -> Option<Arc<RateLimiter>> {
+        self.limiter.clone()
+    }
+
+    /// Transfers stored for the receivers to collect later, unset when the relay keeps none.
+    pub fn mailbox(&self) -> Option<Arc<Mailbox>> {
+        self.mailbox.clone()
+    }
+
     /// Shutdown all sessions.
     pub fn shutdown(&self) {
         for entry in &self.store {
@@ -128,9 +162,18 @@ impl Relay {
     pub fn new(
         external_ip: Option<IpAddr>,
         local_relay: bool,
+        session_limit_rate: Option<u64>,
+        limit_rate: Option<u64>,
+        mailbox: Option<MailboxConfig>,
     ) -> Result<Self> {
         Ok(Self {
-            state: Arc::new(RelayState::new(external_ip, local_relay)?),
+            state: Arc::new(RelayState::new(
+                external_ip,
+                local_relay,
+                session_limit_rate,
+                limit_rate,
+                mailbox,
+            )?),
             shutdown: Shutdown::new(),
         })
     }
@@ -139,10 +182,19 @@ impl Relay {
     pub fn new_with_shutdown(
         external_ip: Option<IpAddr>,
         local_relay: bool,
+        session_limit_rate: Option<u64>,
+        limit_rate: Option<u64>,
+        mailbox: Option<MailboxConfig>,
         shutdown: Shutdown,
     ) -> Result<Self> {
         Ok(Self {
-            state: Arc::new(RelayState::new(external_ip, local_relay)?),
+            state: Arc::new(RelayState::new(
+                external_ip,
+                local_relay,
+                session_limit_rate,
+                limit_rate,
+                mailbox,
+            )?),
             shutdown,
         })
     }
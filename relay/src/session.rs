@@ -1,4 +1,7 @@
-use std::time::Instant;
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
 
 use anyhow::Result;
 use bytes::Bytes;
@@ -6,7 +9,8 @@ use parking_lot::Mutex;
 
 use flash_cat_common::{
     Shutdown,
-    proto::{RelayInfo, relay_update::RelayMessage},
+    proto::{Character, RelayInfo, relay_update::RelayMessage},
+    utils::rate_limit::RateLimiter,
 };
 
 #[derive(Debug, Clone)]
@@ -15,6 +19,10 @@ pub struct Metadata {
     pub encrypted_share_code: Bytes,
     /// Local relay info for sender.
     pub sender_local_relay: Option<RelayInfo>,
+    /// Receivers the sender accepts, each gets a slot of its own when more than one.
+    pub max_receivers: u32,
+    /// The session outlives its receivers, the sender serves them one after another.
+    pub keep_alive: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -36,6 +44,32 @@ impl SessionUserPair {
             recipient_update_rx,
         }
     }
+
+    /// Channel the client of `character` sends its updates to, and the one it receives from.
+    pub fn updates(
+        &self,
+        character: Character,
+    ) -> (&async_channel::Sender<RelayMessage>, &async_channel::Receiver<RelayMessage>) {
+        match character {
+            Character::Sender => (&self.recipient_update_tx, &self.sharer_update_rx),
+            Character::Receiver => (&self.sharer_update_tx, &self.recipient_update_rx),
+        }
+    }
+
+    /// Send a message to both ends.
+    pub async fn broadcast(
+        &self,
+        msg: RelayMessage,
+    ) -> Result<()> {
+        self.sharer_update_tx.send(msg.clone()).await?;
+        self.recipient_update_tx.send(msg).await?;
+        Ok(())
+    }
+
+    /// Drop the updates queued for the recipient.
+    fn drain_recipient(&self) {
+        while self.recipient_update_rx.try_recv().is_ok() {}
+    }
 }
 
 #[derive(Debug)]
@@ -46,6 +80,13 @@ pub struct Session {
     metadata: Metadata,
     /// User pair for this session.
     user_pair: SessionUserPair,
+    /// User pairs of the other slots and of the parallel data streams, by slot and stream index.
+    streams: Mutex<HashMap<(u32, u32), SessionUserPair>>,
+    /// Slots handed out to the receivers of a broadcast or kept alive session, kept for them to
+    /// reconnect to until they close.
+    claimed_slots: Mutex<HashSet<u32>>,
+    /// Limit of the bytes relayed by this session.
+    limiter: Option<RateLimiter>,
     /// Timestamp of the last backend client message from an active connection.
     last_accessed: Mutex<Instant>,
     /// Set when this session has been closed and removed.
@@ -56,13 +97,19 @@ pub struct Session {
 }
 
 impl Session {
-    pub fn new(metadata: Metadata) -> Self {
+    pub fn new(
+        metadata: Metadata,
+        limit_rate: Option<u64>,
+    ) -> Self {
         let id = nanoid::nanoid!(10);
         Session {
             id,
             metadata,
+            limiter: limit_rate.map(RateLimiter::new),
             last_accessed: Mutex::new(Instant::now()),
             user_pair: SessionUserPair::new(),
+            streams: Mutex::new(HashMap::new()),
+            claimed_slots: Mutex::new(HashSet::new()),
             shutdown: Shutdown::new(),
         }
     }
@@ -75,6 +122,66 @@ impl Session {
         &self.metadata
     }
 
+    /// Whether several receivers may join, each on a slot of its own.
+    pub fn is_broadcast(&self) -> bool {
+        self.metadata.max_receivers > 1
+    }
+
+    /// Whether a receiver leaving only ends its own slot, instead of the whole session.
+    pub fn outlives_receivers(&self) -> bool {
+        self.is_broadcast() || self.metadata.keep_alive
+    }
+
+    /// Number of slots of the session, one per receiver.
+    pub fn slots(&self) -> u32 {
+        self.metadata.max_receivers.max(1)
+    }
+
+    /// Slot for a receiver joining, `None` once every slot of a broadcast session is taken, or
+    /// while a kept alive session serves a receiver. The single receiver of a session always
+    /// gets slot 0.
+    pub fn claim_slot(&self) -> Option<u32> {
+        if !self.outlives_receivers() {
+            return Some(0);
+        }
+        let mut claimed = self.claimed_slots.lock();
+        let slot = (0..self.slots()).find(|slot| !claimed.contains(slot))?;
+        claimed.insert(slot);
+        drop(claimed);
+        // sent on by the sender until it saw the receiver before leave
+        self.drain_slot(slot);
+        Some(slot)
+    }
+
+    /// Free the slot of a receiver gone for the next one to join.
+    pub fn release_slot(
+        &self,
+        slot: u32,
+    ) {
+        self.claimed_slots.lock().remove(&slot);
+        self.drain_slot(slot);
+    }
+
+    /// Drop what is queued on the streams of a slot for the receiver gone, it is sealed under
+    /// the key of that receiver and a `Done` would end the transfer of the next one.
+    fn drain_slot(
+        &self,
+        slot: u32,
+    ) {
+        if slot == 0 {
+            self.user_pair.drain_recipient();
+        }
+        for ((stream_slot, _), user_pair) in self.streams.lock().iter() {
+            if *stream_slot == slot {
+                user_pair.drain_recipient();
+            }
+        }
+    }
+
+    pub fn limiter(&self) -> Option<&RateLimiter> {
+        self.limiter.as_ref()
+    }
+
     pub fn access(&self) {
         *self.last_accessed.lock() = Instant::now();
     }
@@ -107,15 +214,30 @@ impl Session {
         Ok(self.user_pair.recipient_update_rx.recv().await?)
     }
 
+    /// Send a message to both ends of every slot.
     pub async fn broadcast(
         &self,
         msg: RelayMessage,
     ) -> Result<()> {
-        self.user_pair.sharer_update_tx.send(msg.clone()).await?;
-        self.user_pair.recipient_update_tx.send(msg).await?;
+        for slot in 0..self.slots() {
+            self.stream(slot, 0).broadcast(msg.clone()).await?;
+        }
         Ok(())
     }
 
+    /// User pair of a stream of a slot, 0 is the control stream and the others carry the
+    /// data striped across them.
+    pub fn stream(
+        &self,
+        slot: u32,
+        index: u32,
+    ) -> SessionUserPair {
+        if slot == 0 && index == 0 {
+            return self.user_pair.clone();
+        }
+        self.streams.lock().entry((slot, index)).or_insert_with(SessionUserPair::new).clone()
+    }
+
     pub fn sharer_update_tx(&self) -> &async_channel::Sender<RelayMessage> {
         &self.user_pair.sharer_update_tx
     }
@@ -140,3 +262,41 @@ impl Session {
         self.shutdown.wait().await
     }
 }
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use flash_cat_common::proto::{Character, Done, relay_update::RelayMessage};
+
+    use super::{Metadata, Session};
+
+    #[tokio::test]
+    async fn slot_released_is_handed_over_empty() {
+        let session = Session::new(
+            Metadata {
+                encrypted_share_code: Bytes::new(),
+                sender_local_relay: None,
+                max_receivers: 1,
+                keep_alive: true,
+            },
+            None,
+        );
+        assert_eq!(session.claim_slot(), Some(0));
+        assert_eq!(session.claim_slot(), None);
+        // queued by the sender for the receiver leaving, on the control and a data stream
+        for index in 0..2 {
+            let user_pair = session.stream(0, index);
+            let (update_tx, _) = user_pair.updates(Character::Sender);
+            update_tx.send(RelayMessage::Done(Done {})).await.unwrap();
+        }
+        session.release_slot(0);
+
+        assert_eq!(session.claim_slot(), Some(0));
+        for index in 0..2 {
+            let user_pair = session.stream(0, index);
+            let (_, update_rx) = user_pair.updates(Character::Receiver);
+            assert!(update_rx.is_empty());
+        }
+    }
+}